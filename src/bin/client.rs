@@ -0,0 +1,380 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use simple_tftp::{
+    client::Client,
+    error::TransferError,
+    netascii::{ModeReader, ModeWriter},
+    packet::{self, Packet},
+    transport::{ReadSource, Transport, WriteSink},
+};
+use std::{
+    cell::Cell,
+    fs::File,
+    io::{self, IsTerminal, Read, Write},
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    num::{NonZeroU16, NonZeroU8},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+/// Transfers files from and to a TFTP server.
+#[derive(Debug, Parser)]
+#[command(name = "simple-tftp", version)]
+struct Args {
+    /// the host name or ip address of the server.
+    server: String,
+    #[command(subcommand)]
+    command: Command,
+    /// the port the server listens on.
+    #[arg(short, long, default_value_t = 69, global = true)]
+    port: u16,
+    /// the blocksize to request, the server may pick a smaller one.
+    #[arg(short, long, global = true, value_parser = clap::value_parser!(u16).range(8..=65464))]
+    blksize: Option<u16>,
+    /// ask the server for the size of the file, to show how far along a download is.
+    /// Octet mode uploads tell the server the size of the file instead, so it can refuse files that are too large right away.
+    #[arg(long, global = true)]
+    tsize: bool,
+    /// the retransmission timeout in seconds to request.
+    #[arg(short, long, global = true)]
+    timeout: Option<NonZeroU8>,
+    /// the amount of blocks to request to be sent before each acknowledgement.
+    #[arg(short, long, global = true)]
+    windowsize: Option<NonZeroU16>,
+    /// the transfer mode. netascii translates line endings.
+    #[arg(short, long, value_enum, default_value_t = Mode::Octet, global = true)]
+    mode: Mode,
+    /// print every packet sent and received.
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// don't show the progress of the transfer.
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// how many times the same packet is sent again before giving up.
+    #[arg(long, global = true)]
+    retries: Option<u32>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// downloads a file from the server.
+    Get {
+        /// the name of the file on the server.
+        remote: String,
+        /// where to store the file, `-` writes it to stdout. Defaults to the last part of the remote name.
+        local: Option<PathBuf>,
+    },
+    /// uploads a file to the server.
+    Put {
+        /// the file to upload, `-` reads it from stdin.
+        local: PathBuf,
+        /// the name of the file on the server. Defaults to the name of the local file.
+        remote: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Mode {
+    Octet,
+    Netascii,
+}
+
+impl From<Mode> for packet::Mode {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Octet => packet::Mode::Octet,
+            Mode::Netascii => packet::Mode::NetAscii,
+        }
+    }
+}
+
+/// a transport printing every packet that passes through it, and noting the transfer size the server acknowledges.
+struct Tracing<'p, T> {
+    inner: T,
+    verbose: bool,
+    progress: &'p Progress,
+}
+
+impl<T: Transport> Transport for Tracing<'_, T> {
+    type Error = T::Error;
+
+    fn send_to(&mut self, data: &[u8], addr: SocketAddr) -> Result<(), Self::Error> {
+        if self.verbose {
+            eprintln!("-> {addr} {}", describe(data));
+        }
+        self.inner.send_to(data, addr)
+    }
+
+    fn recv_from(
+        &mut self,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> Result<Option<(usize, SocketAddr)>, Self::Error> {
+        let received = self.inner.recv_from(buffer, timeout)?;
+        if let Some((n_bytes, from)) = received {
+            if let Ok(Packet::OptionAck(options)) = Packet::from_bytes(&buffer[..n_bytes]) {
+                if let Some(size) = options.transfer_size {
+                    self.progress.total.set(Some(size));
+                }
+            }
+            if self.verbose {
+                eprintln!("<- {from} {}", describe(&buffer[..n_bytes]));
+            }
+        }
+        Ok(received)
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr, Self::Error> {
+        self.inner.local_addr()
+    }
+
+    fn now(&self) -> Duration {
+        self.inner.now()
+    }
+}
+
+// a single line describing the packet in `data`, leaving out the payload of data packets.
+fn describe(data: &[u8]) -> String {
+    match Packet::from_bytes(data) {
+        Ok(Packet::Request(request)) => {
+            let kind = if request.is_read() { "RRQ" } else { "WRQ" };
+            let mut line = format!("{kind} {:?} {}", request.filename, request.mode.as_str());
+            if let Some(blocksize) = request.blocksize {
+                line += &format!(" blksize={blocksize}");
+            }
+            if let Some(size) = request.transfer_size {
+                line += &format!(" tsize={size}");
+            }
+            if let Some(timeout) = request.timeout_seconds {
+                line += &format!(" timeout={timeout}");
+            }
+            if let Some(window_size) = request.window_size {
+                line += &format!(" windowsize={window_size}");
+            }
+            line
+        }
+        Ok(Packet::Data(data)) => {
+            format!("DATA block {} ({} bytes)", data.block_nr, data.data.len())
+        }
+        Ok(Packet::Ack(ack)) => format!("ACK block {}", ack.block_nr),
+        Ok(Packet::Error(error)) => format!("ERROR {}: {:?}", error.error_code, error.message),
+        Ok(Packet::OptionAck(options)) => {
+            let mut line = String::from("OACK");
+            if let Some(blocksize) = options.blocksize {
+                line += &format!(" blksize={blocksize}");
+            }
+            if let Some(size) = options.transfer_size {
+                line += &format!(" tsize={size}");
+            }
+            if let Some(timeout) = options.timeout_seconds {
+                line += &format!(" timeout={timeout}");
+            }
+            if let Some(window_size) = options.window_size {
+                line += &format!(" windowsize={window_size}");
+            }
+            line
+        }
+        Err(e) => format!("invalid packet of {} bytes: {e}", data.len()),
+    }
+}
+
+/// how far along the transfer is, shown on stderr while it runs.
+struct Progress {
+    enabled: bool,
+    done: Cell<u64>,
+    total: Cell<Option<u64>>,
+    shown: Cell<Option<Instant>>,
+}
+
+impl Progress {
+    fn new(enabled: bool, total: Option<u64>) -> Self {
+        Self {
+            enabled,
+            done: Cell::new(0),
+            total: Cell::new(total),
+            shown: Cell::new(None),
+        }
+    }
+
+    fn advance(&self, n_bytes: usize) {
+        self.done.set(self.done.get() + n_bytes as u64);
+        let due = self
+            .shown
+            .get()
+            .is_none_or(|shown| shown.elapsed() >= Duration::from_millis(100));
+        if due {
+            self.show();
+        }
+    }
+
+    fn show(&self) {
+        if !self.enabled {
+            return;
+        }
+        let done = self.done.get();
+        match self.total.get() {
+            Some(total) if total > 0 => {
+                eprint!(
+                    "\r{done} / {total} bytes ({}%)",
+                    done.min(total) * 100 / total
+                )
+            }
+            _ => eprint!("\r{done} bytes"),
+        }
+        self.shown.set(Some(Instant::now()));
+    }
+
+    fn finish(&self) {
+        if self.enabled && self.shown.get().is_some() {
+            self.show();
+            eprintln!();
+        }
+    }
+}
+
+/// counts the bytes passing through a reader or writer.
+struct Counting<'p, T> {
+    inner: T,
+    progress: &'p Progress,
+}
+
+impl<R: Read> Read for Counting<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n_bytes = self.inner.read(buf)?;
+        self.progress.advance(n_bytes);
+        Ok(n_bytes)
+    }
+}
+
+impl<W: Write> Write for Counting<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n_bytes = self.inner.write(buf)?;
+        self.progress.advance(n_bytes);
+        Ok(n_bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("simple-tftp: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let server = (args.server.as_str(), args.port)
+        .to_socket_addrs()
+        .map_err(|e| format!("can't resolve {}: {e}", args.server))?
+        .next()
+        .ok_or_else(|| format!("{} has no address", args.server))?;
+    let mut client = Client::new(server);
+    client.set_mode(args.mode.into());
+    client.set_blocksize(args.blksize);
+    client.set_timeout(args.timeout);
+    client.set_window_size(args.windowsize);
+    client.set_request_transfer_size(args.tsize);
+    if let Some(retries) = args.retries {
+        let mut retransmit = client.retransmit_policy();
+        retransmit.max_retries = retries;
+        client.set_retransmit_policy(retransmit);
+    }
+
+    let mode = args.mode.into();
+    let socket = UdpSocket::bind(match server {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    })?;
+    let mut buffer = vec![0u8; 4 + args.blksize.unwrap_or(512).max(512) as usize];
+    // the progress would get mixed up with the packets.
+    let show_progress = !args.quiet && !args.verbose && io::stderr().is_terminal();
+
+    match &args.command {
+        Command::Get { remote, local } => {
+            let local = match local {
+                Some(local) => local.clone(),
+                None => Path::new(remote)
+                    .file_name()
+                    .map(PathBuf::from)
+                    .ok_or_else(|| format!("can't pick a local name for {remote:?}"))?,
+            };
+            let sink: Box<dyn Write> = if local == Path::new("-") {
+                Box::new(io::stdout().lock())
+            } else {
+                Box::new(
+                    File::create_new(&local).map_err(|e| format!("can't create {local:?}: {e}"))?,
+                )
+            };
+            let progress = Progress::new(show_progress, None);
+            let mut transport = Tracing {
+                inner: socket,
+                verbose: args.verbose,
+                progress: &progress,
+            };
+            let mut sink = WriteSink::new(Counting {
+                inner: ModeWriter::new(sink, mode),
+                progress: &progress,
+            });
+            let received = client.get_with(&mut transport, remote, &mut sink, &mut buffer);
+            progress.finish();
+            // a netascii transfer may end in a `\r` that is only written out now.
+            let received = match received {
+                Ok(_) => sink
+                    .into_inner()
+                    .inner
+                    .finish()
+                    .map_err(TransferError::from),
+                Err(e) => Err(TransferError::from(e)),
+            };
+            if let Err(e) = received {
+                // don't leave a partial file behind.
+                if local != Path::new("-") {
+                    let _ = std::fs::remove_file(&local);
+                }
+                return Err(e.into());
+            }
+        }
+        Command::Put { local, remote } => {
+            let remote = match remote {
+                Some(remote) => remote.clone(),
+                None if local != Path::new("-") => local
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .map(String::from)
+                    .ok_or_else(|| format!("can't pick a remote name for {local:?}"))?,
+                None => return Err("uploading from stdin needs a remote name".into()),
+            };
+            let (source, size): (Box<dyn Read>, _) = if local == Path::new("-") {
+                (Box::new(io::stdin().lock()), None)
+            } else {
+                let file = File::open(local).map_err(|e| format!("can't open {local:?}: {e}"))?;
+                let size = file.metadata()?.len();
+                (Box::new(file), Some(size))
+            };
+            // netascii changes the size of the data while sending it.
+            let size = size.filter(|_| matches!(args.mode, Mode::Octet));
+            client.set_write_transfer_size(size.filter(|_| args.tsize));
+            let progress = Progress::new(show_progress, size);
+            let mut transport = Tracing {
+                inner: socket,
+                verbose: args.verbose,
+                progress: &progress,
+            };
+            let source = ReadSource::new(Counting {
+                inner: ModeReader::new(source, mode),
+                progress: &progress,
+            });
+            let sent = client.put_with(&mut transport, &remote, source, &mut buffer);
+            progress.finish();
+            sent.map_err(TransferError::from)?;
+        }
+    }
+    Ok(())
+}
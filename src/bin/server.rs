@@ -0,0 +1,225 @@
+use clap::{builder::RangedU64ValueParser, ArgAction, Parser};
+use simple_tftp::{
+    error::TransferError,
+    options::OptionPolicy,
+    packet::{Error, OptionAck, Request},
+    server::{
+        config::Config,
+        fs::RootDirectory,
+        remap::{Remap, Remapped},
+        Handler, Limits, Server,
+    },
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+/// Serves the files in a directory over TFTP.
+#[derive(Debug, Parser)]
+#[command(name = "simple-tftp-server", version)]
+struct Args {
+    /// serve the roots described by this TOML file instead, with the listen address, limits and option limits configured in it.
+    #[arg(short, long, conflicts_with_all = [
+        "root", "address", "port", "writable", "max_blocksize", "min_timeout", "max_timeout", "max_transfers", "max_transfers_per_client"
+    ])]
+    config: Option<PathBuf>,
+    /// rewrite or refuse requested filenames using the rules in this file, in the format of tftp-hpa's map files.
+    #[arg(short = 'm', long)]
+    map_file: Option<PathBuf>,
+    /// the directory to serve.
+    #[arg(default_value = ".")]
+    root: PathBuf,
+    /// the ip address to listen on. Listens on all addresses by default.
+    #[arg(short, long, default_value_t = IpAddr::V4(Ipv4Addr::UNSPECIFIED))]
+    address: IpAddr,
+    /// the port to listen on.
+    #[arg(short, long, default_value_t = 69)]
+    port: u16,
+    /// accept write requests for files that don't exist yet. Existing files are never overwritten.
+    #[arg(short, long)]
+    writable: bool,
+    /// the largest blocksize acknowledged, larger requests are answered with this one.
+    #[arg(long, default_value_t = 65464, value_parser = clap::value_parser!(u16).range(8..=65464))]
+    max_blocksize: u16,
+    /// the shortest timeout in seconds a client may ask for.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..))]
+    min_timeout: u8,
+    /// the longest timeout in seconds a client may ask for.
+    #[arg(long, default_value_t = 255, value_parser = clap::value_parser!(u8).range(1..))]
+    max_timeout: u8,
+    /// the maximum amount of transfers running at once.
+    #[arg(long, default_value_t = Limits::default().max_transfers, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    max_transfers: usize,
+    /// the maximum amount of transfers running at once with a single client.
+    #[arg(long, default_value_t = Limits::default().max_transfers_per_client, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    max_transfers_per_client: usize,
+    /// log more, repeat for even more. By default only failed transfers are logged,
+    /// `-v` logs every transfer and `-vv` every request.
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+    /// don't log anything but the error stopping the server.
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+/// a handler logging what the handler it wraps does, depending on the verbosity.
+struct Logging<H> {
+    handler: H,
+    verbosity: i8,
+}
+
+impl<H: Handler> Logging<H> {
+    // logs the outcome of opening the file for `request`.
+    fn log_open<T>(&self, request: &Request, client: SocketAddr, opened: &Result<T, Error>) {
+        let direction = if request.is_read() { "read" } else { "write" };
+        match opened {
+            Ok(_) if self.verbosity >= 2 => {
+                eprintln!(
+                    "{client}: {direction} {:?} ({:?})",
+                    request.filename, request.mode
+                )
+            }
+            Err(e) if self.verbosity >= 1 => eprintln!(
+                "{client}: refused {direction} {:?}: {}",
+                request.filename, e.message
+            ),
+            _ => {}
+        }
+    }
+}
+
+impl<H: Handler> Handler for Logging<H> {
+    type Reader = H::Reader;
+    type Writer = H::Writer;
+
+    fn open_read(
+        &self,
+        request: &Request,
+        client: SocketAddr,
+    ) -> Result<(Self::Reader, Option<u64>), Error<'static>> {
+        let opened = self.handler.open_read(request, client);
+        self.log_open(request, client, &opened);
+        opened
+    }
+
+    fn open_write(
+        &self,
+        request: &Request,
+        client: SocketAddr,
+    ) -> Result<Self::Writer, Error<'static>> {
+        let opened = self.handler.open_write(request, client);
+        self.log_open(request, client, &opened);
+        opened
+    }
+
+    fn transfer_finished(
+        &self,
+        filename: &str,
+        client: SocketAddr,
+        result: &Result<(), TransferError>,
+    ) {
+        match result {
+            Ok(()) if self.verbosity >= 1 => eprintln!("{client}: transferred {filename:?}"),
+            Err(e) if self.verbosity >= 0 => eprintln!("{client}: failed {filename:?}: {e}"),
+            _ => {}
+        }
+        self.handler.transfer_finished(filename, client, result)
+    }
+
+    fn negotiate_options(
+        &self,
+        request: &Request,
+        client: SocketAddr,
+        options: &mut OptionAck<'static>,
+    ) {
+        self.handler.negotiate_options(request, client, options)
+    }
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let verbosity = if args.quiet { -1 } else { args.verbose as i8 };
+    let served = match &args.config {
+        Some(config) => serve_config(config, &args, verbosity),
+        None => serve(&args, verbosity),
+    };
+    match served {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("simple-tftp-server: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn serve_config(
+    config: &Path,
+    args: &Args,
+    verbosity: i8,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load(config)?;
+    let handler = config.handler()?;
+    let server = config
+        .server()
+        .map_err(|e| format!("can't listen on {}: {e}", config.listen))?;
+    if verbosity >= 1 {
+        for root in &config.roots {
+            eprintln!(
+                "serving {:?} as {:?} on {}",
+                root.path, root.prefix, config.listen
+            );
+        }
+    }
+    run(server, handler, args, verbosity)
+}
+
+fn serve(args: &Args, verbosity: i8) -> Result<(), Box<dyn std::error::Error>> {
+    if args.min_timeout > args.max_timeout {
+        return Err("--min-timeout can't be larger than --max-timeout".into());
+    }
+    let mut handler =
+        RootDirectory::new(&args.root).map_err(|e| format!("can't serve {:?}: {e}", args.root))?;
+    handler.set_writable(args.writable);
+
+    let mut server = Server::connect_with_port(args.address, args.port).map_err(|e| {
+        format!(
+            "can't listen on port {} of {}: {e}",
+            args.port, args.address
+        )
+    })?;
+    server.set_option_policy(OptionPolicy {
+        max_blocksize: args.max_blocksize,
+        min_timeout: args.min_timeout,
+        max_timeout: args.max_timeout,
+        ..Default::default()
+    });
+    server.set_limits(Limits {
+        max_transfers: args.max_transfers,
+        max_transfers_per_client: args.max_transfers_per_client,
+    });
+    if verbosity >= 1 {
+        eprintln!(
+            "serving {:?} on {}",
+            handler.root(),
+            SocketAddr::new(args.address, args.port)
+        );
+    }
+    run(server, handler, args, verbosity)
+}
+
+fn run<H: Handler + Sync>(
+    mut server: Server,
+    handler: H,
+    args: &Args,
+    verbosity: i8,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let remap = match &args.map_file {
+        Some(map_file) => Remap::load(map_file)?,
+        None => Remap::new(),
+    };
+    let handler = Remapped::new(handler, remap);
+    server.serve(&Logging { handler, verbosity })?;
+    Ok(())
+}
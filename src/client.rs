@@ -0,0 +1,592 @@
+#[cfg(feature = "std")]
+use crate::{
+    error::TransferError,
+    netascii::{NetAsciiReader, NetAsciiWriter},
+    transport::{ReadSource, WriteSink},
+};
+use crate::{
+    machine::{Receiver, Sender},
+    packet::{Ack, Data, ErrorCode, Mode, OptionAck, Packet, Request, Rollover},
+    transport::{self, DataSink, DataSource, Failure, RetransmitPolicy, Settings, Transport},
+};
+use core::{
+    net::SocketAddr,
+    num::{NonZeroU16, NonZeroU8},
+};
+#[cfg(feature = "std")]
+use std::{
+    io::{Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket},
+};
+
+/// A TFTP client implementation.
+///
+/// Every transfer uses its own socket, so a single client can be used for any amount of transfers.
+/// Without the `std` feature, transfers run over any [`Transport`] using [`get_with`](Client::get_with) and [`put_with`](Client::put_with) instead.
+/// The options set on the client are requested from the server for every transfer. If the server ignores them,
+/// the transfer falls back to the defaults of [RFC-1350](https://www.rfc-editor.org/rfc/inline-errata/rfc1350.html).
+pub struct Client {
+    server: SocketAddr,
+    options: ClientOptions,
+}
+
+/// The settings of a client, shared with the async client.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ClientOptions {
+    pub mode: Mode,
+    pub blocksize: Option<u16>,
+    pub timeout_seconds: Option<NonZeroU8>,
+    pub window_size: Option<NonZeroU16>,
+    pub rollover: Option<Rollover>,
+    pub request_transfer_size: bool,
+    pub write_transfer_size: Option<u64>,
+    pub retransmit: RetransmitPolicy,
+}
+
+impl Client {
+    /// creates a new client for the server at `server`. Note that the default port for TFTP is 69.
+    pub fn new(server: SocketAddr) -> Self {
+        Self {
+            server,
+            options: ClientOptions::default(),
+        }
+    }
+
+    /// sets the transfer mode used for every transfer. Defaults to [`Mode::Octet`].
+    ///
+    /// In [`Mode::NetAscii`] the data is translated from and to local unix line endings using the adapters in [`netascii`](crate::netascii).
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.options.mode = mode;
+    }
+
+    /// sets the blocksize to request using the blocksize option defined in [RFC-2348](https://www.rfc-editor.org/rfc/rfc2348.html).
+    /// The server may pick a smaller blocksize.
+    pub fn set_blocksize(&mut self, blocksize: Option<u16>) {
+        self.options.blocksize = blocksize;
+    }
+
+    /// sets the timeout to request using the timeout option defined in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html).
+    /// If the server acknowledges it, it replaces the timeout of the [retransmit policy](Client::set_retransmit_policy).
+    pub fn set_timeout(&mut self, timeout_seconds: Option<NonZeroU8>) {
+        self.options.timeout_seconds = timeout_seconds;
+    }
+
+    /// sets the window size to request using the windowsize option defined in [RFC-7440](https://www.rfc-editor.org/rfc/rfc7440.html).
+    /// The server may pick a smaller window.
+    pub fn set_window_size(&mut self, window_size: Option<NonZeroU16>) {
+        self.options.window_size = window_size;
+    }
+
+    /// sets the block number the transfer continues with after block 65535, using the rollover option some clients send.
+    /// Servers that don't know the option usually continue with block 0.
+    pub fn set_rollover(&mut self, rollover: Option<Rollover>) {
+        self.options.rollover = rollover;
+    }
+
+    /// if set, read requests ask the server for the size of the file using the tsize option defined in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html).
+    pub fn set_request_transfer_size(&mut self, request_transfer_size: bool) {
+        self.options.request_transfer_size = request_transfer_size;
+    }
+
+    /// sets the size of the data uploaded by write requests, told to the server using the tsize option defined in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html).
+    /// The server may refuse files that are too large before any data is sent. In [`Mode::NetAscii`] it has to be the size after translation.
+    pub fn set_write_transfer_size(&mut self, size: Option<u64>) {
+        self.options.write_transfer_size = size;
+    }
+
+    /// sets how transfers deal with lost packets, including the request that starts them.
+    pub fn set_retransmit_policy(&mut self, policy: RetransmitPolicy) {
+        self.options.retransmit = policy;
+    }
+
+    /// returns how transfers deal with lost packets.
+    pub fn retransmit_policy(&self) -> RetransmitPolicy {
+        self.options.retransmit
+    }
+
+    /// downloads the file `filename` from the server and writes it to `sink`, returning the amount of bytes received.
+    ///
+    /// The transfer is bound to the port the server first replies from. Packets from any other address are answered with an unknown transfer ID error.
+    /// In [`Mode::NetAscii`] the amount of bytes returned is the amount received, before translating them.
+    #[cfg(feature = "std")]
+    #[doc(cfg(feature = "std"))]
+    pub fn get<W: Write>(&self, filename: &str, sink: W) -> Result<u64, TransferError> {
+        match self.options.mode {
+            Mode::Octet => self.receive(filename, sink),
+            Mode::NetAscii => {
+                let mut sink = NetAsciiWriter::new(sink);
+                let received = self.receive(filename, &mut sink)?;
+                sink.finish()?;
+                Ok(received)
+            }
+        }
+    }
+
+    /// uploads the contents of `source` to the server as the file `filename`.
+    ///
+    /// The transfer is bound to the port the server first replies from. Packets from any other address are answered with an unknown transfer ID error.
+    #[cfg(feature = "std")]
+    #[doc(cfg(feature = "std"))]
+    pub fn put<R: Read>(&self, filename: &str, source: R) -> Result<(), TransferError> {
+        match self.options.mode {
+            Mode::Octet => self.send(filename, source),
+            Mode::NetAscii => self.send(filename, NetAsciiReader::new(source)),
+        }
+    }
+
+    /// downloads the file `filename` from the server over `transport` and writes it to `sink`, returning the amount of bytes received.
+    ///
+    /// `buffer` is used to build and receive packets, it has to hold at least 4 + the requested blocksize bytes, and no less than 516 bytes.
+    /// The data is passed on as received, also in [`Mode::NetAscii`].
+    pub fn get_with<'b, T: Transport, K: DataSink>(
+        &self,
+        transport: &mut T,
+        filename: &str,
+        mut sink: K,
+        buffer: &'b mut [u8],
+    ) -> Result<u64, transport::Error<'b, T::Error, K::Error>> {
+        let result = self
+            .options
+            .receive(transport, self.server, filename, &mut sink, buffer);
+        result.map_err(|failure| failure.with_buffer(buffer))
+    }
+
+    /// uploads the data of `source` to the server over `transport` as the file `filename`.
+    ///
+    /// `buffer` is used to build and receive packets, it has to hold at least 4 + the requested blocksize bytes, and no less than 516 bytes.
+    /// The data is sent as is, also in [`Mode::NetAscii`].
+    pub fn put_with<'b, T: Transport, S: DataSource>(
+        &self,
+        transport: &mut T,
+        filename: &str,
+        mut source: S,
+        buffer: &'b mut [u8],
+    ) -> Result<(), transport::Error<'b, T::Error, S::Error>> {
+        let result = self
+            .options
+            .send(transport, self.server, filename, &mut source, buffer);
+        result.map_err(|failure| failure.with_buffer(buffer))
+    }
+
+    #[cfg(feature = "std")]
+    fn receive<W: Write>(&self, filename: &str, sink: W) -> Result<u64, TransferError> {
+        let mut sock = UdpSocket::bind(unspecified_address(self.server))?;
+        let mut buffer = vec![0u8; self.options.buffer_size()];
+        self.get_with(&mut sock, filename, WriteSink::new(sink), &mut buffer)
+            .map_err(TransferError::from)
+    }
+
+    #[cfg(feature = "std")]
+    fn send<R: Read>(&self, filename: &str, source: R) -> Result<(), TransferError> {
+        let mut sock = UdpSocket::bind(unspecified_address(self.server))?;
+        let mut buffer = vec![0u8; self.options.buffer_size()];
+        self.put_with(&mut sock, filename, ReadSource::new(source), &mut buffer)
+            .map_err(TransferError::from)
+    }
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Octet,
+            blocksize: None,
+            timeout_seconds: None,
+            window_size: None,
+            rollover: None,
+            request_transfer_size: false,
+            write_transfer_size: None,
+            retransmit: RetransmitPolicy::default(),
+        }
+    }
+}
+
+impl ClientOptions {
+    /// builds the read request for `filename` using these options.
+    pub fn read_request<'a>(&self, filename: &'a str) -> Request<'a> {
+        let mut request = self.request(Request::new_read_request(filename, self.blocksize));
+        request.transfer_size = self.request_transfer_size.then_some(0);
+        request
+    }
+
+    /// builds the write request for `filename` using these options.
+    pub fn write_request<'a>(&self, filename: &'a str) -> Request<'a> {
+        let mut request = self.request(Request::new_write_request(filename, self.blocksize));
+        request.transfer_size = self.write_transfer_size;
+        request
+    }
+
+    // adds the mode and options shared by read and write requests to `request`.
+    fn request<'a>(&self, mut request: Request<'a>) -> Request<'a> {
+        request.mode = self.mode;
+        request.timeout_seconds = self.timeout_seconds;
+        request.window_size = self.window_size;
+        request.rollover = self.rollover;
+        request
+    }
+
+    /// requests `filename` from `server` and writes the data it sends to `sink`, returning the amount of bytes received.
+    pub fn receive<T: Transport, K: DataSink>(
+        &self,
+        transport: &mut T,
+        server: SocketAddr,
+        filename: &str,
+        sink: &mut K,
+        buffer: &mut [u8],
+    ) -> Result<u64, Failure<T::Error, K::Error>> {
+        if buffer.len() < 4 + self.blocksize.map_or(512, |blocksize| blocksize.max(512)) as usize {
+            return Err(Failure::BufferTooSmall);
+        }
+        let request = self.read_request(filename);
+        let (n_bytes, server_tid) =
+            transport::send_request(transport, server, buffer, &request, &self.retransmit)?;
+        let settings = match Packet::from_bytes(&buffer[..n_bytes]) {
+            Ok(Packet::OptionAck(option_ack)) => self.check_option_ack(&option_ack, &request),
+            // the server ignored our options and started sending right away
+            Ok(Packet::Data(Data { block_nr: 1, data })) if data.len() <= 512 => {
+                return self.receive_first_block(transport, server_tid, sink, buffer, n_bytes);
+            }
+            _ => {
+                return Err(transport::reject_reply(
+                    transport, server_tid, buffer, n_bytes,
+                ))
+            }
+        };
+        match settings {
+            Ok(settings) => transport::receive_blocks(
+                transport,
+                server_tid,
+                &mut Receiver::acknowledging(settings, 0),
+                sink,
+                buffer,
+            ),
+            Err(reason) => Err(transport::refuse_options(
+                transport, server_tid, buffer, reason,
+            )),
+        }
+    }
+
+    // writes the first block of a transfer without options, which is in the first `n_bytes` of `buffer`, and receives the rest.
+    fn receive_first_block<T: Transport, K: DataSink>(
+        &self,
+        transport: &mut T,
+        server_tid: SocketAddr,
+        sink: &mut K,
+        buffer: &mut [u8],
+        n_bytes: usize,
+    ) -> Result<u64, Failure<T::Error, K::Error>> {
+        let settings = self.default_settings();
+        let data_len = n_bytes - 4;
+        let is_last_block = data_len < settings.blocksize;
+        let written = sink.write(&buffer[4..n_bytes]).and_then(|_| {
+            if is_last_block {
+                sink.finish()
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(e) = written {
+            transport::send_error(
+                transport,
+                server_tid,
+                buffer,
+                ErrorCode::DISK_FULL_OR_ALLOCATION_EXCEEDED,
+                "Unexpected IO error",
+            );
+            return Err(Failure::Data(e));
+        }
+        if is_last_block {
+            let n_bytes = Ack::new(1)
+                .to_bytes(buffer)
+                .map_err(|_| Failure::BufferTooSmall)?;
+            transport
+                .send_to(&buffer[..n_bytes], server_tid)
+                .map_err(Failure::Transport)?;
+            return Ok(data_len as u64);
+        }
+        transport::receive_blocks(
+            transport,
+            server_tid,
+            &mut Receiver::acknowledging(settings, 1),
+            sink,
+            buffer,
+        )
+        .map(|received| received + data_len as u64)
+    }
+
+    /// asks `server` to write `filename` and sends it the data of `source`.
+    pub fn send<T: Transport, S: DataSource>(
+        &self,
+        transport: &mut T,
+        server: SocketAddr,
+        filename: &str,
+        source: &mut S,
+        buffer: &mut [u8],
+    ) -> Result<(), Failure<T::Error, S::Error>> {
+        let request = self.write_request(filename);
+        let (n_bytes, server_tid) =
+            transport::send_request(transport, server, buffer, &request, &self.retransmit)?;
+        let settings = match Packet::from_bytes(&buffer[..n_bytes]) {
+            Ok(Packet::OptionAck(option_ack)) => self.check_option_ack(&option_ack, &request),
+            // the server ignored our options
+            Ok(Packet::Ack(Ack { block_nr: 0 })) => Ok(self.default_settings()),
+            _ => {
+                return Err(transport::reject_reply(
+                    transport, server_tid, buffer, n_bytes,
+                ))
+            }
+        };
+        let settings = settings
+            .map_err(|reason| transport::refuse_options(transport, server_tid, buffer, reason))?;
+        transport::send_blocks(
+            transport,
+            server_tid,
+            &mut Sender::with_settings(settings),
+            source,
+            buffer,
+        )
+    }
+
+    /// the size of the buffer needed to receive the largest packet the server may send.
+    #[cfg(feature = "std")]
+    pub fn buffer_size(&self) -> usize {
+        512 + (self.blocksize.unwrap_or(512) as usize)
+    }
+
+    /// the settings used if the server ignores our options.
+    pub fn default_settings(&self) -> Settings {
+        Settings::from_options(&OptionAck::new(None, None, None), self.retransmit)
+    }
+
+    /// checks that the server only acknowledged options we asked for in `request`, with values we can work with, and returns the settings of the transfer.
+    pub fn check_option_ack(
+        &self,
+        option_ack: &OptionAck,
+        request: &Request,
+    ) -> Result<Settings, &'static str> {
+        let acceptable_blocksize = match (option_ack.blocksize, self.blocksize) {
+            (Some(acked), Some(requested)) => acked <= requested,
+            (acked, _) => acked.is_none(),
+        };
+        let acceptable_window = match (option_ack.window_size, self.window_size) {
+            (Some(acked), Some(requested)) => acked <= requested,
+            (acked, _) => acked.is_none(),
+        };
+        if !acceptable_blocksize {
+            Err("blksize was not requested or is too large")
+        } else if !acceptable_window {
+            Err("windowsize was not requested or is too large")
+        } else if option_ack.timeout_seconds.is_some()
+            && option_ack.timeout_seconds != self.timeout_seconds
+        {
+            Err("timeout was not requested or differs from the request")
+        } else if option_ack.transfer_size.is_some() && request.transfer_size.is_none() {
+            Err("tsize was not requested")
+        } else if option_ack.rollover.is_some() && option_ack.rollover != self.rollover {
+            Err("rollover was not requested or differs from the request")
+        } else if option_ack.multicast.is_some() {
+            Err("multicast was not requested")
+        } else {
+            Ok(Settings::from_options(option_ack, self.retransmit))
+        }
+    }
+}
+
+/// the unspecified address of the same family as `server`, to bind the socket of a transfer to.
+#[cfg(feature = "std")]
+pub(crate) fn unspecified_address(server: SocketAddr) -> SocketAddr {
+    let unspecified: IpAddr = match server {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    SocketAddr::new(unspecified, 0)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    // a server socket on localhost and a client for it that retransmits quickly.
+    fn localhost() -> (UdpSocket, Client) {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut client = Client::new(sock.local_addr().unwrap());
+        client.set_retransmit_policy(RetransmitPolicy {
+            timeout: Duration::from_millis(200),
+            max_retries: 3,
+            exponential_backoff: false,
+        });
+        (sock, client)
+    }
+
+    fn transfer_socket() -> UdpSocket {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        sock
+    }
+
+    fn send(sock: &UdpSocket, packet: Packet, to: SocketAddr) {
+        let mut buffer = [0u8; 1024];
+        let n_bytes = packet.to_bytes(&mut buffer).unwrap();
+        sock.send_to(&buffer[..n_bytes], to).unwrap();
+    }
+
+    fn expect_ack(sock: &UdpSocket, block_nr: u16) {
+        let mut buffer = [0u8; 1024];
+        let n_bytes = sock.recv(&mut buffer).unwrap();
+        assert!(
+            matches!(Packet::from_bytes(&buffer[..n_bytes]), Ok(Packet::Ack(ack)) if ack.block_nr == block_nr)
+        );
+    }
+
+    #[test]
+    fn server_ignores_options() {
+        let (server, mut client) = localhost();
+        client.set_blocksize(Some(1024));
+        client.set_window_size(NonZeroU16::new(4));
+        let file: Vec<u8> = (0..600u32).map(|i| i as u8).collect();
+        thread::scope(|s| {
+            let get = s.spawn(|| {
+                let mut received = Vec::new();
+                client.get("file", &mut received).map(|_| received)
+            });
+            let mut buffer = [0u8; 1024];
+            let (_, from) = server.recv_from(&mut buffer).unwrap();
+            // an RFC-1350 server answers with the first block of 512 bytes right away
+            let transfer = transfer_socket();
+            send(&transfer, Packet::Data(Data::new(1, &file[..512])), from);
+            expect_ack(&transfer, 1);
+            send(&transfer, Packet::Data(Data::new(2, &file[512..])), from);
+            expect_ack(&transfer, 2);
+            assert_eq!(get.join().unwrap().unwrap(), file);
+
+            let put = s.spawn(|| client.put("file", &file[..]));
+            let (_, from) = server.recv_from(&mut buffer).unwrap();
+            let transfer = transfer_socket();
+            send(&transfer, Packet::Ack(Ack::new(0)), from);
+            let mut received = Vec::new();
+            for block_nr in 1..=2 {
+                let n_bytes = transfer.recv(&mut buffer).unwrap();
+                let Ok(Packet::Data(data)) = Packet::from_bytes(&buffer[..n_bytes]) else {
+                    panic!("expected a data packet");
+                };
+                assert_eq!(data.block_nr, block_nr);
+                received.extend_from_slice(data.data);
+                send(&transfer, Packet::Ack(Ack::new(block_nr)), from);
+            }
+            put.join().unwrap().unwrap();
+            assert_eq!(received, file);
+        });
+    }
+
+    #[test]
+    fn write_transfer_size() {
+        let (server, mut client) = localhost();
+        client.set_write_transfer_size(Some(4));
+        thread::scope(|s| {
+            let put = s.spawn(|| client.put("file", &b"data"[..]));
+            let mut buffer = [0u8; 1024];
+            let (n_bytes, from) = server.recv_from(&mut buffer).unwrap();
+            assert!(matches!(
+                Packet::from_bytes(&buffer[..n_bytes]),
+                Ok(Packet::Request(request)) if request.transfer_size == Some(4)
+            ));
+            let transfer = transfer_socket();
+            send(
+                &transfer,
+                Packet::OptionAck(OptionAck::new(None, Some(4), None)),
+                from,
+            );
+            let n_bytes = transfer.recv(&mut buffer).unwrap();
+            assert!(
+                matches!(Packet::from_bytes(&buffer[..n_bytes]), Ok(Packet::Data(data)) if data.data == b"data")
+            );
+            send(&transfer, Packet::Ack(Ack::new(1)), from);
+            put.join().unwrap().unwrap();
+        });
+
+        // reads don't announce a size, so the server may not acknowledge one
+        thread::scope(|s| {
+            let get = s.spawn(|| client.get("file", Vec::new()));
+            let mut buffer = [0u8; 1024];
+            let (n_bytes, from) = server.recv_from(&mut buffer).unwrap();
+            assert!(matches!(
+                Packet::from_bytes(&buffer[..n_bytes]),
+                Ok(Packet::Request(request)) if request.transfer_size.is_none()
+            ));
+            let transfer = transfer_socket();
+            send(
+                &transfer,
+                Packet::OptionAck(OptionAck::new(None, Some(4), None)),
+                from,
+            );
+            assert!(matches!(
+                get.join().unwrap(),
+                Err(TransferError::OptionNegotiation(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn request_retransmission() {
+        let (server, client) = localhost();
+        thread::scope(|s| {
+            let put = s.spawn(|| client.put("file", &b"data"[..]));
+            let mut first = [0u8; 1024];
+            let mut second = [0u8; 1024];
+            // the first request gets lost
+            let (n_bytes, from) = server.recv_from(&mut first).unwrap();
+            assert_eq!(server.recv_from(&mut second).unwrap(), (n_bytes, from));
+            assert_eq!(first[..n_bytes], second[..n_bytes]);
+            let transfer = transfer_socket();
+            send(&transfer, Packet::Ack(Ack::new(0)), from);
+            let n_bytes = transfer.recv(&mut first).unwrap();
+            assert!(
+                matches!(Packet::from_bytes(&first[..n_bytes]), Ok(Packet::Data(data)) if data.data == b"data")
+            );
+            send(&transfer, Packet::Ack(Ack::new(1)), from);
+            put.join().unwrap().unwrap();
+        });
+
+        // a server that never answers
+        let (server, client) = localhost();
+        assert!(matches!(
+            client.get("file", Vec::new()),
+            Err(TransferError::TimedOut { attempts: 4 })
+        ));
+        let mut buffer = [0u8; 1024];
+        for _ in 0..4 {
+            server.recv_from(&mut buffer).unwrap();
+        }
+    }
+
+    #[test]
+    fn unknown_transfer_id() {
+        let (server, client) = localhost();
+        let file = [7u8; 700];
+        thread::scope(|s| {
+            let get = s.spawn(|| {
+                let mut received = Vec::new();
+                client.get("file", &mut received).map(|_| received)
+            });
+            let mut buffer = [0u8; 1024];
+            let (_, from) = server.recv_from(&mut buffer).unwrap();
+            let transfer = transfer_socket();
+            send(&transfer, Packet::Data(Data::new(1, &file[..512])), from);
+            expect_ack(&transfer, 1);
+
+            // the transfer is bound to the port that answered first
+            let stranger = transfer_socket();
+            send(&stranger, Packet::Data(Data::new(2, &[0; 10])), from);
+            let n_bytes = stranger.recv(&mut buffer).unwrap();
+            assert!(matches!(
+                Packet::from_bytes(&buffer[..n_bytes]),
+                Ok(Packet::Error(error)) if error.error_code == ErrorCode::UNKNOWN_TRANSFER_ID
+            ));
+
+            send(&transfer, Packet::Data(Data::new(2, &file[512..])), from);
+            expect_ack(&transfer, 2);
+            assert_eq!(get.join().unwrap().unwrap(), file);
+        });
+    }
+}
@@ -34,14 +34,17 @@ impl<R: std::io::Read> ChunkyReader<R> {
 
 /// Wrapper around a source that implements [std::io::Read] that can be used to read out fixed size chunks at a time.
 /// similar to the chunks method on slices. This struct serves as a helper for splitting a stream like source into packets.
+///
+/// Only the last packet is kept around, blocks that have to be sent again are read from the source again, see [`restart_at`](DataStream::restart_at).
 pub(crate) struct DataStream<R: std::io::Read> {
     source: ChunkyReader<R>,
+    /// the block number of the last block read from the source.
     block_counter: u16,
     is_finished: bool,
     buffer: Vec<u8>,
 }
 
-impl<'a, R: std::io::Read> DataStream<R> {
+impl<R: std::io::Read> DataStream<R> {
     /// creates a new DataStream that will split the source up into chunks of blocksize bytes.
     pub fn new(source: R, blocksize: u16) -> Self {
         let mut buffer = vec![0u8; 4 + blocksize as usize];
@@ -59,6 +62,7 @@ impl<'a, R: std::io::Read> DataStream<R> {
         self.buffer.len() - 4
     }
 
+    /// returns the next data packet, including its header.
     pub(crate) fn next_raw(&mut self) -> std::io::Result<Option<&[u8]>> {
         if self.is_finished {
             return Ok(None);
@@ -79,15 +83,28 @@ impl<'a, R: std::io::Read> DataStream<R> {
         }
     }
 
-    pub fn last_block(&self) -> u16 {
-        self.block_counter
+    /// returns true if `block_nr` is the last block returned by [`next_raw`](Self::next_raw), or 0 before the first one,
+    /// so that the stream can just go on with the next block.
+    pub(crate) fn acknowledge(&self, block_nr: u16) -> bool {
+        block_nr == self.block_counter
+    }
+}
+
+impl<R: std::io::Read + std::io::Seek> DataStream<R> {
+    /// makes [`next_raw`](Self::next_raw) continue with the block after `block_nr`, reading it from the source again.
+    /// Used to let a multicast client that missed blocks catch up, see [RFC-2090](https://www.rfc-editor.org/rfc/rfc2090.html).
+    pub(crate) fn restart_at(&mut self, block_nr: u16) -> std::io::Result<()> {
+        let offset = block_nr as u64 * self.blocksize() as u64;
+        self.source.inner.seek(std::io::SeekFrom::Start(offset))?;
+        self.block_counter = block_nr;
+        self.is_finished = false;
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Read;
     #[test]
     fn reader() {
         let source = b"aaaabbbbccccddddeeeexxx";
@@ -116,6 +133,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn datastream_restart() {
+        let source = std::io::Cursor::new(b"aaaabbbbccccdd");
+        let mut ds = DataStream::new(source, 4);
+        assert_eq!(ds.next_raw().unwrap().unwrap(), b"\0\x03\0\x01aaaa");
+        assert_eq!(ds.next_raw().unwrap().unwrap(), b"\0\x03\0\x02bbbb");
+        assert_eq!(ds.next_raw().unwrap().unwrap(), b"\0\x03\0\x03cccc");
+        assert_eq!(ds.next_raw().unwrap().unwrap(), b"\0\x03\0\x04dd");
+        assert_eq!(ds.next_raw().unwrap(), None);
+        assert!(ds.acknowledge(4));
+        ds.restart_at(1).unwrap();
+        assert!(!ds.acknowledge(4));
+        assert_eq!(ds.next_raw().unwrap().unwrap(), b"\0\x03\0\x02bbbb");
+        ds.restart_at(0).unwrap();
+        assert_eq!(ds.next_raw().unwrap().unwrap(), b"\0\x03\0\x01aaaa");
+        assert!(ds.acknowledge(1));
+        assert_eq!(ds.next_raw().unwrap().unwrap(), b"\0\x03\0\x02bbbb");
+    }
+
     //todo: add tests for the error cases
     // e.g. implement a reader that fails after a few bytes and check that it doesn't return garbage
 }
@@ -1,3 +1,9 @@
+#[cfg(feature = "std")]
+use crate::packet::{ErrorCode, OpCode};
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io::{Error as IoError, ErrorKind};
+
 /// Errors that can occur during TFTP parsing or io.
 #[derive(Debug)]
 pub enum Error {
@@ -16,8 +22,133 @@ pub enum Error {
     #[cfg(feature = "std")]
     #[doc(cfg(feature = "std"))]
     /// an error occured during io
-    IoError(std::io::Error),
+    IoError(IoError),
 }
 
 /// Alias for `Result<T, Error>`
 pub type Result<T> = core::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BufferTooSmall => f.write_str("buffer too small for the packet"),
+            Self::InvalidOpcode(opcode) => write!(f, "invalid opcode {opcode}"),
+            Self::InvalidAck => f.write_str("unexpected ack"),
+            Self::BadFormatting => f.write_str("badly formatted string field"),
+            Self::OptionRepeated => f.write_str("option sent more than once"),
+            Self::InvalidBlockSize(blocksize) => write!(f, "invalid blocksize {blocksize}"),
+            #[cfg(feature = "std")]
+            Self::IoError(e) => e.fmt(f),
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            Self::IoError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[doc(cfg(feature = "std"))]
+impl From<IoError> for Error {
+    fn from(error: IoError) -> Self {
+        Self::IoError(error)
+    }
+}
+
+/// Why a transfer run by the [server](crate::server) or [client](crate::client::Client) failed.
+///
+/// Whenever it makes sense the peer was sent an error packet before this is returned, see the documentation of the transfer.
+#[cfg(feature = "std")]
+#[doc(cfg(feature = "std"))]
+#[derive(Debug)]
+pub enum TransferError {
+    /// the peer aborted the transfer by sending an error packet.
+    Peer {
+        /// the error code the peer sent.
+        error_code: ErrorCode,
+        /// the message the peer sent along with it.
+        message: String,
+    },
+    /// the peer stopped replying after the same packet was sent `attempts` times.
+    TimedOut {
+        /// how many times the last packet was sent.
+        attempts: u32,
+    },
+    /// the peer sent a packet that doesn't fit the transfer, or one that isn't a valid TFTP packet.
+    ProtocolViolation {
+        /// the opcode of the offending packet, or `None` if it couldn't be parsed.
+        opcode: Option<OpCode>,
+        /// what was wrong with it.
+        reason: String,
+    },
+    /// reading or writing the data, or using the socket failed.
+    Io(IoError),
+    /// the server acknowledged options that weren't requested, or with values we can't work with.
+    OptionNegotiation(&'static str),
+    /// the data needs more than 65535 blocks, and block numbers may not roll over.
+    TooManyBlocks,
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Peer {
+                error_code,
+                message,
+            } => write!(f, "Received TFTP error ({error_code} : \"{message}\")"),
+            Self::TimedOut { attempts } => write!(
+                f,
+                "No reply received after sending the same packet {attempts} times"
+            ),
+            Self::ProtocolViolation { reason, .. } => f.write_str(reason),
+            Self::Io(e) => e.fmt(f),
+            Self::OptionNegotiation(reason) => {
+                write!(f, "Server acknowledged invalid options: {reason}")
+            }
+            Self::TooManyBlocks => f.write_str(
+                "File needs more than 65535 blocks, but block numbers may not roll over",
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TransferError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<IoError> for TransferError {
+    fn from(error: IoError) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// keeps io errors as they are, and wraps anything else into an io error of a fitting kind.
+#[cfg(feature = "std")]
+impl From<TransferError> for IoError {
+    fn from(error: TransferError) -> Self {
+        let kind = match error {
+            TransferError::Io(e) => return e,
+            TransferError::TimedOut { .. } => ErrorKind::TimedOut,
+            TransferError::Peer { .. } => ErrorKind::Other,
+            TransferError::ProtocolViolation { .. } | TransferError::OptionNegotiation(_) => {
+                ErrorKind::InvalidData
+            }
+            TransferError::TooManyBlocks => ErrorKind::FileTooLarge,
+        };
+        IoError::new(kind, error)
+    }
+}
@@ -14,29 +14,57 @@
 //!
 //! ✅ [2348 - TFTP Blocksize Option](https://www.rfc-editor.org/rfc/rfc2348.html)
 //!
-//! ⚠️ [2349 - TFTP Timeout Interval and Transfer Size Options](https://www.rfc-editor.org/rfc/rfc2349.html)
+//! ✅ [2349 - TFTP Timeout Interval and Transfer Size Options](https://www.rfc-editor.org/rfc/rfc2349.html)
 //!
-//! ╰Timeout option is recognized by the packet parser, but not supported by the server.
+//! ✅ [7440 - TFTP Windowsize Option](https://www.rfc-editor.org/rfc/rfc7440.html)
 //!
-//! ❌ [2090 - TFTP Multicast Option](https://www.rfc-editor.org/rfc/rfc2090.html)
+//! ✅ [2090 - TFTP Multicast Option](https://www.rfc-editor.org/rfc/rfc2090.html) (server only)
 //!
 //!# `#[no_std]` support
-//! This crate is `#[no_std]` by default, exposing only packet and error handling code.
-//! With the `std` feature turned on a small socket interface and server are enabled too.
+//! This crate is `#[no_std]` by default, exposing packet handling code and transfers that run over any network stack implementing the [`Transport`](transport::Transport) trait.
+//! With the `std` feature turned on a small socket interface, client and server are enabled too.
+//! The `alloc` feature adds owned versions of the packet types, like [`OwnedPacket`](packet::OwnedPacket), for platforms without `std` that have an allocator. `std` turns it on too.
+//! The `tokio` feature adds async versions of those.
+//! The `simulator` feature adds an in-memory network that loses, duplicates, reorders and delays packets, for testing transfers.
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+/// a small client implementation
+pub mod client;
 #[cfg(feature = "std")]
 mod datastream;
 /// error types for this crate
 pub mod error;
+/// sans-IO state machines implementing the protocol of a transfer
+pub mod machine;
+/// adapters translating text to and from the netascii transfer mode
+#[cfg(feature = "std")]
+#[doc(cfg(feature = "std"))]
+pub mod netascii;
+/// negotiating the options defined in RFC-2347, including ones this crate doesn't know
+pub mod options;
 /// all type definitions needed to parse TFTP packets
 pub mod packet;
 /// a small server implementation
 #[cfg(feature = "std")]
 #[doc(cfg(feature = "std"))]
 pub mod server;
+/// an in-memory network with configurable packet loss, for testing transfers
+#[cfg(feature = "simulator")]
+#[doc(cfg(feature = "simulator"))]
+pub mod simulator;
 #[cfg(feature = "std")]
 #[doc(cfg(feature = "std"))]
 /// A wrapper around a UDP socket that can be used to build a client or server,
 pub mod socket;
+/// async versions of the socket, server and client, using tokio
+#[cfg(feature = "tokio")]
+#[doc(cfg(feature = "tokio"))]
+pub mod tokio;
+#[cfg(feature = "std")]
+mod transfer;
+/// running transfers over any network stack
+pub mod transport;
 
 pub use error::Result;
 pub use packet::Packet;
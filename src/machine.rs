@@ -0,0 +1,897 @@
+use crate::{
+    packet::{self, Ack, Data, ErrorCode, OpCode, OptionAck, Packet, Rollover},
+    transport::{DataSource, RetransmitPolicy, Settings},
+};
+use core::time::Duration;
+
+/// Why a state machine stopped a transfer.
+///
+/// Packets received from the peer are borrowed from the bytes passed to `handle_packet`.
+/// Unless the peer sent an error itself, an error packet for the peer is waiting to be sent by `poll_transmit`.
+#[derive(Debug)]
+pub enum Stop<'p> {
+    /// the peer aborted the transfer by sending an error packet.
+    Peer(packet::Error<'p>),
+    /// the peer sent a packet that doesn't fit the transfer.
+    Unexpected(Packet<'p>),
+    /// the peer sent something that isn't a valid TFTP packet.
+    InvalidPacket(crate::error::Error),
+    /// the peer stopped replying after the same packet was sent `attempts` times.
+    TimedOut {
+        /// how many times the last packet was sent.
+        attempts: u32,
+    },
+    /// the data needs more than 65535 blocks, and block numbers may not roll over.
+    TooManyBlocks,
+}
+
+// the highest block number, after which block numbers roll over if that is allowed.
+const LAST_BLOCK_NR: u16 = u16::MAX;
+
+// the message sent to a peer that sent a packet that doesn't fit the transfer.
+pub(crate) const ILLEGAL_OPERATION: &str = "Illegal TFTP operation";
+
+/// How often a [`Sender`] received acks that didn't acknowledge anything new, see [`Sender::handle_packet`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IgnoredAcks {
+    /// acks of the last acknowledged block received again, either duplicated by the network or sent again by the peer after a timeout.
+    pub duplicate: u64,
+    /// acks of blocks before the last acknowledged one, which the network delayed or reordered.
+    pub stale: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// waiting for the peer to acknowledge our option acknowledgement with an ack of block 0.
+    OptionAck,
+    Transferring,
+    /// every block has been received, but the final ack hasn't been sent yet.
+    Complete,
+    Finished,
+    Failed,
+}
+
+/// The sending side of a transfer, without any io: the server answering a read request, or the client after a write request.
+///
+/// Drive it by calling [`poll_transmit`](Sender::poll_transmit) until it returns `None` and sending every packet it writes,
+/// then wait for a packet from the peer until the [`deadline`](Sender::deadline) and pass it to [`handle_packet`](Sender::handle_packet),
+/// or call [`handle_timeout`](Sender::handle_timeout) if none arrived in time. Repeat until [`is_finished`](Sender::is_finished) or a [`Stop`] is returned.
+///
+/// Time is passed in as the time elapsed since any fixed point, like the start of the transfer.
+/// [`transport::Transfer`](crate::transport::Transfer) drives it over a [`Transport`](crate::transport::Transport).
+#[derive(Debug, Clone)]
+pub struct Sender {
+    settings: Settings,
+    option_ack: OptionAck<'static>,
+    phase: Phase,
+    // blocks are counted from 1 without wrapping around, only the block numbers sent to the peer do.
+    acknowledged: u64,
+    /// the blocks after the acknowledged ones that have been sent since the last acknowledgement or retransmission.
+    next: u64,
+    /// the blocks after the acknowledged ones that have been sent at all.
+    sent: u64,
+    /// the final block, once we know it.
+    last_block: Option<u64>,
+    /// the data before this offset has been released from the source.
+    released: u64,
+    attempt: u32,
+    ignored_acks: IgnoredAcks,
+    deadline: Option<Duration>,
+    error: Option<(ErrorCode, &'static str)>,
+}
+
+impl Sender {
+    /// creates a sender replying to a read request. If `options` isn't empty it is sent first,
+    /// and the peer has to acknowledge it before any data is sent.
+    pub fn new(options: OptionAck<'static>, retransmit: RetransmitPolicy) -> Self {
+        let mut sender = Self::with_settings(Settings::from_options(&options, retransmit));
+        if !options.is_empty() {
+            sender.phase = Phase::OptionAck;
+            sender.option_ack = options;
+        }
+        sender
+    }
+
+    /// creates a sender that starts sending data right away, using the settings of an already negotiated transfer.
+    pub(crate) fn with_settings(settings: Settings) -> Self {
+        Self {
+            settings,
+            option_ack: OptionAck::new(None, None, None),
+            phase: Phase::Transferring,
+            acknowledged: 0,
+            next: 0,
+            sent: 0,
+            last_block: None,
+            released: 0,
+            attempt: 0,
+            ignored_acks: IgnoredAcks::default(),
+            deadline: None,
+            error: None,
+        }
+    }
+
+    /// sets what the block number continues with after block 65535, or `None` to stop the transfer with [`Stop::TooManyBlocks`] once it needs more blocks than that.
+    /// [`new`](Sender::new) uses the rollover in the options, or [`Rollover::Zero`] if it isn't set.
+    pub fn set_rollover(&mut self, rollover: Option<Rollover>) {
+        self.settings.rollover = rollover;
+    }
+
+    /// the size of the buffer needed by [`poll_transmit`](Sender::poll_transmit).
+    pub fn buffer_size(&self) -> usize {
+        4 + self.settings.blocksize.max(512)
+    }
+
+    /// writes the next packet to send to the peer into `buffer` and returns its size, or `None` if there is nothing to send
+    /// until a packet arrives or the deadline passes. Data blocks are read from `source`, which is told once blocks have been acknowledged.
+    ///
+    /// If reading the source fails, the transfer is stopped and the error returned. The next call returns an error packet for the peer.
+    ///
+    /// # Panics
+    /// if `buffer` is smaller than [`buffer_size`](Sender::buffer_size).
+    pub fn poll_transmit<S: DataSource>(
+        &mut self,
+        now: Duration,
+        source: &mut S,
+        buffer: &mut [u8],
+    ) -> Result<Option<usize>, S::Error> {
+        if let Some((error_code, message)) = self.error.take() {
+            return Ok(Packet::new_error(error_code, message).to_bytes(buffer).ok());
+        }
+        let blocksize = self.settings.blocksize;
+        let released = self.acknowledged * blocksize as u64;
+        if released > self.released {
+            source.release(released);
+            self.released = released;
+        }
+        match self.phase {
+            Phase::OptionAck if self.next == 0 => {
+                let n_bytes = self
+                    .option_ack
+                    .to_bytes(buffer)
+                    .expect("buffer too small for the option acknowledgement");
+                self.next = 1;
+                self.deadline =
+                    Some(now + self.settings.retransmit.timeout_for_attempt(self.attempt));
+                Ok(Some(n_bytes))
+            }
+            Phase::Transferring
+                if self.next < self.settings.window_size as u64
+                    && self
+                        .last_block
+                        .is_none_or(|last| self.acknowledged + self.next < last)
+                    && (self.settings.rollover.is_some()
+                        || self.acknowledged + self.next < LAST_BLOCK_NR as u64) =>
+            {
+                let block = self.acknowledged + self.next + 1;
+                let offset = (block - 1) * blocksize as u64;
+                let n_bytes = match source.read_at(offset, &mut buffer[4..4 + blocksize]) {
+                    Ok(n_bytes) => n_bytes,
+                    Err(e) => {
+                        self.fail(ErrorCode::NOT_DEFINED, "Unexpected IO error");
+                        return Err(e);
+                    }
+                };
+                if n_bytes < blocksize {
+                    self.last_block = Some(block);
+                }
+                buffer[..2].copy_from_slice(&(OpCode::Data as u16).to_be_bytes());
+                buffer[2..4].copy_from_slice(&self.block_nr(block).to_be_bytes());
+                self.next += 1;
+                self.sent = self.sent.max(self.next);
+                self.deadline =
+                    Some(now + self.settings.retransmit.timeout_for_attempt(self.attempt));
+                Ok(Some(4 + n_bytes))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// handles a packet received from the peer.
+    ///
+    /// If the peer acknowledges only part of a window, the transfer continues from the first block the peer is missing.
+    /// Acks that don't acknowledge anything new are ignored and counted in [`ignored_acks`](Sender::ignored_acks):
+    /// a duplicate ack of the last acknowledged block doesn't make us send the blocks after it again, as that leads to the
+    /// Sorcerer's Apprentice Syndrome described in [RFC-1123](https://www.rfc-editor.org/rfc/rfc1123#section-4.2.3.1).
+    /// Acks of earlier blocks that were delayed by the network are ignored too. Lost blocks are sent again once the deadline passes instead.
+    /// Anything else, like an ack of a block that was never sent, stops the transfer.
+    pub fn handle_packet<'p>(&mut self, packet: &'p [u8]) -> Result<(), Stop<'p>> {
+        if !matches!(self.phase, Phase::OptionAck | Phase::Transferring) {
+            return Ok(());
+        }
+        let block_nr = match Packet::from_bytes(packet) {
+            Ok(Packet::Ack(Ack { block_nr })) => block_nr,
+            // the server sent its option acknowledgement again because our first block got lost, or the network duplicated it.
+            // It acknowledges the request just like an ack of block 0 would.
+            Ok(Packet::OptionAck(_)) if self.option_ack.is_empty() => 0,
+            Ok(packet) => return Err(self.stop(packet)),
+            Err(e) => {
+                self.fail(ErrorCode::ILLEGAL_TFTP_OPERATION, ILLEGAL_OPERATION);
+                return Err(Stop::InvalidPacket(e));
+            }
+        };
+        if self.phase == Phase::OptionAck {
+            if block_nr != 0 {
+                return Err(self.stop(Packet::new_ack(block_nr)));
+            }
+            self.phase = Phase::Transferring;
+            self.next = 0;
+            self.attempt = 0;
+            self.deadline = None;
+            return Ok(());
+        }
+        let newly_acknowledged =
+            (1..=self.sent).find(|&n| self.block_nr(self.acknowledged + n) == block_nr);
+        if let Some(newly_acknowledged) = newly_acknowledged {
+            self.acknowledged += newly_acknowledged;
+            self.sent -= newly_acknowledged;
+            // if the peer didn't acknowledge the whole window it missed a block,
+            // so we continue from the first block it doesn't have.
+            self.next = 0;
+            self.attempt = 0;
+            self.deadline = None;
+            if Some(self.acknowledged) == self.last_block {
+                self.phase = Phase::Finished;
+            } else if self.settings.rollover.is_none() && self.acknowledged == LAST_BLOCK_NR as u64
+            {
+                self.fail(
+                    ErrorCode::NOT_DEFINED,
+                    "File is too large to send without block number rollover",
+                );
+                return Err(Stop::TooManyBlocks);
+            }
+            return Ok(());
+        }
+        // block numbers are reused once they roll over, so an ack that could be for one of the next blocks
+        // in the window is for a block we didn't send yet, rather than for a block that was acknowledged a long time ago.
+        let not_sent_yet = (self.sent + 1..=self.settings.window_size as u64)
+            .any(|n| self.block_nr(self.acknowledged + n) == block_nr);
+        match self.last_block_numbered(block_nr) {
+            Some(block) if block == self.acknowledged => self.ignored_acks.duplicate += 1,
+            Some(_) if !not_sent_yet => self.ignored_acks.stale += 1,
+            _ => return Err(self.stop(Packet::new_ack(block_nr))),
+        }
+        Ok(())
+    }
+
+    /// returns how many acks were ignored because they didn't acknowledge anything new.
+    pub fn ignored_acks(&self) -> IgnoredAcks {
+        self.ignored_acks
+    }
+
+    /// handles the deadline passing without a reply from the peer, by sending the unacknowledged packets again as described by the retransmit policy.
+    /// Does nothing if the deadline hasn't passed yet.
+    pub fn handle_timeout(&mut self, now: Duration) -> Result<(), Stop<'static>> {
+        if self.deadline.is_none_or(|deadline| now < deadline) {
+            return Ok(());
+        }
+        self.deadline = None;
+        self.attempt += 1;
+        if self.attempt > self.settings.retransmit.max_retries {
+            self.fail(ErrorCode::NOT_DEFINED, "Timed out waiting for a reply");
+            return Err(Stop::TimedOut {
+                attempts: self.attempt,
+            });
+        }
+        self.next = 0;
+        Ok(())
+    }
+
+    /// returns when [`handle_timeout`](Sender::handle_timeout) should be called if no packet arrives, or `None` if we're not waiting on the peer.
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline
+            .filter(|_| matches!(self.phase, Phase::OptionAck | Phase::Transferring))
+    }
+
+    /// returns true once the peer has acknowledged every block.
+    pub fn is_finished(&self) -> bool {
+        self.phase == Phase::Finished
+    }
+
+    // the number block `block` (counting from 1, where block 0 is the request) is sent with.
+    fn block_nr(&self, block: u64) -> u16 {
+        match self.settings.rollover {
+            Some(Rollover::One) if block > 0 => ((block - 1) % LAST_BLOCK_NR as u64 + 1) as u16,
+            _ => block as u16,
+        }
+    }
+
+    // the last block up to the acknowledged one that was sent with number `block_nr`, if any.
+    fn last_block_numbered(&self, block_nr: u16) -> Option<u64> {
+        let acknowledged = self.acknowledged;
+        match self.settings.rollover {
+            // block 0 is never used again after the request.
+            Some(Rollover::One) if block_nr == 0 => Some(0),
+            Some(Rollover::One) => {
+                let period = LAST_BLOCK_NR as u64;
+                let behind =
+                    (self.block_nr(acknowledged) as u64 + period - block_nr as u64) % period;
+                acknowledged.checked_sub(behind).filter(|&block| block > 0)
+            }
+            _ => {
+                acknowledged.checked_sub(self.block_nr(acknowledged).wrapping_sub(block_nr) as u64)
+            }
+        }
+    }
+
+    // stops the transfer on a packet that doesn't fit it. Unless it is an error of the peer, the peer is told it broke the protocol.
+    fn stop<'p>(&mut self, packet: Packet<'p>) -> Stop<'p> {
+        match packet {
+            Packet::Error(e) => {
+                self.phase = Phase::Failed;
+                Stop::Peer(e)
+            }
+            packet => {
+                self.fail(ErrorCode::ILLEGAL_TFTP_OPERATION, ILLEGAL_OPERATION);
+                Stop::Unexpected(packet)
+            }
+        }
+    }
+
+    fn fail(&mut self, error_code: ErrorCode, message: &'static str) {
+        self.phase = Phase::Failed;
+        self.error = Some((error_code, message));
+    }
+}
+
+/// The receiving side of a transfer, without any io: the server answering a write request, or the client after a read request.
+///
+/// Drive it by sending the packet [`poll_transmit`](Receiver::poll_transmit) writes, if any,
+/// then wait for a packet from the peer until the [`deadline`](Receiver::deadline) and pass it to [`handle_packet`](Receiver::handle_packet),
+/// which returns the data to deliver, or call [`handle_timeout`](Receiver::handle_timeout) if none arrived in time.
+/// Repeat until [`is_finished`](Receiver::is_finished) or a [`Stop`] is returned.
+///
+/// Time is passed in as the time elapsed since any fixed point, like the start of the transfer.
+/// [`transport::IncomingTransfer`](crate::transport::IncomingTransfer) drives it over a [`Transport`](crate::transport::Transport).
+#[derive(Debug, Clone)]
+pub struct Receiver {
+    settings: Settings,
+    /// sent instead of an ack of block 0 if not empty.
+    option_ack: OptionAck<'static>,
+    phase: Phase,
+    last_block: u16,
+    received_in_window: usize,
+    bytes_received: u64,
+    attempt: u32,
+    /// set when the last acknowledgement (or option acknowledgement) has to be sent (again).
+    transmit: bool,
+    deadline: Option<Duration>,
+    error: Option<(ErrorCode, &'static str)>,
+}
+
+impl Receiver {
+    /// creates a receiver replying to a write request. The request is acknowledged with `options`, or with an ack of block 0 if it is empty.
+    pub fn new(options: OptionAck<'static>, retransmit: RetransmitPolicy) -> Self {
+        let mut receiver = Self::acknowledging(Settings::from_options(&options, retransmit), 0);
+        receiver.option_ack = options;
+        receiver
+    }
+
+    /// creates a receiver that starts by acknowledging `block_nr`, using the settings of an already negotiated transfer.
+    pub(crate) fn acknowledging(settings: Settings, block_nr: u16) -> Self {
+        Self {
+            settings,
+            option_ack: OptionAck::new(None, None, None),
+            phase: Phase::Transferring,
+            last_block: block_nr,
+            received_in_window: 0,
+            bytes_received: 0,
+            attempt: 0,
+            transmit: true,
+            deadline: None,
+            error: None,
+        }
+    }
+
+    /// sets what the block number continues with after block 65535, or `None` to stop the transfer with [`Stop::TooManyBlocks`] once the peer sends more blocks than that.
+    /// [`new`](Receiver::new) uses the rollover in the options, or [`Rollover::Zero`] if it isn't set.
+    pub fn set_rollover(&mut self, rollover: Option<Rollover>) {
+        self.settings.rollover = rollover;
+    }
+
+    /// the size of the buffer needed to receive the largest packet the peer may send.
+    pub fn buffer_size(&self) -> usize {
+        4 + self.settings.blocksize.max(512)
+    }
+
+    /// writes the next packet to send to the peer into `buffer` and returns its size, or `None` if there is nothing to send
+    /// until a packet arrives or the deadline passes.
+    ///
+    /// # Panics
+    /// if `buffer` can't hold the option acknowledgement passed to [`new`](Receiver::new).
+    pub fn poll_transmit(&mut self, now: Duration, buffer: &mut [u8]) -> Option<usize> {
+        if let Some((error_code, message)) = self.error.take() {
+            return Packet::new_error(error_code, message).to_bytes(buffer).ok();
+        }
+        if !self.transmit || self.phase == Phase::Failed {
+            return None;
+        }
+        self.transmit = false;
+        let n_bytes = if self.last_block == 0 && !self.option_ack.is_empty() {
+            self.option_ack.to_bytes(buffer)
+        } else {
+            Ack::new(self.last_block).to_bytes(buffer)
+        }
+        .expect("buffer too small for the option acknowledgement");
+        if self.phase == Phase::Complete {
+            // nobody will tell us if this ack gets lost. If it does, the peer will time out
+            // but it will still have sent us the whole file.
+            self.phase = Phase::Finished;
+        } else {
+            self.deadline = Some(now + self.settings.retransmit.timeout_for_attempt(self.attempt));
+        }
+        Some(n_bytes)
+    }
+
+    /// handles a packet received from the peer, returning the data to deliver if it is the next block.
+    ///
+    /// Every window of blocks is acknowledged. If the peer sends a data packet we don't expect, for example because our ack got lost,
+    /// the last block we received is acknowledged again and the packet is ignored. Anything but a data packet stops the transfer.
+    pub fn handle_packet<'p>(
+        &mut self,
+        now: Duration,
+        packet: &'p [u8],
+    ) -> Result<Option<&'p [u8]>, Stop<'p>> {
+        if self.phase != Phase::Transferring {
+            return Ok(None);
+        }
+        let (block_nr, data) = match Packet::from_bytes(packet) {
+            Ok(Packet::Data(Data { block_nr, data })) => (block_nr, data),
+            // the server sent its option acknowledgement again because our ack of it got lost, which we answer,
+            // or the network duplicated or delayed it, which we ignore once data arrives.
+            Ok(Packet::OptionAck(_)) if self.option_ack.is_empty() => {
+                self.transmit |= self.last_block == 0 && self.bytes_received == 0;
+                return Ok(None);
+            }
+            Ok(Packet::Error(e)) => {
+                self.phase = Phase::Failed;
+                return Err(Stop::Peer(e));
+            }
+            Ok(packet) => {
+                self.abort(ErrorCode::ILLEGAL_TFTP_OPERATION, ILLEGAL_OPERATION);
+                return Err(Stop::Unexpected(packet));
+            }
+            Err(e) => {
+                self.abort(ErrorCode::ILLEGAL_TFTP_OPERATION, ILLEGAL_OPERATION);
+                return Err(Stop::InvalidPacket(e));
+            }
+        };
+        let next_block_nr = match self.settings.rollover {
+            Some(rollover) => rollover.next(self.last_block),
+            None if self.last_block == LAST_BLOCK_NR => {
+                if block_nr == LAST_BLOCK_NR {
+                    // the last block we can receive was sent again, so our ack got lost.
+                    self.transmit = true;
+                    return Ok(None);
+                }
+                self.abort(
+                    ErrorCode::DISK_FULL_OR_ALLOCATION_EXCEEDED,
+                    "File is too large to receive without block number rollover",
+                );
+                return Err(Stop::TooManyBlocks);
+            }
+            None => self.last_block + 1,
+        };
+        if block_nr != next_block_nr || data.len() > self.settings.blocksize {
+            // either our previous ack got lost and the peer sent the same block again,
+            // or we missed a block of the current window. Either way the peer needs to know where to continue.
+            self.transmit = true;
+            self.received_in_window = 0;
+            return Ok(None);
+        }
+        self.last_block = block_nr;
+        self.bytes_received += data.len() as u64;
+        self.received_in_window += 1;
+        self.attempt = 0;
+        self.deadline = Some(now + self.settings.retransmit.timeout_for_attempt(self.attempt));
+        if data.len() < self.settings.blocksize {
+            self.phase = Phase::Complete;
+            self.transmit = true;
+        } else if self.received_in_window == self.settings.window_size {
+            self.transmit = true;
+            self.received_in_window = 0;
+        }
+        Ok(Some(data))
+    }
+
+    /// handles the deadline passing without a packet from the peer, by sending the last acknowledgement again as described by the retransmit policy.
+    /// Does nothing if the deadline hasn't passed yet.
+    pub fn handle_timeout(&mut self, now: Duration) -> Result<(), Stop<'static>> {
+        if self.phase != Phase::Transferring || self.deadline.is_none_or(|deadline| now < deadline)
+        {
+            return Ok(());
+        }
+        self.deadline = None;
+        self.attempt += 1;
+        if self.attempt > self.settings.retransmit.max_retries {
+            self.abort(ErrorCode::NOT_DEFINED, "Timed out waiting for a reply");
+            return Err(Stop::TimedOut {
+                attempts: self.attempt,
+            });
+        }
+        self.transmit = true;
+        self.received_in_window = 0;
+        Ok(())
+    }
+
+    /// stops the transfer, for example because the data couldn't be written. The next call to [`poll_transmit`](Receiver::poll_transmit) returns an error packet for the peer.
+    pub fn abort(&mut self, error_code: ErrorCode, message: &'static str) {
+        self.phase = Phase::Failed;
+        self.error = Some((error_code, message));
+    }
+
+    /// returns when [`handle_timeout`](Receiver::handle_timeout) should be called if no packet arrives, or `None` if we're not waiting on the peer.
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline.filter(|_| self.phase == Phase::Transferring)
+    }
+
+    /// returns true once the final block has been received, which is when the data should be flushed.
+    pub fn is_complete(&self) -> bool {
+        matches!(self.phase, Phase::Complete | Phase::Finished)
+    }
+
+    /// returns true once the final block has been received and acknowledged.
+    pub fn is_finished(&self) -> bool {
+        self.phase == Phase::Finished
+    }
+
+    /// returns the amount of bytes received so far.
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::num::NonZeroU16;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn options(blocksize: u16, window_size: u16) -> OptionAck<'static> {
+        let mut options = OptionAck::new(Some(blocksize), None, None);
+        options.window_size = NonZeroU16::new(window_size);
+        options
+    }
+
+    #[test]
+    fn sender_windows() {
+        let data = [7u8; 20];
+        let mut source = &data[..];
+        let mut sender = Sender::new(options(8, 2), RetransmitPolicy::default());
+        let mut buffer = [0u8; 516];
+        // the option acknowledgement has to be acknowledged first
+        let n_bytes = sender.poll_transmit(Duration::ZERO, &mut source, &mut buffer);
+        assert!(matches!(
+            Packet::from_bytes(&buffer[..n_bytes.unwrap().unwrap()]),
+            Ok(Packet::OptionAck(_))
+        ));
+        assert_eq!(
+            sender.poll_transmit(Duration::ZERO, &mut source, &mut buffer),
+            Ok(None)
+        );
+        sender.handle_packet(&[0, 4, 0, 0]).unwrap();
+
+        let mut sent = [0u16; 2];
+        for block in &mut sent {
+            let n_bytes = sender
+                .poll_transmit(Duration::ZERO, &mut source, &mut buffer)
+                .unwrap()
+                .unwrap();
+            assert_eq!(n_bytes, 12);
+            *block = u16::from_be_bytes([buffer[2], buffer[3]]);
+        }
+        assert_eq!(sent, [1, 2]);
+        assert_eq!(
+            sender.poll_transmit(Duration::ZERO, &mut source, &mut buffer),
+            Ok(None)
+        );
+        assert_eq!(sender.deadline(), Some(SECOND));
+
+        // only the first block arrived, so the window continues at block 2
+        sender.handle_packet(&[0, 4, 0, 1]).unwrap();
+        sender
+            .poll_transmit(Duration::ZERO, &mut source, &mut buffer)
+            .unwrap()
+            .unwrap();
+        assert_eq!(buffer[3], 2);
+        let n_bytes = sender
+            .poll_transmit(Duration::ZERO, &mut source, &mut buffer)
+            .unwrap()
+            .unwrap();
+        assert_eq!((buffer[3], n_bytes), (3, 8));
+
+        // nothing arrives, both blocks are sent again
+        sender.handle_timeout(SECOND / 2).unwrap();
+        assert_eq!(
+            sender.poll_transmit(SECOND / 2, &mut source, &mut buffer),
+            Ok(None)
+        );
+        sender.handle_timeout(SECOND).unwrap();
+        sender
+            .poll_transmit(SECOND, &mut source, &mut buffer)
+            .unwrap()
+            .unwrap();
+        assert_eq!(buffer[3], 2);
+
+        sender.handle_packet(&[0, 4, 0, 3]).unwrap();
+        assert!(sender.is_finished());
+        assert_eq!(sender.deadline(), None);
+    }
+
+    #[test]
+    fn sender_gives_up() {
+        let retransmit = RetransmitPolicy {
+            max_retries: 1,
+            ..Default::default()
+        };
+        let mut source: &[u8] = b"data";
+        let mut sender = Sender::new(OptionAck::new(None, None, None), retransmit);
+        let mut buffer = [0u8; 516];
+        assert_eq!(
+            sender.poll_transmit(Duration::ZERO, &mut source, &mut buffer),
+            Ok(Some(8))
+        );
+        sender.handle_timeout(SECOND).unwrap();
+        assert_eq!(
+            sender.poll_transmit(SECOND, &mut source, &mut buffer),
+            Ok(Some(8))
+        );
+        assert!(matches!(
+            sender.handle_timeout(2 * SECOND),
+            Err(Stop::TimedOut { attempts: 2 })
+        ));
+        let n_bytes = sender
+            .poll_transmit(2 * SECOND, &mut source, &mut buffer)
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            Packet::from_bytes(&buffer[..n_bytes]),
+            Ok(Packet::Error(_))
+        ));
+    }
+
+    #[test]
+    fn sender_ignores_old_acks() {
+        let data = [7u8; 20];
+        let mut source = &data[..];
+        let mut sender = Sender::new(options(8, 1), RetransmitPolicy::default());
+        let mut buffer = [0u8; 516];
+        sender
+            .poll_transmit(Duration::ZERO, &mut source, &mut buffer)
+            .unwrap();
+        sender.handle_packet(&[0, 4, 0, 0]).unwrap();
+        sender
+            .poll_transmit(Duration::ZERO, &mut source, &mut buffer)
+            .unwrap();
+        sender.handle_packet(&[0, 4, 0, 1]).unwrap();
+        sender
+            .poll_transmit(Duration::ZERO, &mut source, &mut buffer)
+            .unwrap();
+        assert_eq!(buffer[3], 2);
+
+        // a duplicate of the last ack doesn't make us send block 2 again, and neither does a delayed ack of block 0
+        sender.handle_packet(&[0, 4, 0, 1]).unwrap();
+        sender.handle_packet(&[0, 4, 0, 0]).unwrap();
+        assert_eq!(
+            sender.poll_transmit(Duration::ZERO, &mut source, &mut buffer),
+            Ok(None)
+        );
+        assert_eq!(
+            sender.ignored_acks(),
+            IgnoredAcks {
+                duplicate: 1,
+                stale: 1
+            }
+        );
+
+        // but acknowledging a block that wasn't sent yet is an error
+        assert!(matches!(
+            sender.handle_packet(&[0, 4, 0, 3]),
+            Err(Stop::Unexpected(Packet::Ack(Ack { block_nr: 3 })))
+        ));
+    }
+
+    // sends `data` in blocks of 8 bytes, acknowledging each block right away,
+    // and returns how many blocks were sent, the numbers of the last 5 and whether it ran out of block numbers.
+    fn send_acknowledged(data: &[u8], rollover: Option<Rollover>) -> (usize, [u16; 5], bool) {
+        let mut source = data;
+        let mut sender = Sender::new(options(8, 1), RetransmitPolicy::default());
+        sender.set_rollover(rollover);
+        let mut buffer = [0u8; 516];
+        sender
+            .poll_transmit(Duration::ZERO, &mut source, &mut buffer)
+            .unwrap();
+        sender.handle_packet(&[0, 4, 0, 0]).unwrap();
+        let (mut sent, mut last) = (0, [0u16; 5]);
+        while !sender.is_finished() {
+            sender
+                .poll_transmit(Duration::ZERO, &mut source, &mut buffer)
+                .unwrap();
+            sent += 1;
+            last.rotate_left(1);
+            last[4] = u16::from_be_bytes([buffer[2], buffer[3]]);
+            let ack = [0, 4, buffer[2], buffer[3]];
+            let acked = sender.handle_packet(&ack);
+            if acked.is_err() {
+                assert!(matches!(acked, Err(Stop::TooManyBlocks)));
+                let n_bytes = sender
+                    .poll_transmit(Duration::ZERO, &mut source, &mut buffer)
+                    .unwrap()
+                    .unwrap();
+                assert!(matches!(
+                    Packet::from_bytes(&buffer[..n_bytes]),
+                    Ok(Packet::Error(_))
+                ));
+                return (sent, last, true);
+            }
+        }
+        (sent, last, false)
+    }
+
+    #[test]
+    fn sender_rollover() {
+        static DATA: [u8; 8 * 65537 + 1] = [7; 8 * 65537 + 1];
+        let (sent, last, too_many_blocks) = send_acknowledged(&DATA, Some(Rollover::Zero));
+        assert!(!too_many_blocks);
+        assert_eq!((sent, last), (65538, [65534, 65535, 0, 1, 2]));
+
+        let (sent, last, too_many_blocks) = send_acknowledged(&DATA, Some(Rollover::One));
+        assert!(!too_many_blocks);
+        assert_eq!((sent, last), (65538, [65534, 65535, 1, 2, 3]));
+
+        let (sent, last, too_many_blocks) = send_acknowledged(&DATA, None);
+        assert!(too_many_blocks);
+        assert_eq!((sent, last[4]), (65535, 65535));
+
+        // a file that fits into 65535 blocks is fine without rollover
+        let (sent, last, too_many_blocks) = send_acknowledged(&DATA[..8 * 65535 - 1], None);
+        assert!(!too_many_blocks);
+        assert_eq!((sent, last[4]), (65535, 65535));
+    }
+
+    #[test]
+    fn receiver_rollover() {
+        let mut buffer = [0u8; 516];
+        for (rollover, next) in [(Some(Rollover::Zero), 0), (Some(Rollover::One), 1)] {
+            let mut receiver = Receiver::acknowledging(
+                Settings::from_options(
+                    &OptionAck::new(None, None, None),
+                    RetransmitPolicy::default(),
+                ),
+                65535,
+            );
+            receiver.set_rollover(rollover);
+            let block = [0, 3, 0, next, 42];
+            assert_eq!(
+                receiver.handle_packet(Duration::ZERO, &block).unwrap(),
+                Some(&[42][..])
+            );
+            receiver.poll_transmit(Duration::ZERO, &mut buffer);
+            assert_eq!(&buffer[..4], &[0, 4, 0, next]);
+        }
+
+        let mut receiver = Receiver::acknowledging(
+            Settings::from_options(
+                &OptionAck::new(None, None, None),
+                RetransmitPolicy::default(),
+            ),
+            65535,
+        );
+        receiver.set_rollover(None);
+        receiver.poll_transmit(Duration::ZERO, &mut buffer);
+        // the last block being sent again is fine, it is acknowledged again
+        assert_eq!(
+            receiver
+                .handle_packet(Duration::ZERO, &[0, 3, 255, 255])
+                .unwrap(),
+            None
+        );
+        assert_eq!(receiver.poll_transmit(Duration::ZERO, &mut buffer), Some(4));
+        assert!(matches!(
+            receiver.handle_packet(Duration::ZERO, &[0, 3, 0, 0, 42]),
+            Err(Stop::TooManyBlocks)
+        ));
+        let n_bytes = receiver.poll_transmit(Duration::ZERO, &mut buffer).unwrap();
+        assert!(matches!(
+            Packet::from_bytes(&buffer[..n_bytes]),
+            Ok(Packet::Error(_))
+        ));
+    }
+
+    #[test]
+    fn receiver() {
+        let mut receiver = Receiver::new(
+            OptionAck::new(None, None, None),
+            RetransmitPolicy::default(),
+        );
+        let mut buffer = [0u8; 516];
+        assert_eq!(receiver.poll_transmit(Duration::ZERO, &mut buffer), Some(4));
+        assert_eq!(&buffer[..4], &[0, 4, 0, 0]);
+        assert_eq!(receiver.poll_transmit(Duration::ZERO, &mut buffer), None);
+
+        let mut block = [0u8; 516];
+        block[..4].copy_from_slice(&[0, 3, 0, 1]);
+        assert_eq!(
+            receiver.handle_packet(SECOND / 2, &block).unwrap(),
+            Some(&block[4..])
+        );
+        assert_eq!(receiver.poll_transmit(SECOND / 2, &mut buffer), Some(4));
+        assert_eq!(&buffer[..4], &[0, 4, 0, 1]);
+        assert_eq!(receiver.deadline(), Some(SECOND * 3 / 2));
+
+        // a duplicate is acknowledged again but not delivered
+        assert_eq!(receiver.handle_packet(SECOND, &block).unwrap(), None);
+        assert_eq!(receiver.poll_transmit(SECOND, &mut buffer), Some(4));
+        assert_eq!(&buffer[..4], &[0, 4, 0, 1]);
+
+        assert_eq!(
+            receiver.handle_packet(SECOND, &[0, 3, 0, 2, 42]).unwrap(),
+            Some(&[42][..])
+        );
+        assert!(receiver.is_complete() && !receiver.is_finished());
+        assert_eq!(receiver.poll_transmit(SECOND, &mut buffer), Some(4));
+        assert_eq!(&buffer[..4], &[0, 4, 0, 2]);
+        assert!(receiver.is_finished());
+        assert_eq!(receiver.bytes_received(), 513);
+    }
+
+    // the error packet `poll_transmit` wrote into `buffer`.
+    fn error_code(buffer: &[u8], n_bytes: Option<usize>) -> Option<ErrorCode> {
+        match Packet::from_bytes(&buffer[..n_bytes?]) {
+            Ok(Packet::Error(error)) => Some(error.error_code),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn protocol_violations() {
+        let mut buffer = [0u8; 516];
+        let mut source = &[1u8, 2, 3][..];
+        for (packet, illegal) in [
+            (&[0, 3, 0, 1, 42][..], true),
+            (&[0, 9, 0, 0][..], true),
+            (&[0, 5, 0, 0, b'n', b'o', 0][..], false),
+        ] {
+            let mut sender = Sender::new(
+                OptionAck::new(None, None, None),
+                RetransmitPolicy::default(),
+            );
+            sender
+                .poll_transmit(Duration::ZERO, &mut source, &mut buffer)
+                .unwrap();
+            let stop = sender.handle_packet(packet).unwrap_err();
+            assert_eq!(!matches!(stop, Stop::Peer(_)), illegal);
+            // the peer is told it broke the protocol, but not sent an error in reply to its own
+            let n_bytes = sender
+                .poll_transmit(Duration::ZERO, &mut source, &mut buffer)
+                .unwrap();
+            assert_eq!(
+                error_code(&buffer, n_bytes),
+                illegal.then_some(ErrorCode::ILLEGAL_TFTP_OPERATION)
+            );
+            assert_eq!(
+                sender.poll_transmit(SECOND, &mut source, &mut buffer),
+                Ok(None)
+            );
+
+            let mut receiver = Receiver::new(
+                OptionAck::new(None, None, None),
+                RetransmitPolicy::default(),
+            );
+            let packet = if packet[1] == 3 {
+                &[0, 4, 0, 1][..]
+            } else {
+                packet
+            };
+            let stop = receiver.handle_packet(Duration::ZERO, packet).unwrap_err();
+            assert_eq!(!matches!(stop, Stop::Peer(_)), illegal);
+            // the ack of the request was never sent, and isn't once the transfer stopped
+            let n_bytes = receiver.poll_transmit(Duration::ZERO, &mut buffer);
+            assert_eq!(
+                error_code(&buffer, n_bytes),
+                illegal.then_some(ErrorCode::ILLEGAL_TFTP_OPERATION)
+            );
+            assert_eq!(receiver.poll_transmit(SECOND, &mut buffer), None);
+        }
+    }
+}
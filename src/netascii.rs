@@ -0,0 +1,308 @@
+use crate::{packet::Mode, transport::DataSink};
+use std::io::{Error as IoError, Read, Result as IoResult, Write};
+
+/// Wraps a source of local text and translates it to netascii while reading.
+///
+/// Every `\n` is turned into `\r\n` and every `\r` into `\r\0`, as required by [RFC-1350](https://www.rfc-editor.org/rfc/inline-errata/rfc1350.html).
+/// The source is assumed to use unix line endings.
+/// Pass it to [`Server::create_transfer_to`](crate::server::Server::create_transfer_to) to answer a read request in [`Mode::NetAscii`].
+pub struct NetAsciiReader<R: Read> {
+    inner: R,
+    /// the second byte of a translated character that didn't fit in the previous read.
+    pending: Option<u8>,
+}
+
+impl<R: Read> NetAsciiReader<R> {
+    /// creates a reader that translates the text read from `inner` to netascii.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            pending: None,
+        }
+    }
+
+    /// returns the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for NetAsciiReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if let Some(byte) = self.pending.take() {
+            buf[0] = byte;
+            return Ok(1);
+        }
+        // every byte translates to at most two bytes, so reading half of what fits in `buf`
+        // overflows by at most a single byte, which is kept for the next read.
+        let mut raw = [0u8; 512];
+        let to_read = buf.len().div_ceil(2).min(raw.len());
+        let n_bytes = self.inner.read(&mut raw[..to_read])?;
+        let mut written = 0;
+        for &byte in &raw[..n_bytes] {
+            let (first, second) = match byte {
+                b'\n' => (b'\r', Some(b'\n')),
+                b'\r' => (b'\r', Some(0)),
+                byte => (byte, None),
+            };
+            buf[written] = first;
+            written += 1;
+            if let Some(second) = second {
+                if written < buf.len() {
+                    buf[written] = second;
+                    written += 1;
+                } else {
+                    self.pending = Some(second);
+                }
+            }
+        }
+        Ok(written)
+    }
+}
+
+/// Wraps a sink for local text and translates the netascii written to it.
+///
+/// Every `\r\n` is turned into `\n` and every `\r\0` into `\r`, as required by [RFC-1350](https://www.rfc-editor.org/rfc/inline-errata/rfc1350.html).
+/// A `\r` followed by anything else is passed on as is.
+/// Valid netascii never ends in a bare `\r`, if it does anyway that `\r` is only written out by [`finish`](NetAsciiWriter::finish).
+/// Pass it to [`Server::create_transfer_from`](crate::server::Server::create_transfer_from) to answer a write request in [`Mode::NetAscii`].
+pub struct NetAsciiWriter<W: Write> {
+    inner: W,
+    /// set if the last byte written was a `\r`, whose meaning depends on the next byte.
+    pending_cr: bool,
+}
+
+impl<W: Write> NetAsciiWriter<W> {
+    /// creates a writer that translates netascii to local text before writing it to `inner`.
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            pending_cr: false,
+        }
+    }
+
+    /// writes out a trailing `\r`, if the data ended with one, and returns the wrapped writer.
+    pub fn finish(mut self) -> IoResult<W> {
+        self.write_pending()?;
+        Ok(self.inner)
+    }
+
+    // writes out a `\r` that is still waiting for the next byte, for when no more bytes will follow.
+    fn write_pending(&mut self) -> IoResult<()> {
+        if std::mem::take(&mut self.pending_cr) {
+            self.inner.write_all(b"\r")?;
+        }
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for NetAsciiWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let mut translated = Vec::with_capacity(buf.len() + 1);
+        for &byte in buf {
+            if self.pending_cr {
+                self.pending_cr = false;
+                match byte {
+                    b'\n' => {
+                        translated.push(b'\n');
+                        continue;
+                    }
+                    0 => {
+                        translated.push(b'\r');
+                        continue;
+                    }
+                    _ => translated.push(b'\r'),
+                }
+            }
+            if byte == b'\r' {
+                self.pending_cr = true;
+            } else {
+                translated.push(byte);
+            }
+        }
+        self.inner.write_all(&translated)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader that translates to netascii or passes the data on unchanged, depending on the [`Mode`] of the transfer.
+pub enum ModeReader<R: Read> {
+    /// passes the data on unchanged.
+    Octet(R),
+    /// translates the data to netascii.
+    NetAscii(NetAsciiReader<R>),
+}
+
+impl<R: Read> ModeReader<R> {
+    /// wraps `inner` as needed for a transfer in `mode`.
+    pub fn new(inner: R, mode: Mode) -> Self {
+        match mode {
+            Mode::Octet => Self::Octet(inner),
+            Mode::NetAscii => Self::NetAscii(NetAsciiReader::new(inner)),
+        }
+    }
+}
+
+impl<R: Read> Read for ModeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        match self {
+            Self::Octet(inner) => inner.read(buf),
+            Self::NetAscii(inner) => inner.read(buf),
+        }
+    }
+}
+
+/// A writer that translates from netascii or passes the data on unchanged, depending on the [`Mode`] of the transfer.
+pub enum ModeWriter<W: Write> {
+    /// passes the data on unchanged.
+    Octet(W),
+    /// translates the data from netascii.
+    NetAscii(NetAsciiWriter<W>),
+}
+
+impl<W: Write> ModeWriter<W> {
+    /// wraps `inner` as needed for a transfer in `mode`.
+    pub fn new(inner: W, mode: Mode) -> Self {
+        match mode {
+            Mode::Octet => Self::Octet(inner),
+            Mode::NetAscii => Self::NetAscii(NetAsciiWriter::new(inner)),
+        }
+    }
+
+    /// writes out what is left of the data, see [`NetAsciiWriter::finish`], and returns the wrapped writer.
+    /// Call this once the transfer is complete, or a trailing `\r` is lost.
+    pub fn finish(self) -> IoResult<W> {
+        match self {
+            Self::Octet(mut inner) => inner.flush().map(|()| inner),
+            Self::NetAscii(inner) => inner.finish(),
+        }
+    }
+}
+
+/// Writes the data of an incoming transfer, and what is left of it once the transfer is complete.
+impl<W: Write> DataSink for ModeWriter<W> {
+    type Error = IoError;
+
+    fn write(&mut self, data: &[u8]) -> IoResult<()> {
+        self.write_all(data)
+    }
+
+    fn finish(&mut self) -> IoResult<()> {
+        match self {
+            Self::Octet(inner) => inner.flush(),
+            Self::NetAscii(inner) => inner.write_pending(),
+        }
+    }
+}
+
+impl<W: Write> Write for ModeWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        match self {
+            Self::Octet(inner) => inner.write(buf),
+            Self::NetAscii(inner) => inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        match self {
+            Self::Octet(inner) => inner.flush(),
+            Self::NetAscii(inner) => inner.flush(),
+        }
+    }
+}
+
+/// returns the size of the data read from `source` once translated to netascii.
+/// Use this to fill in the tsize option of a netascii transfer, as described in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html).
+pub fn translated_len<R: Read>(mut source: R) -> IoResult<u64> {
+    let mut buffer = [0u8; 4096];
+    let mut size = 0;
+    loop {
+        let n_bytes = match source.read(&mut buffer) {
+            Ok(0) => return Ok(size),
+            Ok(n_bytes) => n_bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        size += n_bytes as u64;
+        size += buffer[..n_bytes]
+            .iter()
+            .filter(|&&byte| byte == b'\n' || byte == b'\r')
+            .count() as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL: &[u8] = b"line one\nline\rtwo\n\n\r";
+    const NETASCII: &[u8] = b"line one\r\nline\r\0two\r\n\r\n\r\0";
+
+    #[test]
+    fn reader() {
+        let mut translated = Vec::new();
+        NetAsciiReader::new(LOCAL)
+            .read_to_end(&mut translated)
+            .unwrap();
+        assert_eq!(translated, NETASCII);
+        assert_eq!(translated_len(LOCAL).unwrap(), NETASCII.len() as u64);
+    }
+
+    #[test]
+    fn reader_small_buffers() {
+        for size in 1..8 {
+            let mut reader = NetAsciiReader::new(LOCAL);
+            let mut translated = Vec::new();
+            let mut buffer = vec![0u8; size];
+            loop {
+                let n = reader.read(&mut buffer).unwrap();
+                if n == 0 {
+                    break;
+                }
+                translated.extend_from_slice(&buffer[..n]);
+            }
+            assert_eq!(translated, NETASCII, "buffer size {size}");
+        }
+    }
+
+    #[test]
+    fn writer() {
+        // split the input at every possible point, so that `\r` ends up at the end of a write
+        for split in 0..NETASCII.len() {
+            let mut writer = NetAsciiWriter::new(Vec::new());
+            writer.write_all(&NETASCII[..split]).unwrap();
+            writer.write_all(&NETASCII[split..]).unwrap();
+            assert_eq!(writer.finish().unwrap(), LOCAL, "split at {split}");
+        }
+    }
+
+    #[test]
+    fn writer_lone_carriage_return() {
+        let mut writer = NetAsciiWriter::new(Vec::new());
+        writer.write_all(b"a\rb\r").unwrap();
+        assert_eq!(writer.finish().unwrap(), b"a\rb\r");
+    }
+
+    #[test]
+    fn mode_writer_finish() {
+        let mut writer = ModeWriter::new(Vec::new(), Mode::NetAscii);
+        writer.write_all(b"a\r\nb\r").unwrap();
+        assert_eq!(writer.finish().unwrap(), b"a\nb\r");
+
+        // as a data sink the trailing `\r` is written once the transfer is complete
+        let mut sink = ModeWriter::new(Vec::new(), Mode::NetAscii);
+        DataSink::write(&mut sink, b"a\r").unwrap();
+        DataSink::finish(&mut sink).unwrap();
+        let ModeWriter::NetAscii(writer) = sink else {
+            unreachable!()
+        };
+        assert_eq!(writer.finish().unwrap(), b"a\r");
+    }
+}
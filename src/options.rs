@@ -0,0 +1,277 @@
+#[cfg(feature = "alloc")]
+use crate::error::Error as TftpError;
+use crate::{
+    error::Result as TftpResult,
+    packet::{OptionAck, Request},
+};
+use core::{fmt::Display, num::NonZeroU16};
+
+/// The options this crate understands. They have their own fields in [`Request`] and [`OptionAck`],
+/// any other option is available through [`Request::unknown_options`] and [`Request::option`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnownOption {
+    /// `blksize`, defined in [RFC-2348](https://www.rfc-editor.org/rfc/rfc2348.html).
+    Blocksize,
+    /// `tsize`, defined in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html).
+    TransferSize,
+    /// `timeout`, defined in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html).
+    Timeout,
+    /// `windowsize`, defined in [RFC-7440](https://www.rfc-editor.org/rfc/rfc7440.html).
+    WindowSize,
+    /// `multicast`, defined in [RFC-2090](https://www.rfc-editor.org/rfc/rfc2090.html).
+    Multicast,
+    /// `rollover`, see [`Rollover`](crate::packet::Rollover).
+    Rollover,
+}
+
+impl KnownOption {
+    /// every option this crate understands.
+    pub const ALL: [Self; 6] = [
+        Self::Blocksize,
+        Self::TransferSize,
+        Self::Timeout,
+        Self::WindowSize,
+        Self::Multicast,
+        Self::Rollover,
+    ];
+
+    /// returns the name of the option as used in a packet.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Blocksize => "blksize",
+            Self::TransferSize => "tsize",
+            Self::Timeout => "timeout",
+            Self::WindowSize => "windowsize",
+            Self::Multicast => "multicast",
+            Self::Rollover => "rollover",
+        }
+    }
+
+    /// returns the option called `name`, if this crate understands it. Option names are case-insensitive.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|option| option.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// An option this crate doesn't know about, like a vendor specific extension.
+///
+/// Implementing this is all it takes to read the option from a packet with [`Request::option`] or [`OptionAck::option`],
+/// and to add it to one with `set_option` (with the `alloc` feature).
+/// The value is written using its [`Display`] implementation, which has to produce something [`parse`](CustomOption::parse) accepts.
+pub trait CustomOption: Sized + Display {
+    /// the name of the option. Options are matched case-insensitively.
+    const NAME: &'static str;
+
+    /// parses the value of the option as send in a packet.
+    fn parse(value: &str) -> TftpResult<Self>;
+}
+
+// finds the custom option `O` among the unknown options of a packet.
+pub(crate) fn find<'a, O: CustomOption>(
+    mut options: impl Iterator<Item = TftpResult<(&'a str, &'a str)>>,
+) -> Option<TftpResult<O>> {
+    options.find_map(|option| match option {
+        Ok((name, value)) if name.eq_ignore_ascii_case(O::NAME) => Some(O::parse(value)),
+        Ok(_) => None,
+        Err(e) => Some(Err(e)),
+    })
+}
+
+// appends the option `name` with `value` to `options`, as it would appear in a packet.
+// `existing` are the unknown options the packet has already.
+#[cfg(feature = "alloc")]
+pub(crate) fn push<'a>(
+    options: &mut alloc::vec::Vec<u8>,
+    mut existing: impl Iterator<Item = TftpResult<(&'a str, &'a str)>>,
+    name: &str,
+    value: &str,
+) -> TftpResult<()> {
+    let printable = |s: &str| s.bytes().all(|byte| (32..=127).contains(&byte));
+    if name.is_empty()
+        || !printable(name)
+        || !printable(value)
+        || KnownOption::from_name(name).is_some()
+    {
+        return Err(TftpError::BadFormatting);
+    }
+    if existing.any(|option| option.is_ok_and(|(existing, _)| existing.eq_ignore_ascii_case(name)))
+    {
+        return Err(TftpError::OptionRepeated);
+    }
+    options.extend_from_slice(name.as_bytes());
+    options.push(0);
+    options.extend_from_slice(value.as_bytes());
+    options.push(0);
+    Ok(())
+}
+
+/// Decides which of the options a client requests a server acknowledges, see [`negotiate`](OptionPolicy::negotiate).
+///
+/// The default acknowledges every option this crate can use with any value the RFCs allow, except for multicast,
+/// which needs a [multicast transfer](crate::server::Server::create_multicast_transfer).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptionPolicy {
+    /// requests for a smaller blocksize are answered without the blocksize option, so the transfer uses 512 byte blocks.
+    pub min_blocksize: u16,
+    /// requests for a larger blocksize are acknowledged with this one instead.
+    pub max_blocksize: u16,
+    /// requested timeouts outside of `min_timeout..=max_timeout` seconds aren't acknowledged,
+    /// as [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html) doesn't allow the server to pick another one.
+    pub min_timeout: u8,
+    /// see [`min_timeout`](OptionPolicy::min_timeout).
+    pub max_timeout: u8,
+    /// requests for a larger window are acknowledged with this one instead. With `None` the windowsize option isn't acknowledged.
+    pub max_window_size: Option<NonZeroU16>,
+    /// whether the size of the file is send to clients that ask for it, and the size announced with a write request is acknowledged.
+    pub transfer_size: bool,
+    /// whether the rollover a client asks for is acknowledged.
+    pub rollover: bool,
+}
+
+impl Default for OptionPolicy {
+    fn default() -> Self {
+        Self {
+            min_blocksize: 8,
+            max_blocksize: 65464,
+            min_timeout: 1,
+            max_timeout: 255,
+            max_window_size: NonZeroU16::new(u16::MAX),
+            transfer_size: true,
+            rollover: true,
+        }
+    }
+}
+
+impl OptionPolicy {
+    /// builds the option acknowledgement answering `request`, acknowledging the options this policy allows.
+    ///
+    /// `transfer_size` is the size of the requested file, which is acknowledged if the client asked for it on a read request.
+    /// On write requests the size announced by the client is acknowledged instead.
+    /// Multicast and any options this crate doesn't know about are never acknowledged, add custom options to the result yourself.
+    /// If the result [is empty](OptionAck::is_empty), the request should be answered as if it had no options at all.
+    pub fn negotiate(&self, request: &Request, transfer_size: Option<u64>) -> OptionAck<'static> {
+        let blocksize = request
+            .blocksize
+            .filter(|&blocksize| blocksize >= self.min_blocksize)
+            .map(|blocksize| blocksize.min(self.max_blocksize));
+        let timeout = request
+            .timeout_seconds
+            .filter(|timeout| (self.min_timeout..=self.max_timeout).contains(&timeout.get()));
+        let transfer_size = if request.is_read() {
+            transfer_size.filter(|_| request.transfer_size.is_some())
+        } else {
+            request.transfer_size
+        }
+        .filter(|_| self.transfer_size);
+        let mut options = OptionAck::new(blocksize, transfer_size, timeout);
+        options.window_size = request
+            .window_size
+            .zip(self.max_window_size)
+            .map(|(requested, max)| requested.min(max));
+        options.rollover = request.rollover.filter(|_| self.rollover);
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::num::NonZeroU8;
+
+    #[test]
+    fn negotiate() {
+        let mut request = Request::new_read_request("file", Some(1468));
+        request.timeout_seconds = NonZeroU8::new(5);
+        request.window_size = NonZeroU16::new(64);
+        request.transfer_size = Some(0);
+        request.multicast = true;
+
+        let options = OptionPolicy::default().negotiate(&request, Some(1000));
+        assert_eq!(options.blocksize, Some(1468));
+        assert_eq!(options.timeout_seconds, NonZeroU8::new(5));
+        assert_eq!(options.window_size, NonZeroU16::new(64));
+        assert_eq!(options.transfer_size, Some(1000));
+        assert_eq!(options.multicast, None);
+
+        let policy = OptionPolicy {
+            max_blocksize: 1024,
+            min_timeout: 10,
+            max_window_size: NonZeroU16::new(16),
+            transfer_size: false,
+            ..Default::default()
+        };
+        let options = policy.negotiate(&request, Some(1000));
+        assert_eq!(options.blocksize, Some(1024));
+        assert_eq!(options.timeout_seconds, None);
+        assert_eq!(options.window_size, NonZeroU16::new(16));
+        assert_eq!(options.transfer_size, None);
+
+        let policy = OptionPolicy {
+            min_blocksize: 2048,
+            min_timeout: 10,
+            max_window_size: None,
+            transfer_size: false,
+            ..Default::default()
+        };
+        assert!(policy.negotiate(&request, Some(1000)).is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn custom_options() {
+        use crate::packet::Packet;
+
+        struct Checksum(u32);
+
+        impl Display for Checksum {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                write!(f, "{:08x}", self.0)
+            }
+        }
+
+        impl CustomOption for Checksum {
+            const NAME: &'static str = "x-checksum";
+
+            fn parse(value: &str) -> TftpResult<Self> {
+                u32::from_str_radix(value, 16)
+                    .map(Self)
+                    .map_err(|_| TftpError::BadFormatting)
+            }
+        }
+
+        let mut request = Request::new_write_request("file", Some(1024));
+        request.set_option(&Checksum(0xc0ffee)).unwrap();
+        request.push_option("X-Vendor", "acme").unwrap();
+        assert!(matches!(
+            request.push_option("x-vendor", "other"),
+            Err(TftpError::OptionRepeated)
+        ));
+        assert!(matches!(
+            request.push_option("BlkSize", "512"),
+            Err(TftpError::BadFormatting)
+        ));
+
+        let mut buffer = [0u8; 128];
+        let n_bytes = request.to_bytes(&mut buffer).unwrap();
+        let Ok(Packet::Request(request)) = Packet::from_bytes(&buffer[..n_bytes]) else {
+            panic!("expected a request");
+        };
+        assert_eq!(request.blocksize, Some(1024));
+        assert!(matches!(request.option(), Some(Ok(Checksum(0xc0ffee)))));
+        assert!(request
+            .unknown_options()
+            .map(Result::unwrap)
+            .eq([("x-checksum", "00c0ffee"), ("X-Vendor", "acme")]));
+
+        let mut options = OptionPolicy::default().negotiate(&request, None);
+        options.set_option(&Checksum(1)).unwrap();
+        let n_bytes = Packet::OptionAck(options).to_bytes(&mut buffer).unwrap();
+        let Ok(Packet::OptionAck(options)) = Packet::from_bytes(&buffer[..n_bytes]) else {
+            panic!("expected an option acknowledgement");
+        };
+        assert_eq!(options.blocksize, Some(1024));
+        assert!(matches!(options.option(), Some(Ok(Checksum(1)))));
+    }
+}
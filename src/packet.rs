@@ -1,5 +1,22 @@
-use crate::error::{Error as TftpError, Result as TftpResult};
-use core::{fmt::Write, num::NonZeroU8};
+mod builder;
+#[cfg(feature = "alloc")]
+mod owned;
+
+use crate::{
+    error::{Error as TftpError, Result as TftpResult},
+    options::{self, CustomOption, KnownOption},
+};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+pub use builder::RequestBuilder;
+use core::{
+    fmt::Write,
+    net::{IpAddr, SocketAddr},
+    num::{NonZeroU16, NonZeroU8},
+};
+#[cfg(feature = "alloc")]
+#[doc(cfg(feature = "alloc"))]
+pub use owned::{OwnedData, OwnedError, OwnedOptionAck, OwnedPacket, OwnedRequest};
 
 struct BufferWriter<'a> {
     buff: &'a mut [u8],
@@ -52,7 +69,7 @@ impl<'a> core::fmt::Write for BufferWriter<'a> {
 }
 
 /// The 16 bit opcodes used for TFTP packets as defined in [RFC-1350](https://www.rfc-editor.org/rfc/inline-errata/rfc1350.html) section 5 and [RFC-2347](https://www.rfc-editor.org/rfc/inline-errata/rfc2347.html).
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 #[repr(u16)]
 #[allow(missing_docs)]
 pub enum OpCode {
@@ -104,6 +121,9 @@ impl ErrorCode {
     pub const FILE_ALREADY_EXISTS: Self = Self(6);
     /// No such user.
     pub const NO_SUCH_USER: Self = Self(7);
+    /// Option negotiation failed, as defined in [RFC-2347](https://www.rfc-editor.org/rfc/inline-errata/rfc2347.html).
+    /// Send by the client when it doesn't agree with the options acknowledged by the server, or by the server when it refuses a request because of its options.
+    pub const OPTION_NEGOTIATION_FAILED: Self = Self(8);
     fn possibly_invalid(code: u16) -> Self {
         Self(code)
     }
@@ -122,38 +142,69 @@ impl core::fmt::Display for ErrorCode {
             Self::UNKNOWN_TRANSFER_ID => f.write_str("Unknown transfer ID"),
             Self::FILE_ALREADY_EXISTS => f.write_str("File already exists"),
             Self::NO_SUCH_USER => f.write_str("No such user"),
+            Self::OPTION_NEGOTIATION_FAILED => f.write_str("Option negotiation failed"),
             _ => f.write_fmt(format_args!("Undefined Error Code({})", self.0)),
         }
     }
 }
 
+/// The transfer modes defined in [RFC-1350](https://www.rfc-editor.org/rfc/inline-errata/rfc1350.html). The obsolete `mail` mode is not supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// The data is transferred as is, as raw 8 bit bytes.
+    #[default]
+    Octet,
+    /// The data is text using `\r\n` as line endings and `\r\0` for a carriage return on its own.
+    /// Translating it to and from the local format is up to the end-points, see the `netascii` module (with the `std` feature) for helpers.
+    NetAscii,
+}
+
+impl Mode {
+    /// returns the name of this mode as used in a request packet.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Octet => "octet",
+            Self::NetAscii => "netascii",
+        }
+    }
+}
+
 /// A read- or write-request packet.
-///
-/// Will always use the octer mode. netascii mode is not supported.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Request<'a> {
     is_read: bool,
     /// the requested filename. Should be in net-ascii according to the standard but we support utf-8.
     pub filename: &'a str,
-    //only the octet mode is supported so it isn't stored here
+    /// The mode of the transfer. Defaults to [`Mode::Octet`].
+    pub mode: Mode,
     /// The blocksize requested using the options extension defined in [RFC-2348](https://www.rfc-editor.org/rfc/rfc2348.html).
     pub blocksize: Option<u16>,
-    /// If set, the packet will send the size of the file should be to the server (on a write request) or request the file size from the server (on a read request) using the tsize option defined in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html)
-    pub include_transfer_size: bool,
-    /// unsupported, see [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html) for a definition
+    /// The tsize option defined in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html). Read requests send 0 to ask the server for the size of the file,
+    /// write requests send the size of the file they're about to transfer.
+    pub transfer_size: Option<u64>,
+    /// The timeout in seconds the client would like to use for retransmissions, using the timeout option defined in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html).
     pub timeout_seconds: Option<NonZeroU8>,
-    unknown_options: &'a [u8],
+    /// The amount of blocks the sender may send before waiting for an acknowledgement, using the windowsize option defined in [RFC-7440](https://www.rfc-editor.org/rfc/rfc7440.html).
+    pub window_size: Option<NonZeroU16>,
+    /// If set, the client asks to receive the file over multicast using the multicast option defined in [RFC-2090](https://www.rfc-editor.org/rfc/rfc2090.html).
+    /// Only valid on read requests.
+    pub multicast: bool,
+    /// What the block number continues with after block 65535, using the `rollover` option.
+    pub rollover: Option<Rollover>,
+    unknown_options: UnknownOptions<'a>,
 }
 
 /// A data package that borrows a slice of data
 #[derive(Debug)]
 pub struct Data<'a> {
-    block_nr: u16,
-    data: &'a [u8],
+    /// the number of this block. The first block of a transfer is numbered 1.
+    pub block_nr: u16,
+    /// the payload of this block. A block shorter than the negotiated blocksize signals the end of the transfer.
+    pub data: &'a [u8],
 }
 
 /// an acknowledge packet, send in response to a data packet
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Ack {
     /// the block_nr of the data packet being ack'ed.
     /// A write request is acked with a block_nr of 0.
@@ -174,10 +225,87 @@ pub struct Error<'a> {
     pub message: &'a str,
 }
 
+/// The value of the multicast option defined in [RFC-2090](https://www.rfc-editor.org/rfc/rfc2090.html), as send in an option acknowledgement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Multicast {
+    /// The multicast group (address and port) the data packets are send to.
+    /// May be left out when the client already knows it, for example when it is made the master client halfway through a transfer.
+    pub group: Option<SocketAddr>,
+    /// If set, the client receiving the option acknowledgement is the master client, and is the only one that acknowledges data packets.
+    pub master_client: bool,
+}
+
+impl Multicast {
+    fn parse(as_str: &str) -> TftpResult<Self> {
+        let mut fields = as_str.split(',');
+        let (Some(address), Some(port), Some(master_client), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(TftpError::BadFormatting);
+        };
+        let group = match (address, port) {
+            ("", "") => None,
+            (address, port) => match (address.parse::<IpAddr>(), port.parse()) {
+                (Ok(address), Ok(port)) => Some(SocketAddr::new(address, port)),
+                _ => return Err(TftpError::BadFormatting),
+            },
+        };
+        let master_client = match master_client {
+            "0" => false,
+            "1" => true,
+            _ => return Err(TftpError::BadFormatting),
+        };
+        Ok(Self {
+            group,
+            master_client,
+        })
+    }
+}
+
+/// What the block number of a transfer continues with after block 65535, negotiated with the `rollover` option.
+///
+/// The option isn't defined in any RFC, but many clients and servers understand it. Without it, most implementations roll over to block 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rollover {
+    /// block 65535 is followed by block 0.
+    #[default]
+    Zero,
+    /// block 65535 is followed by block 1, so that block 0 only ever acknowledges the request.
+    One,
+}
+
+impl Rollover {
+    /// returns the number of the block after `block_nr`.
+    pub fn next(self, block_nr: u16) -> u16 {
+        match (block_nr, self) {
+            (u16::MAX, Self::Zero) => 0,
+            (u16::MAX, Self::One) => 1,
+            (block_nr, _) => block_nr + 1,
+        }
+    }
+
+    fn parse(as_str: &str) -> TftpResult<Self> {
+        match as_str {
+            "0" => Ok(Self::Zero),
+            "1" => Ok(Self::One),
+            _ => Err(TftpError::BadFormatting),
+        }
+    }
+}
+
+impl core::fmt::Display for Rollover {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::Zero => "0",
+            Self::One => "1",
+        })
+    }
+}
+
 /// an option acknowledge packet
 ///
 /// These are send in response to a read or write request to confirm which optional extension to use for the transfer.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct OptionAck<'a> {
     /// Indicates acknowledgement of a specific blocksize requested using the options extension defined in [RFC-2348](https://www.rfc-editor.org/rfc/rfc2348.html) if present.
     pub blocksize: Option<u16>,
@@ -187,8 +315,15 @@ pub struct OptionAck<'a> {
     pub transfer_size: Option<u64>,
     /// If set, indicates acknowledgement of timeour option extension as defined in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html)
     pub timeout_seconds: Option<NonZeroU8>,
+    /// If set, indicates acknowledgement of the windowsize option extension as defined in [RFC-7440](https://www.rfc-editor.org/rfc/rfc7440.html).
+    /// The server may acknowledge a smaller window than the client requested, but never a larger one.
+    pub window_size: Option<NonZeroU16>,
+    /// If set, indicates acknowledgement of the multicast option extension as defined in [RFC-2090](https://www.rfc-editor.org/rfc/rfc2090.html).
+    pub multicast: Option<Multicast>,
+    /// If set, indicates acknowledgement of the `rollover` option, see [`Rollover`].
+    pub rollover: Option<Rollover>,
     /// options which aren't understood by this library
-    unknown_options: &'a [u8],
+    unknown_options: UnknownOptions<'a>,
 }
 
 /// an enum of all types of TFTP packet
@@ -274,7 +409,7 @@ impl<'a> Packet<'a> {
 
     /// write this packet into the buffer `data`. The buffer is allowed to be larger than the packet size.
     /// Will return [TftpError::BufferTooSmall] if the packet doesn't fit but might still mutate the buffer.
-    pub fn to_bytes(&self, data: &'a mut [u8]) -> Result<usize, TftpError> {
+    pub fn to_bytes(&self, data: &mut [u8]) -> Result<usize, TftpError> {
         match self {
             Self::Ack(x) => x.to_bytes(data),
             Self::Data(x) => x.to_bytes(data),
@@ -302,7 +437,7 @@ impl<'a> Data<'a> {
 
     /// write this packet into the buffer `data`. The buffer is allowed to be larger than the packet size.
     /// Will return [TftpError::BufferTooSmall] if the packet doesn't fit but might still mutate the buffer.
-    pub fn to_bytes(&self, buf: &'a mut [u8]) -> Result<usize, TftpError> {
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, TftpError> {
         let n_bytes = 4 + self.data.len();
         if n_bytes > buf.len() {
             return Err(TftpError::BufferTooSmall);
@@ -319,7 +454,7 @@ impl<'a> Data<'a> {
 // and this function will usually be called on data generated by a remote host, which may not be compliant itself
 // and instead send utf-8 or 'normal' ascii.
 fn printable_ascii_str_from_u8(data: &[u8]) -> TftpResult<(&str, &[u8])> {
-    let first_non_ascii = data.into_iter().position(|&n| n < 32 || n > 127);
+    let first_non_ascii = data.iter().position(|&n| !(32..=127).contains(&n));
     if let Some(index) = first_non_ascii {
         if data[index] == 0 {
             return Ok(unsafe {
@@ -333,8 +468,11 @@ fn printable_ascii_str_from_u8(data: &[u8]) -> TftpResult<(&str, &[u8])> {
     Err(TftpError::BadFormatting)
 }
 
-fn get_option_pair(data: &[u8]) -> TftpResult<Option<((&str, &str), &[u8])>> {
-    if data.len() == 0 {
+/// a name-value pair of an option, as defined in [RFC-2347](https://www.rfc-editor.org/rfc/inline-errata/rfc2347.html)
+type OptionPair<'a> = (&'a str, &'a str);
+
+fn get_option_pair(data: &[u8]) -> TftpResult<Option<(OptionPair<'_>, &[u8])>> {
+    if data.is_empty() {
         Ok(None)
     } else {
         let (name, data) = printable_ascii_str_from_u8(data)?;
@@ -348,13 +486,66 @@ fn parse_blocksize(as_str: &str) -> TftpResult<u16> {
         return Err(TftpError::BadFormatting);
     };
     //Valid values range between "8" and "65464" octets, inclusive.
-    if requested_blocksize < 8 || requested_blocksize > 65464 {
+    if !(8..=65464).contains(&requested_blocksize) {
         Err(TftpError::InvalidBlockSize(requested_blocksize))
     } else {
         Ok(requested_blocksize as u16)
     }
 }
 
+/// the options of a request or option acknowledgement that this crate understands, see [`KnownOption`].
+#[derive(Default)]
+struct ParsedOptions<'a> {
+    blocksize: Option<u16>,
+    transfer_size: Option<u64>,
+    timeout_seconds: Option<NonZeroU8>,
+    window_size: Option<NonZeroU16>,
+    /// the value is parsed differently in requests and option acknowledgements.
+    multicast: Option<&'a str>,
+    rollover: Option<Rollover>,
+    /// all of the options, if there are any this crate doesn't understand among them.
+    unknown_options: &'a [u8],
+}
+
+impl<'a> ParsedOptions<'a> {
+    fn parse(data: &'a [u8]) -> TftpResult<Self> {
+        fn set_once<T>(option: &mut Option<T>, value: TftpResult<T>) -> TftpResult<()> {
+            if option.is_some() {
+                return Err(TftpError::OptionRepeated);
+            }
+            *option = Some(value?);
+            Ok(())
+        }
+        fn number<T: core::str::FromStr>(value: &str) -> TftpResult<T> {
+            value.parse().map_err(|_| TftpError::BadFormatting)
+        }
+
+        let mut options = Self::default();
+        let mut remaining = data;
+        while let Some(((name, value), remainder)) = get_option_pair(remaining)? {
+            match KnownOption::from_name(name) {
+                Some(KnownOption::Blocksize) => {
+                    set_once(&mut options.blocksize, parse_blocksize(value))?
+                }
+                Some(KnownOption::TransferSize) => {
+                    set_once(&mut options.transfer_size, number(value))?
+                }
+                Some(KnownOption::Timeout) => {
+                    set_once(&mut options.timeout_seconds, number(value))?
+                }
+                Some(KnownOption::WindowSize) => set_once(&mut options.window_size, number(value))?,
+                Some(KnownOption::Multicast) => set_once(&mut options.multicast, Ok(value))?,
+                Some(KnownOption::Rollover) => {
+                    set_once(&mut options.rollover, Rollover::parse(value))?
+                }
+                None => options.unknown_options = data,
+            }
+            remaining = remainder;
+        }
+        Ok(options)
+    }
+}
+
 impl<'a> Request<'a> {
     /// creates a new read request packet for the given file, optionally request a specific blocksize using the blocksize option defined in [RFC-2347](https://www.rfc-editor.org/rfc/inline-errata/rfc2347.html) and [RFC-2348](https://www.rfc-editor.org/rfc/rfc2348.html)
     pub fn new_read_request(filename: &'a str, blocksize: Option<u16>) -> Self {
@@ -371,60 +562,47 @@ impl<'a> Request<'a> {
         Self {
             is_read,
             filename,
-            include_transfer_size: false,
+            mode: Mode::Octet,
+            transfer_size: None,
             timeout_seconds: None,
+            window_size: None,
+            multicast: false,
+            rollover: None,
             blocksize,
-            unknown_options: &[],
+            unknown_options: UnknownOptions::default(),
         }
     }
 
     fn from_bytes_skip_opcode_check(data: &'a [u8], is_read: bool) -> TftpResult<Self> {
         let (filename, data) = printable_ascii_str_from_u8(&data[2..])?;
-        let (mode, mut options_data) = printable_ascii_str_from_u8(data)?;
-        let options_start = options_data;
-        let mut blocksize = None;
-        let mut include_transfer_size = false;
-        let mut timeout_seconds = None;
-        let mut has_unknown_options = false;
-        while let Some((option, remainder)) = get_option_pair(options_data)? {
-            if option.0.eq_ignore_ascii_case("blksize") {
-                if blocksize.is_some() {
-                    return Err(TftpError::OptionRepeated);
-                }
-                blocksize = Some(parse_blocksize(option.1)?)
-            } else if option.0.eq_ignore_ascii_case("tsize") {
-                if include_transfer_size {
-                    return Err(TftpError::OptionRepeated);
-                }
-                if option.1 != "0" {
-                    return Err(TftpError::BadFormatting);
-                }
-                include_transfer_size = true;
-            } else if option.0.eq_ignore_ascii_case("timeout") {
-                if timeout_seconds.is_some() {
-                    return Err(TftpError::OptionRepeated);
-                }
-                let Ok(timeout) = option.1.parse() else {
-                    return Err(TftpError::BadFormatting);
-                };
-                timeout_seconds = Some(timeout);
-            } else {
-                has_unknown_options = true;
-            }
-            options_data = remainder;
-        }
-        if !mode.eq_ignore_ascii_case("octet") {
+        let (mode, options_data) = printable_ascii_str_from_u8(data)?;
+        let options = ParsedOptions::parse(options_data)?;
+        // when reading, the client sends 0 and the server replies with the actual size.
+        if is_read && options.transfer_size.is_some_and(|size| size != 0) {
             return Err(TftpError::BadFormatting);
         }
+        // the client always sends an empty value, the server fills it in.
+        let multicast = match options.multicast {
+            None => false,
+            Some("") => true,
+            Some(_) => return Err(TftpError::BadFormatting),
+        };
+        let mode = if mode.eq_ignore_ascii_case("octet") {
+            Mode::Octet
+        } else if mode.eq_ignore_ascii_case("netascii") {
+            Mode::NetAscii
+        } else {
+            return Err(TftpError::BadFormatting);
+        };
         Ok(Self {
-            include_transfer_size,
-            timeout_seconds,
-            unknown_options: if has_unknown_options {
-                options_start
-            } else {
-                &[]
-            },
-            blocksize,
+            mode,
+            transfer_size: options.transfer_size,
+            timeout_seconds: options.timeout_seconds,
+            window_size: options.window_size,
+            multicast,
+            rollover: options.rollover,
+            unknown_options: UnknownOptions::parsed(options.unknown_options),
+            blocksize: options.blocksize,
             is_read,
             filename,
         })
@@ -449,20 +627,33 @@ impl<'a> Request<'a> {
 
     /// write this packet into the buffer `data`. The buffer is allowed to be larger than the packet size.
     /// Will return [TftpError::BufferTooSmall] if the packet doesn't fit but might still mutate the buffer.
-    pub fn to_bytes(&self, buf: &'a mut [u8]) -> Result<usize, TftpError> {
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, TftpError> {
         let mut write_target = BufferWriter::new(buf);
         write_target.push_bytes(&(self.opcode() as u16).to_be_bytes());
         write_target.push_bytes(self.filename.as_bytes());
         write_target.push_byte(0);
-        write_target.push_bytes(b"octets\0");
+        write_target.push_bytes(self.mode.as_str().as_bytes());
+        write_target.push_byte(0);
         if let Some(blocksize) = self.blocksize {
             let _ = write!(write_target, "blksize\0{blocksize}\0");
         }
         if let Some(timeout) = self.timeout_seconds {
             let _ = write!(write_target, "timeout\0{timeout}\0");
         }
-        if self.include_transfer_size {
-            write_target.push_bytes(b"tsize\00\0");
+        if let Some(window_size) = self.window_size {
+            let _ = write!(write_target, "windowsize\0{window_size}\0");
+        }
+        if let Some(transfer_size) = self.transfer_size {
+            let _ = write!(write_target, "tsize\0{transfer_size}\0");
+        }
+        if self.multicast {
+            write_target.push_bytes(b"multicast\0\0");
+        }
+        if let Some(rollover) = self.rollover {
+            let _ = write!(write_target, "rollover\0{rollover}\0");
+        }
+        for (name, value) in self.unknown_options.iter().flatten() {
+            let _ = write!(write_target, "{name}\0{value}\0");
         }
         if write_target.overflowed() {
             Err(TftpError::BufferTooSmall)
@@ -475,11 +666,29 @@ impl<'a> Request<'a> {
     /// This iterator returns a result over tuple pairs of option names and values. Will return an error if either of these is not a null-terminated ascii string.
     /// see [RFC-2347](https://www.rfc-editor.org/rfc/inline-errata/rfc2347.html) for a definition of options.
     pub fn unknown_options(&self) -> impl Iterator<Item = TftpResult<(&str, &str)>> {
-        OptionsIterator {
-            buff: self.unknown_options,
-            error: false,
-        }
-        .unknown()
+        self.unknown_options.iter()
+    }
+
+    /// returns the value of the custom option `O` if this packet contains it, see [`CustomOption`].
+    pub fn option<O: CustomOption>(&self) -> Option<TftpResult<O>> {
+        options::find(self.unknown_options())
+    }
+
+    /// adds the option `name` with value `value`, which this crate doesn't know about.
+    ///
+    /// Returns [`TftpError::BadFormatting`] if the name or value isn't printable ascii or the option is one of the [known options](KnownOption),
+    /// which have their own fields, and [`TftpError::OptionRepeated`] if the packet already contains the option.
+    #[cfg(feature = "alloc")]
+    #[doc(cfg(feature = "alloc"))]
+    pub fn push_option(&mut self, name: &str, value: &str) -> TftpResult<()> {
+        self.unknown_options.push(name, value)
+    }
+
+    /// adds the custom option `option`, like [`push_option`](Self::push_option) does.
+    #[cfg(feature = "alloc")]
+    #[doc(cfg(feature = "alloc"))]
+    pub fn set_option<O: CustomOption>(&mut self, option: &O) -> TftpResult<()> {
+        self.push_option(O::NAME, &alloc::string::ToString::to_string(option))
     }
 }
 
@@ -501,7 +710,7 @@ impl Ack {
     pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, TftpError> {
         let n_bytes = 4;
         if buf.len() >= n_bytes {
-            buf[0..2].copy_from_slice(&(OpCode::Error as u16).to_be_bytes());
+            buf[0..2].copy_from_slice(&(OpCode::Acknowledgement as u16).to_be_bytes());
             buf[2..4].copy_from_slice(&self.block_nr.to_be_bytes());
             Ok(n_bytes)
         } else {
@@ -529,87 +738,57 @@ impl<'a> Error<'a> {
         })
     }
     /// write this packet into the provided buffer. On success returns the amounts of bytes written. If the buffer is too small to hold the packet, returns an [TftpError::BufferTooSmall] error.
-    pub fn to_bytes(&self, buf: &'a mut [u8]) -> Result<usize, TftpError> {
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, TftpError> {
         let n_bytes = 4 + self.message.len() + 1;
         if n_bytes > buf.len() {
             return Err(TftpError::BufferTooSmall);
         }
         buf[0..2].copy_from_slice(&(OpCode::Error as u16).to_be_bytes());
         buf[2..4].copy_from_slice(&self.error_code.0.to_be_bytes());
-        buf[4..4 + self.message.bytes().len()].copy_from_slice(self.message.as_bytes());
-        buf[4 + self.message.bytes().len()] = 0;
+        buf[4..4 + self.message.len()].copy_from_slice(self.message.as_bytes());
+        buf[4 + self.message.len()] = 0;
         Ok(n_bytes)
     }
 }
 
 impl OptionAck<'static> {
     /// Creates an Option Ack packet, optionally including a blocksize as defined in [RFC-2348](https://datatracker.ietf.org/doc/html/rfc2348), transfer size([RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html)), or timeout ([RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html)).
+    /// The other options, like [`window_size`](OptionAck::window_size), can be set on the returned packet directly.
     pub fn new(
         blocksize: Option<u16>,
         transfer_size: Option<u64>,
         timeout_seconds: Option<NonZeroU8>,
     ) -> Self {
         //can't _construct_ an option ack with unknown fields because the server wouldn't know how to handle them.
-        // we don't support timeouts in the server either, so we don't construct those either.
         Self {
             blocksize,
             transfer_size,
             timeout_seconds,
-            unknown_options: &[],
+            window_size: None,
+            multicast: None,
+            rollover: None,
+            unknown_options: UnknownOptions::default(),
         }
     }
 }
 
 impl<'a> OptionAck<'a> {
     fn from_bytes_skip_opcode_check(data: &'a [u8]) -> TftpResult<Self> {
-        let mut data = &data[2..];
-        let mut blocksize = None;
-        let mut transfer_size = None;
-        let mut timeout_seconds = None;
-        let original_options = data;
-        let mut has_unknown_options = false;
-        while let Some((option, remainder)) = get_option_pair(data)? {
-            if option.0.eq_ignore_ascii_case("blksize") {
-                if blocksize.is_some() {
-                    return Err(TftpError::OptionRepeated);
-                }
-                blocksize = Some(parse_blocksize(option.1)?)
-            } else if option.0.eq_ignore_ascii_case("tsize") {
-                if transfer_size.is_some() {
-                    return Err(TftpError::OptionRepeated);
-                }
-                let Ok(transfer_size_val) = option.1.parse() else {
-                    return Err(TftpError::BadFormatting);
-                };
-                transfer_size = Some(transfer_size_val);
-            } else if option.0.eq_ignore_ascii_case("timeout") {
-                if timeout_seconds.is_some() {
-                    return Err(TftpError::OptionRepeated);
-                }
-                let Ok(timeout) = option.1.parse() else {
-                    return Err(TftpError::BadFormatting);
-                };
-                timeout_seconds = Some(timeout);
-            } else {
-                has_unknown_options = true;
-            }
-            data = remainder;
-        }
+        let options = ParsedOptions::parse(&data[2..])?;
         Ok(Self {
-            blocksize,
-            transfer_size,
-            timeout_seconds,
-            unknown_options: if has_unknown_options {
-                original_options
-            } else {
-                &[]
-            },
+            blocksize: options.blocksize,
+            transfer_size: options.transfer_size,
+            timeout_seconds: options.timeout_seconds,
+            window_size: options.window_size,
+            multicast: options.multicast.map(Multicast::parse).transpose()?,
+            rollover: options.rollover,
+            unknown_options: UnknownOptions::parsed(options.unknown_options),
         })
     }
 
     /// write this packet into the buffer `data`. The buffer is allowed to be larger than the packet size.
     /// Will return [TftpError::BufferTooSmall] if the packet doesn't fit but might still mutate the buffer.
-    pub fn to_bytes(&self, buf: &'a mut [u8]) -> Result<usize, TftpError> {
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, TftpError> {
         let mut write_target = BufferWriter::new(buf);
         write_target.push_bytes(&(OpCode::OptionAck as u16).to_be_bytes());
         if let Some(blocksize) = self.blocksize {
@@ -621,6 +800,24 @@ impl<'a> OptionAck<'a> {
         if let Some(timeout) = self.timeout_seconds {
             let _ = write!(write_target, "timeout\0{timeout}\0");
         }
+        if let Some(window_size) = self.window_size {
+            let _ = write!(write_target, "windowsize\0{window_size}\0");
+        }
+        if let Some(multicast) = self.multicast {
+            write_target.push_bytes(b"multicast\0");
+            if let Some(group) = multicast.group {
+                let _ = write!(write_target, "{},{}", group.ip(), group.port());
+            } else {
+                write_target.push_byte(b',');
+            }
+            let _ = write!(write_target, ",{}\0", multicast.master_client as u8);
+        }
+        if let Some(rollover) = self.rollover {
+            let _ = write!(write_target, "rollover\0{rollover}\0");
+        }
+        for (name, value) in self.unknown_options.iter().flatten() {
+            let _ = write!(write_target, "{name}\0{value}\0");
+        }
         if write_target.overflowed() {
             Err(TftpError::BufferTooSmall)
         } else {
@@ -633,6 +830,9 @@ impl<'a> OptionAck<'a> {
         self.blocksize.is_none()
             && self.timeout_seconds.is_none()
             && self.transfer_size.is_none()
+            && self.window_size.is_none()
+            && self.multicast.is_none()
+            && self.rollover.is_none()
             && self.unknown_options.is_empty()
     }
 
@@ -640,11 +840,80 @@ impl<'a> OptionAck<'a> {
     /// This iterator returns a result over tuple pairs of option names and values. Will return an error if either of these is not a null-terminated ascii string.
     /// see [RFC-2347](https://www.rfc-editor.org/rfc/inline-errata/rfc2347.html) for a definition of options.
     pub fn unknown_options(&self) -> impl Iterator<Item = TftpResult<(&str, &str)>> {
+        self.unknown_options.iter()
+    }
+
+    /// returns the value of the custom option `O` if this packet contains it, see [`CustomOption`].
+    pub fn option<O: CustomOption>(&self) -> Option<TftpResult<O>> {
+        options::find(self.unknown_options())
+    }
+
+    /// adds the option `name` with value `value`, which this crate doesn't know about.
+    ///
+    /// Returns [`TftpError::BadFormatting`] if the name or value isn't printable ascii or the option is one of the [known options](KnownOption),
+    /// which have their own fields, and [`TftpError::OptionRepeated`] if the packet already contains the option.
+    #[cfg(feature = "alloc")]
+    #[doc(cfg(feature = "alloc"))]
+    pub fn push_option(&mut self, name: &str, value: &str) -> TftpResult<()> {
+        self.unknown_options.push(name, value)
+    }
+
+    /// adds the custom option `option`, like [`push_option`](Self::push_option) does.
+    #[cfg(feature = "alloc")]
+    #[doc(cfg(feature = "alloc"))]
+    pub fn set_option<O: CustomOption>(&mut self, option: &O) -> TftpResult<()> {
+        self.push_option(O::NAME, &alloc::string::ToString::to_string(option))
+    }
+}
+
+/// the options of a packet that this crate doesn't know about.
+#[derive(Debug, Clone, Default)]
+struct UnknownOptions<'a> {
+    /// all options of the packet as received, if any of them are unknown.
+    parsed: &'a [u8],
+    /// options added to the packet since, in the format they're sent in.
+    #[cfg(feature = "alloc")]
+    added: Vec<u8>,
+}
+
+impl<'a> UnknownOptions<'a> {
+    fn parsed(parsed: &'a [u8]) -> Self {
+        Self {
+            parsed,
+            #[cfg(feature = "alloc")]
+            added: Vec::new(),
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = TftpResult<(&str, &str)>> {
         OptionsIterator {
-            buff: self.unknown_options,
+            buff: self.parsed,
             error: false,
         }
         .unknown()
+        .chain(OptionsIterator {
+            buff: self.added(),
+            error: false,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.parsed.is_empty() && self.added().is_empty()
+    }
+
+    fn added(&self) -> &[u8] {
+        #[cfg(feature = "alloc")]
+        return &self.added;
+        #[cfg(not(feature = "alloc"))]
+        return &[];
+    }
+
+    #[cfg(feature = "alloc")]
+    fn push(&mut self, name: &str, value: &str) -> TftpResult<()> {
+        let mut option = Vec::new();
+        options::push(&mut option, self.iter(), name, value)?;
+        self.added.append(&mut option);
+        Ok(())
     }
 }
 
@@ -655,13 +924,10 @@ pub struct OptionsIterator<'a> {
 }
 
 impl<'a> OptionsIterator<'a> {
-    /// iterate only over the options that are not understood by this crate (i.e. anything but `blksize`, `timeout` and `tsize`).
+    /// iterate only over the options that are not understood by this crate, see [`KnownOption`].
     pub fn unknown(self) -> impl Iterator<Item = TftpResult<(&'a str, &'a str)>> {
         self.into_iter().filter(|x| match x {
-            Ok((name, _)) => match *name {
-                "blksize" | "timeout" | "tsize" => false,
-                _ => true,
-            },
+            Ok((name, _)) => KnownOption::from_name(name).is_none(),
             Err(_) => true,
         })
     }
@@ -686,3 +952,29 @@ impl<'a> Iterator for OptionsIterator<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wire_format() {
+        let mut buffer = [0u8; 64];
+        let n_bytes = Ack::new(0x0102).to_bytes(&mut buffer).unwrap();
+        assert_eq!(&buffer[..n_bytes], &[0, 4, 1, 2]);
+        assert!(matches!(
+            Packet::from_bytes(&buffer[..n_bytes]),
+            Ok(Packet::Ack(Ack { block_nr: 0x0102 }))
+        ));
+
+        // RFC-1350 calls the binary mode "octet", servers refuse requests for anything else.
+        let n_bytes = Request::new_write_request("file", None)
+            .to_bytes(&mut buffer)
+            .unwrap();
+        assert_eq!(&buffer[..n_bytes], b"\0\x02file\0octet\0");
+        let mut request = Request::new_read_request("file", None);
+        request.transfer_size = Some(0);
+        let n_bytes = request.to_bytes(&mut buffer).unwrap();
+        assert_eq!(&buffer[..n_bytes], b"\0\x01file\0octet\0tsize\x000\0");
+    }
+}
@@ -0,0 +1,182 @@
+use super::{Mode, Request, Rollover, TftpError, TftpResult};
+#[cfg(feature = "alloc")]
+use crate::options::CustomOption;
+use core::num::{NonZeroU16, NonZeroU8};
+
+/// Builds a read or write [`Request`] with any of the options a client may send.
+///
+/// Created with [`Request::read`] or [`Request::write`]. Mistakes like a blocksize outside of the range allowed by
+/// [RFC-2348](https://www.rfc-editor.org/rfc/rfc2348.html) are reported by [`build`](RequestBuilder::build),
+/// so the options can be chained without checking each one.
+#[derive(Debug)]
+#[must_use]
+pub struct RequestBuilder<'a> {
+    request: Request<'a>,
+    /// the first error encountered while adding options.
+    error: Option<TftpError>,
+}
+
+impl<'a> Request<'a> {
+    /// starts building a read request for `filename`.
+    pub fn read(filename: &'a str) -> RequestBuilder<'a> {
+        RequestBuilder::new(Request::new_read_request(filename, None))
+    }
+
+    /// starts building a write request for `filename`.
+    pub fn write(filename: &'a str) -> RequestBuilder<'a> {
+        RequestBuilder::new(Request::new_write_request(filename, None))
+    }
+}
+
+impl<'a> RequestBuilder<'a> {
+    fn new(request: Request<'a>) -> Self {
+        Self {
+            request,
+            error: None,
+        }
+    }
+
+    /// sets the mode of the transfer, [`Mode::Octet`] by default.
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.request.mode = mode;
+        self
+    }
+
+    /// requests a blocksize using the blksize option defined in [RFC-2348](https://www.rfc-editor.org/rfc/rfc2348.html).
+    /// Has to be between 8 and 65464 bytes.
+    pub fn blocksize(mut self, blocksize: u16) -> Self {
+        if !(8..=65464).contains(&blocksize) {
+            self.fail(TftpError::InvalidBlockSize(blocksize.into()));
+        }
+        self.request.blocksize = Some(blocksize);
+        self
+    }
+
+    /// sends the tsize option defined in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html) with `size`.
+    /// Write requests announce the size of the file with it, read requests have to send 0 to ask the server for the size.
+    pub fn transfer_size(mut self, size: u64) -> Self {
+        if self.request.is_read() && size != 0 {
+            self.fail(TftpError::BadFormatting);
+        }
+        self.request.transfer_size = Some(size);
+        self
+    }
+
+    /// asks the server to use `seconds` as the retransmission timeout, using the timeout option defined in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html).
+    pub fn timeout(mut self, seconds: NonZeroU8) -> Self {
+        self.request.timeout_seconds = Some(seconds);
+        self
+    }
+
+    /// requests a window of `window_size` blocks using the windowsize option defined in [RFC-7440](https://www.rfc-editor.org/rfc/rfc7440.html).
+    pub fn window_size(mut self, window_size: NonZeroU16) -> Self {
+        self.request.window_size = Some(window_size);
+        self
+    }
+
+    /// asks to receive the file over multicast, using the multicast option defined in [RFC-2090](https://www.rfc-editor.org/rfc/rfc2090.html).
+    /// Only valid on read requests.
+    pub fn multicast(mut self) -> Self {
+        if self.request.is_write() {
+            self.fail(TftpError::BadFormatting);
+        }
+        self.request.multicast = true;
+        self
+    }
+
+    /// requests what the block number continues with after block 65535, using the `rollover` option.
+    pub fn rollover(mut self, rollover: Rollover) -> Self {
+        self.request.rollover = Some(rollover);
+        self
+    }
+
+    /// adds an option this crate doesn't know about, see [`Request::push_option`].
+    #[cfg(feature = "alloc")]
+    #[doc(cfg(feature = "alloc"))]
+    pub fn option(mut self, name: &str, value: &str) -> Self {
+        if let Err(e) = self.request.push_option(name, value) {
+            self.fail(e);
+        }
+        self
+    }
+
+    /// adds the custom option `option`, see [`Request::set_option`].
+    #[cfg(feature = "alloc")]
+    #[doc(cfg(feature = "alloc"))]
+    pub fn custom<O: CustomOption>(mut self, option: &O) -> Self {
+        if let Err(e) = self.request.set_option(option) {
+            self.fail(e);
+        }
+        self
+    }
+
+    /// returns the request, or the first error in the options that were added.
+    pub fn build(self) -> TftpResult<Request<'a>> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.request),
+        }
+    }
+
+    fn fail(&mut self, error: TftpError) {
+        self.error.get_or_insert(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::Packet;
+
+    #[test]
+    fn build() {
+        let request = Request::write("firmware.bin")
+            .mode(Mode::NetAscii)
+            .blocksize(1428)
+            .transfer_size(123456)
+            .timeout(NonZeroU8::new(3).unwrap())
+            .window_size(NonZeroU16::new(16).unwrap())
+            .rollover(Rollover::One)
+            .build()
+            .unwrap();
+        let mut buffer = [0u8; 128];
+        let n_bytes = request.to_bytes(&mut buffer).unwrap();
+        let Ok(Packet::Request(request)) = Packet::from_bytes(&buffer[..n_bytes]) else {
+            panic!("expected a request");
+        };
+        assert!(request.is_write());
+        assert_eq!(request.filename, "firmware.bin");
+        assert_eq!(request.mode, Mode::NetAscii);
+        assert_eq!(request.blocksize, Some(1428));
+        assert_eq!(request.transfer_size, Some(123456));
+        assert_eq!(request.timeout_seconds, NonZeroU8::new(3));
+        assert_eq!(request.window_size, NonZeroU16::new(16));
+        assert_eq!(request.rollover, Some(Rollover::One));
+
+        assert!(matches!(
+            Request::read("file").blocksize(4).build(),
+            Err(TftpError::InvalidBlockSize(4))
+        ));
+        assert!(matches!(
+            Request::read("file").transfer_size(10).build(),
+            Err(TftpError::BadFormatting)
+        ));
+        assert!(matches!(
+            Request::write("file").multicast().build(),
+            Err(TftpError::BadFormatting)
+        ));
+        assert!(Request::read("file")
+            .transfer_size(0)
+            .multicast()
+            .build()
+            .is_ok());
+        #[cfg(feature = "alloc")]
+        assert!(matches!(
+            Request::read("file")
+                .option("x-a", "1")
+                .option("X-A", "2")
+                .build(),
+            Err(TftpError::OptionRepeated)
+        ));
+    }
+}
@@ -0,0 +1,298 @@
+use super::{
+    Ack, Data, Error, ErrorCode, Mode, Multicast, OpCode, OptionAck, Packet, Request, Rollover,
+    TftpResult, UnknownOptions,
+};
+use alloc::{string::String, vec::Vec};
+use core::num::{NonZeroU16, NonZeroU8};
+
+/// A [`Packet`] that owns its data instead of borrowing the buffer it was received in,
+/// so it can be kept around, queued or sent to another thread.
+///
+/// Create one with [`Packet::to_owned`], and borrow it as a [`Packet`] again with [`as_ref`](OwnedPacket::as_ref) to inspect or send it.
+#[derive(Debug, Clone)]
+pub enum OwnedPacket {
+    /// A data packet,
+    Data(OwnedData),
+    /// A read- or write-request packet,
+    Request(OwnedRequest),
+    /// An error packet indicating something went wrong,
+    Error(OwnedError),
+    /// An acknowledge packet, send in response to a data packet,
+    Ack(Ack),
+    /// An option acknowledge packet, acknowledging options request with a read- or write-request packet,
+    OptionAck(OwnedOptionAck),
+}
+
+/// A read- or write-request that owns its filename, see [`Request`] for the meaning of the fields.
+#[derive(Debug, Clone)]
+pub struct OwnedRequest {
+    is_read: bool,
+    /// the requested filename.
+    pub filename: String,
+    /// The mode of the transfer.
+    pub mode: Mode,
+    /// The requested blocksize.
+    pub blocksize: Option<u16>,
+    /// The tsize option.
+    pub transfer_size: Option<u64>,
+    /// The requested timeout in seconds.
+    pub timeout_seconds: Option<NonZeroU8>,
+    /// The requested window size.
+    pub window_size: Option<NonZeroU16>,
+    /// whether the client asked to receive the file over multicast.
+    pub multicast: bool,
+    /// The requested rollover.
+    pub rollover: Option<Rollover>,
+    unknown_options: Vec<u8>,
+}
+
+/// A data packet that owns its payload.
+#[derive(Debug, Clone)]
+pub struct OwnedData {
+    /// the number of this block.
+    pub block_nr: u16,
+    /// the payload of this block.
+    pub data: Vec<u8>,
+}
+
+/// An error packet that owns its message.
+#[derive(Debug, Clone)]
+pub struct OwnedError {
+    /// The specific error code. See [`ErrorCode`] for details.
+    pub error_code: ErrorCode,
+    /// The human read-able error message.
+    pub message: String,
+}
+
+/// An option acknowledgement that owns the options this library doesn't know about, see [`OptionAck`] for the meaning of the fields.
+#[derive(Debug, Clone)]
+pub struct OwnedOptionAck {
+    /// the acknowledged blocksize.
+    pub blocksize: Option<u16>,
+    /// the acknowledged transfer size.
+    pub transfer_size: Option<u64>,
+    /// the acknowledged timeout.
+    pub timeout_seconds: Option<NonZeroU8>,
+    /// the acknowledged window size.
+    pub window_size: Option<NonZeroU16>,
+    /// the acknowledged multicast option.
+    pub multicast: Option<Multicast>,
+    /// the acknowledged rollover.
+    pub rollover: Option<Rollover>,
+    unknown_options: Vec<u8>,
+}
+
+impl OwnedPacket {
+    /// parses a packet from a data buffer and copies it, see [`Packet::from_bytes`].
+    pub fn from_bytes(data: &[u8]) -> TftpResult<Self> {
+        Packet::from_bytes(data).map(|packet| packet.to_owned())
+    }
+
+    /// borrows this packet as a [`Packet`].
+    pub fn as_ref(&self) -> Packet<'_> {
+        match self {
+            Self::Data(data) => Packet::Data(data.as_ref()),
+            Self::Request(request) => Packet::Request(request.as_ref()),
+            Self::Error(error) => Packet::Error(error.as_ref()),
+            Self::Ack(ack) => Packet::Ack(*ack),
+            Self::OptionAck(option_ack) => Packet::OptionAck(option_ack.as_ref()),
+        }
+    }
+
+    /// returns the opcode of the packet.
+    pub fn opcode(&self) -> OpCode {
+        self.as_ref().opcode()
+    }
+}
+
+impl OwnedRequest {
+    /// returns true if this is a read request.
+    pub fn is_read(&self) -> bool {
+        self.is_read
+    }
+
+    /// returns true if this is a write request.
+    pub fn is_write(&self) -> bool {
+        !self.is_read
+    }
+
+    /// borrows this request as a [`Request`].
+    pub fn as_ref(&self) -> Request<'_> {
+        Request {
+            is_read: self.is_read,
+            filename: &self.filename,
+            mode: self.mode,
+            blocksize: self.blocksize,
+            transfer_size: self.transfer_size,
+            timeout_seconds: self.timeout_seconds,
+            window_size: self.window_size,
+            multicast: self.multicast,
+            rollover: self.rollover,
+            unknown_options: UnknownOptions::parsed(&self.unknown_options),
+        }
+    }
+}
+
+impl OwnedData {
+    /// borrows this packet as a [`Data`] packet.
+    pub fn as_ref(&self) -> Data<'_> {
+        Data::new(self.block_nr, &self.data)
+    }
+}
+
+impl OwnedError {
+    /// borrows this packet as an [`Error`] packet.
+    pub fn as_ref(&self) -> Error<'_> {
+        Error::new(self.error_code, &self.message)
+    }
+}
+
+impl OwnedOptionAck {
+    /// borrows this packet as an [`OptionAck`].
+    pub fn as_ref(&self) -> OptionAck<'_> {
+        OptionAck {
+            blocksize: self.blocksize,
+            transfer_size: self.transfer_size,
+            timeout_seconds: self.timeout_seconds,
+            window_size: self.window_size,
+            multicast: self.multicast,
+            rollover: self.rollover,
+            unknown_options: UnknownOptions::parsed(&self.unknown_options),
+        }
+    }
+}
+
+impl Packet<'_> {
+    /// copies this packet, so it no longer borrows the buffer it was parsed from.
+    pub fn to_owned(&self) -> OwnedPacket {
+        match self {
+            Self::Data(data) => OwnedPacket::Data(data.to_owned()),
+            Self::Request(request) => OwnedPacket::Request(request.to_owned()),
+            Self::Error(error) => OwnedPacket::Error(error.to_owned()),
+            Self::Ack(ack) => OwnedPacket::Ack(*ack),
+            Self::OptionAck(option_ack) => OwnedPacket::OptionAck(option_ack.to_owned()),
+        }
+    }
+}
+
+impl Request<'_> {
+    /// copies this request, so it no longer borrows the buffer it was parsed from.
+    pub fn to_owned(&self) -> OwnedRequest {
+        OwnedRequest {
+            is_read: self.is_read,
+            filename: self.filename.into(),
+            mode: self.mode,
+            blocksize: self.blocksize,
+            transfer_size: self.transfer_size,
+            timeout_seconds: self.timeout_seconds,
+            window_size: self.window_size,
+            multicast: self.multicast,
+            rollover: self.rollover,
+            unknown_options: copy_unknown_options(self.unknown_options()),
+        }
+    }
+}
+
+impl Data<'_> {
+    /// copies this packet, so it no longer borrows the buffer it was parsed from.
+    pub fn to_owned(&self) -> OwnedData {
+        OwnedData {
+            block_nr: self.block_nr,
+            data: self.data.into(),
+        }
+    }
+}
+
+impl Error<'_> {
+    /// copies this packet, so it no longer borrows the buffer it was parsed from.
+    pub fn to_owned(&self) -> OwnedError {
+        OwnedError {
+            error_code: self.error_code,
+            message: self.message.into(),
+        }
+    }
+}
+
+impl OptionAck<'_> {
+    /// copies this packet, so it no longer borrows the buffer it was parsed from.
+    pub fn to_owned(&self) -> OwnedOptionAck {
+        OwnedOptionAck {
+            blocksize: self.blocksize,
+            transfer_size: self.transfer_size,
+            timeout_seconds: self.timeout_seconds,
+            window_size: self.window_size,
+            multicast: self.multicast,
+            rollover: self.rollover,
+            unknown_options: copy_unknown_options(self.unknown_options()),
+        }
+    }
+}
+
+// copies options, leaving out any that can't be parsed.
+fn copy_unknown_options<'a>(
+    options: impl Iterator<Item = TftpResult<(&'a str, &'a str)>>,
+) -> Vec<u8> {
+    let mut copy = Vec::new();
+    for (name, value) in options.flatten() {
+        copy.extend_from_slice(name.as_bytes());
+        copy.push(0);
+        copy.extend_from_slice(value.as_bytes());
+        copy.push(0);
+    }
+    copy
+}
+
+impl From<Packet<'_>> for OwnedPacket {
+    fn from(packet: Packet<'_>) -> Self {
+        packet.to_owned()
+    }
+}
+
+impl From<Request<'_>> for OwnedRequest {
+    fn from(request: Request<'_>) -> Self {
+        request.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut request = Request::new_read_request("boot/kernel.img", Some(1428));
+        request.window_size = NonZeroU16::new(8);
+        request.rollover = Some(Rollover::One);
+        let mut buffer = [0u8; 128];
+        let n_bytes = request.to_bytes(&mut buffer).unwrap();
+        // unknown options are kept too
+        buffer[n_bytes..n_bytes + 6].copy_from_slice(b"foo\0x\0");
+
+        let owned = OwnedPacket::from_bytes(&buffer[..n_bytes + 6]).unwrap();
+        buffer.fill(0);
+        let OwnedPacket::Request(owned) = owned else {
+            panic!("expected a request");
+        };
+        assert!(owned.is_read());
+        assert_eq!(owned.filename, "boot/kernel.img");
+        let request = owned.as_ref();
+        assert_eq!(
+            (request.blocksize, request.window_size, request.rollover),
+            (Some(1428), NonZeroU16::new(8), Some(Rollover::One))
+        );
+        assert!(request
+            .unknown_options()
+            .map(Result::unwrap)
+            .eq([("foo", "x")]));
+
+        let owned = Packet::new_error(ErrorCode::FILE_NOT_FOUND, "missing").to_owned();
+        let n_bytes = owned.as_ref().to_bytes(&mut buffer).unwrap();
+        assert!(matches!(
+            Packet::from_bytes(&buffer[..n_bytes]),
+            Ok(Packet::Error(Error {
+                error_code: ErrorCode::FILE_NOT_FOUND,
+                message: "missing"
+            }))
+        ));
+    }
+}
@@ -1,17 +1,112 @@
+/// serving several directories as described by a configuration file
+#[cfg(feature = "config")]
+#[doc(cfg(feature = "config"))]
+pub mod config;
+/// a [`Handler`] serving the files in a directory
+pub mod fs;
+/// serving a file to many clients at once over multicast
+pub mod multicast;
+/// rewriting requested filenames before they're looked up
+#[cfg(feature = "remap")]
+#[doc(cfg(feature = "remap"))]
+pub mod remap;
+
 use crate::{
-    datastream::DataStream,
-    packet::{Ack, Error, OptionAck, Packet, Request},
-    socket::TFTPSocket,
+    error::TransferError,
+    netascii::{ModeReader, ModeWriter},
+    options::OptionPolicy,
+    packet::{Error, ErrorCode, OptionAck, OwnedRequest, Packet, Request, Rollover},
+    socket::{RetransmitPolicy, TFTPSocket},
+    transfer::to_vec,
+    transport::{self, DataSink, Transport},
 };
 use std::{
-    io::{Error as IoError, Read, Result as IoResult},
-    net::{IpAddr, SocketAddr},
+    collections::HashMap,
+    io::{Error as IoError, Read, Result as IoResult, Seek, SeekFrom, Write},
+    net::{IpAddr, SocketAddr, UdpSocket},
+    sync::{mpsc, Mutex},
     time::Duration,
 };
 
+/// Decides which requests a [`Server`] accepts and provides the data for them.
+///
+/// Implement this to serve something other than a plain directory, see [`fs::RootDirectory`] for the built-in implementation.
+/// Refusing a request with an [`Error`] sends it to the client. Translating the data for netascii transfers is done by the server,
+/// handlers only have to take the mode into account when computing the transfer size.
+pub trait Handler {
+    /// the type the data of a read request is read from.
+    type Reader: Read;
+    /// the type the data of a write request is written to.
+    type Writer: Write;
+
+    /// opens the file requested by the read request `request` of the client at `client`.
+    ///
+    /// Returns the data and, if known, its size. The size is send to the client if it asked for it using the tsize option defined in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html).
+    /// For [netascii](crate::packet::Mode::NetAscii) transfers it should be the size after translation, see [`translated_len`](crate::netascii::translated_len).
+    fn open_read(
+        &self,
+        request: &Request,
+        client: SocketAddr,
+    ) -> Result<(Self::Reader, Option<u64>), Error<'static>>;
+
+    /// opens the file to write the data of the write request `request` of the client at `client` to.
+    fn open_write(
+        &self,
+        request: &Request,
+        client: SocketAddr,
+    ) -> Result<Self::Writer, Error<'static>>;
+
+    /// called by [`Server::serve`] once a transfer accepted by this handler is done, with the result of [`HandledTransfer::finish`],
+    /// or with the error creating the socket for the transfer if that failed.
+    /// Does nothing by default.
+    fn transfer_finished(
+        &self,
+        filename: &str,
+        client: SocketAddr,
+        result: &Result<(), TransferError>,
+    ) {
+        let _ = (filename, client, result);
+    }
+
+    /// called with the options the server acknowledges for an accepted `request` of the client at `client`, after the [`OptionPolicy`] of the server picked them.
+    /// Override this to acknowledge [custom options](crate::options::CustomOption) or to change the picked options for some requests.
+    /// Does nothing by default.
+    fn negotiate_options(
+        &self,
+        request: &Request,
+        client: SocketAddr,
+        options: &mut OptionAck<'static>,
+    ) {
+        let _ = (request, client, options);
+    }
+}
+
+/// Limits how many transfers [`Server::serve`] runs at once. Requests that would exceed them are answered with an error asking the client to try again later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// the maximum amount of transfers running at once. This is also the amount of worker threads `serve` starts.
+    pub max_transfers: usize,
+    /// the maximum amount of transfers running at once with clients on the same ip address.
+    pub max_transfers_per_client: usize,
+}
+
+impl Default for Limits {
+    /// runs up to 16 transfers at once, and up to 4 with the same client.
+    fn default() -> Self {
+        Self {
+            max_transfers: 16,
+            max_transfers_per_client: 4,
+        }
+    }
+}
+
 /// A TFTP Server implementation
 pub struct Server {
     sock: TFTPSocket,
+    retransmit: RetransmitPolicy,
+    limits: Limits,
+    rollover: Option<Rollover>,
+    option_policy: OptionPolicy,
 }
 
 impl Server {
@@ -24,9 +119,56 @@ impl Server {
     pub fn connect_with_port(ip: IpAddr, port: u16) -> IoResult<Self> {
         Ok(Self {
             sock: TFTPSocket::new(SocketAddr::new(ip, port), None, 0xFFFF)?,
+            retransmit: RetransmitPolicy::default(),
+            limits: Limits::default(),
+            rollover: Some(Rollover::Zero),
+            option_policy: OptionPolicy::default(),
         })
     }
 
+    /// sets how transfers created by this server deal with lost packets. Only affects transfers created after calling this method.
+    /// The timeout of the policy is overridden for transfers where the client negotiated a timeout using [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html).
+    pub fn set_retransmit_policy(&mut self, policy: RetransmitPolicy) {
+        self.retransmit = policy;
+    }
+
+    /// returns how transfers created by this server deal with lost packets.
+    pub fn retransmit_policy(&self) -> RetransmitPolicy {
+        self.retransmit
+    }
+
+    /// sets how many transfers [`serve`](Server::serve) runs at once. Only takes effect the next time `serve` is called.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// returns how many transfers [`serve`](Server::serve) runs at once.
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// sets what block numbers continue with after block 65535, for transfers where the client didn't ask for a rollover with the `rollover` option.
+    /// With `None`, transfers of files that need more blocks than that fail instead. Defaults to [`Rollover::Zero`], like most servers do.
+    /// Only affects transfers created after calling this method.
+    pub fn set_rollover(&mut self, rollover: Option<Rollover>) {
+        self.rollover = rollover;
+    }
+
+    /// returns what block numbers continue with after block 65535, if the client didn't ask for a rollover.
+    pub fn rollover(&self) -> Option<Rollover> {
+        self.rollover
+    }
+
+    /// sets which options requested by clients are acknowledged by [`handle_next_request`](Server::handle_next_request) and [`serve`](Server::serve).
+    pub fn set_option_policy(&mut self, policy: OptionPolicy) {
+        self.option_policy = policy;
+    }
+
+    /// returns which options requested by clients are acknowledged.
+    pub fn option_policy(&self) -> OptionPolicy {
+        self.option_policy
+    }
+
     /// sets the read timeout of the underlying socket. Note that this has nothing to do with the timeout option described in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html).
     pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> IoResult<()> {
         self.sock.sock.set_read_timeout(timeout)
@@ -39,32 +181,173 @@ impl Server {
 
     /// gets the next request from a client and returns it plus the adress of the client.
     /// wil return an error if the next packet received is not a request.
+    /// The request borrows the server's receive buffer, use [`Request::to_owned`] to keep it around while the server receives the next one.
     pub fn get_next_request_from(&mut self) -> IoResult<(Request<'_>, SocketAddr)> {
         match self.sock.get_next_message_from()? {
             (Packet::Request(req), addr) => Ok((req, addr)),
-            _ => {
-                return Err(IoError::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Invalid packet received",
-                ))
-            }
+            _ => Err(IoError::new(
+                std::io::ErrorKind::InvalidData,
+                "Invalid packet received",
+            )),
         }
     }
 
     /// transfers the data contained in `source` to `target`, optionally using the TFTP extensions described in `options`.
+    /// If `options` contains a timeout, it is used as the retransmission interval for this transfer instead of the one set with [`set_retransmit_policy`](Server::set_retransmit_policy).
     pub fn create_transfer_to<R: std::io::Read>(
         &self,
         target: SocketAddr,
         source: R,
         options: OptionAck<'static>,
     ) -> IoResult<Transfer<R>> {
-        if options.timeout_seconds.is_some() {
-            return Err(IoError::new(
-                std::io::ErrorKind::Other,
-                "Server does not support setting a time-out",
-            ));
+        Ok(Transfer::new(
+            source,
+            UdpSocket::bind(SocketAddr::new(self.ip()?, 0))?,
+            target,
+            options,
+            self.retransmit,
+            self.rollover,
+        ))
+    }
+
+    /// receives the data send by `target` and writes it to `sink`, optionally using the TFTP extensions described in `options`.
+    /// This is the counterpart of [`create_transfer_to`](Server::create_transfer_to) and should be used to answer write requests.
+    /// If `options` contains a timeout, it is used as the retransmission interval for this transfer instead of the one set with [`set_retransmit_policy`](Server::set_retransmit_policy).
+    pub fn create_transfer_from<W: Write>(
+        &self,
+        target: SocketAddr,
+        sink: W,
+        options: OptionAck<'static>,
+    ) -> IoResult<IncomingTransfer<W>> {
+        Ok(IncomingTransfer::new(
+            sink,
+            UdpSocket::bind(SocketAddr::new(self.ip()?, 0))?,
+            target,
+            options,
+            self.retransmit,
+            self.rollover,
+        ))
+    }
+
+    /// serves the data contained in `source` to `target` and any clients that join later by sending it to the multicast group `group`,
+    /// as described in [RFC-2090](https://www.rfc-editor.org/rfc/rfc2090.html). Use this to answer read requests with the [`multicast`](Request::multicast) option set.
+    ///
+    /// `options` should not contain the multicast option itself, it is added for every client.
+    /// The source has to implement [`Seek`], so that clients that missed blocks can catch up once they become the master client.
+    /// It is served from its start, and may not need more than 65535 blocks: clients acknowledge bare block numbers, so once those roll over
+    /// there'd be no telling which block a client that just became master is missing. Larger sources are refused with [`ErrorKind::FileTooLarge`](std::io::ErrorKind::FileTooLarge).
+    pub fn create_multicast_transfer<R: Read + Seek>(
+        &self,
+        group: SocketAddr,
+        target: SocketAddr,
+        mut source: R,
+        options: OptionAck<'static>,
+    ) -> IoResult<multicast::MulticastTransfer<R>> {
+        let size = source.seek(SeekFrom::End(0))?;
+        source.rewind()?;
+        if size / options.blocksize.unwrap_or(512).max(1) as u64 >= u16::MAX as u64 {
+            return Err(TransferError::TooManyBlocks.into());
         }
-        Transfer::new(source, self.sock.sock.local_addr()?.ip(), target, options)
+        let sock = TFTPSocket::new(
+            SocketAddr::new(self.sock.sock.local_addr()?.ip(), 0),
+            None,
+            512 + (options.blocksize.unwrap_or(512) as usize),
+        )?;
+        Ok(multicast::MulticastTransfer::new(
+            sock,
+            group,
+            target,
+            source,
+            options,
+            self.retransmit,
+        ))
+    }
+
+    /// gets the next request from a client and answers it using `handler`.
+    ///
+    /// If the handler accepts the request, the returned transfer still has to be executed with [`HandledTransfer::finish`].
+    /// The requested options are acknowledged according to the [option policy](Server::set_option_policy), with the transfer size provided by the handler,
+    /// and then passed to [`Handler::negotiate_options`].
+    /// If the handler refuses the request, its error is sent to the client and returned as an io-error.
+    pub fn handle_next_request<H: Handler>(
+        &mut self,
+        handler: &H,
+    ) -> IoResult<HandledTransfer<H::Reader, H::Writer>> {
+        let (ip, policies) = (self.ip()?, self.policies());
+        let (request, client) = self.get_next_request_from()?;
+        handle_request(policies, &mut bind_to(ip), &request, client, handler).map_err(IoError::from)
+    }
+
+    /// answers `request` of the client at `client` using `handler`, like [`handle_next_request`](Server::handle_next_request).
+    pub fn handle_request<H: Handler>(
+        &self,
+        request: &Request,
+        client: SocketAddr,
+        handler: &H,
+    ) -> IoResult<HandledTransfer<H::Reader, H::Writer>> {
+        self.handle_request_with(request, client, handler, bind_to(self.ip()?))
+    }
+
+    /// answers `request` of the client at `client` using `handler`, like [`handle_request`](Server::handle_request),
+    /// but over a transport created with `bind` instead of a new UDP socket, for example a socket on a [simulated network](crate::simulator).
+    /// `bind` should pick a new port every time it's called, just like binding port 0 does.
+    pub fn handle_request_with<T: Transport<Error = IoError>, H: Handler>(
+        &self,
+        request: &Request,
+        client: SocketAddr,
+        handler: &H,
+        mut bind: impl FnMut() -> IoResult<T>,
+    ) -> IoResult<HandledTransfer<H::Reader, H::Writer, T>> {
+        handle_request(self.policies(), &mut bind, request, client, handler).map_err(IoError::from)
+    }
+
+    /// answers requests using `handler` until an io-error occurs on the server's socket, running the transfers on a pool of worker threads.
+    ///
+    /// Requests that would exceed the [limits](Server::set_limits) of the server are answered with an error asking the client to try again later,
+    /// sent from a new port like any other refusal. The handler opens the requested files on the worker threads, so a slow handler only holds up its own client.
+    /// Packets that aren't requests are ignored. Once the server's socket fails, the transfers that are still running are finished before returning the error.
+    /// The result of every transfer is passed to [`Handler::transfer_finished`].
+    pub fn serve<H: Handler + Sync>(&mut self, handler: &H) -> IoResult<()> {
+        let (ip, policies) = (self.ip()?, self.policies());
+        let next_request = || {
+            let (request, client) = self.get_next_request_from()?;
+            Ok((request.to_owned(), client))
+        };
+        serve(policies, next_request, bind_to(ip), handler)
+    }
+
+    /// answers requests received on `listener` using `handler`, like [`serve`](Server::serve), but over transports created with `bind`
+    /// instead of UDP sockets, for example sockets on a [simulated network](crate::simulator).
+    /// Only the settings of the server are used, not its socket. `bind` should pick a new port every time it's called, just like binding port 0 does.
+    /// Returns once receiving on `listener` fails.
+    pub fn serve_with<L, T, H>(
+        &self,
+        mut listener: L,
+        bind: impl Fn() -> IoResult<T> + Sync,
+        handler: &H,
+    ) -> IoResult<()>
+    where
+        L: Transport<Error = IoError>,
+        T: Transport<Error = IoError>,
+        H: Handler + Sync,
+    {
+        let mut buffer = vec![0u8; 0xFFFF];
+        let next_request = || loop {
+            // there is no waiting forever, so just keep waiting.
+            let Some((n_bytes, client)) =
+                listener.recv_from(&mut buffer, Duration::from_secs(1))?
+            else {
+                continue;
+            };
+            return match TFTPSocket::parse(&buffer[..n_bytes], client)? {
+                (Packet::Request(request), client) => Ok((request.to_owned(), client)),
+                _ => Err(IoError::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Invalid packet received",
+                )),
+            };
+        };
+        serve(self.policies(), next_request, bind, handler)
     }
 
     /// sends the error message `error` to the client at `addr`.
@@ -76,89 +359,699 @@ impl Server {
     pub fn ip(&self) -> Result<IpAddr, IoError> {
         self.sock.sock.local_addr().map(|a| a.ip())
     }
+
+    fn policies(&self) -> Policies {
+        Policies {
+            retransmit: self.retransmit,
+            limits: self.limits,
+            rollover: self.rollover,
+            option_policy: self.option_policy,
+        }
+    }
+}
+
+/// the settings of a [`Server`] its requests are answered with, copied so they can be used while the server is busy receiving requests.
+#[derive(Clone, Copy)]
+struct Policies {
+    retransmit: RetransmitPolicy,
+    limits: Limits,
+    rollover: Option<Rollover>,
+    option_policy: OptionPolicy,
+}
+
+// binds new UDP sockets on `ip`, for the transfers of a server.
+fn bind_to(ip: IpAddr) -> impl Fn() -> IoResult<UdpSocket> {
+    move || UdpSocket::bind(SocketAddr::new(ip, 0))
+}
+
+// answers the requests returned by `next_request` using `handler` until it fails, see [`Server::serve`].
+fn serve<T: Transport<Error = IoError>, H: Handler + Sync>(
+    policies: Policies,
+    mut next_request: impl FnMut() -> IoResult<(OwnedRequest, SocketAddr)>,
+    bind: impl Fn() -> IoResult<T> + Sync,
+    handler: &H,
+) -> IoResult<()> {
+    let slots = Slots::new(policies.limits);
+    let (sender, receiver) = mpsc::channel::<(OwnedRequest, SocketAddr)>();
+    let receiver = Mutex::new(receiver);
+    std::thread::scope(|scope| {
+        for _ in 0..policies.limits.max_transfers {
+            scope.spawn(|| loop {
+                // the lock is released as soon as a request is received, so other workers can wait for the next one.
+                let Ok((request, client)) = receiver.lock().unwrap().recv() else {
+                    return;
+                };
+                let request = request.as_ref();
+                let handled = handle_request(policies, &mut &bind, &request, client, handler);
+                let result = match handled {
+                    Ok(transfer) => Some(transfer.finish()),
+                    // the handler already opened the file, so it is told the transfer failed.
+                    Err(Unhandled::Failed(e)) => Some(Err(TransferError::Io(e))),
+                    // the handler refused the request, and the client was told why.
+                    Err(Unhandled::Refused(_)) => None,
+                };
+                slots.release(client.ip());
+                if let Some(result) = result {
+                    handler.transfer_finished(request.filename, client, &result);
+                }
+            });
+        }
+        let result = loop {
+            let (request, client) = match next_request() {
+                Ok(request) => request,
+                Err(e) if e.kind() == std::io::ErrorKind::InvalidData => continue,
+                Err(e) => break Err(e),
+            };
+            if !slots.acquire(client.ip()) {
+                let busy = Error::new(ErrorCode::NOT_DEFINED, "Server busy, try again later");
+                let _may_fail = refuse(&mut &bind, &request.as_ref(), client, busy);
+                continue;
+            }
+            if sender.send((request, client)).is_err() {
+                slots.release(client.ip());
+                break Err(IoError::other("The worker threads stopped"));
+            }
+        };
+        // without a sender the workers stop once they're done with their transfers, which the scope waits for.
+        drop(sender);
+        result
+    })
+}
+
+// answers `request` using `handler`, creating the transfer on a new transport created with `bind`.
+fn handle_request<T: Transport<Error = IoError>, H: Handler>(
+    policies: Policies,
+    bind: &mut impl FnMut() -> IoResult<T>,
+    request: &Request,
+    client: SocketAddr,
+    handler: &H,
+) -> Result<HandledTransfer<H::Reader, H::Writer, T>, Unhandled> {
+    let Policies {
+        retransmit,
+        rollover,
+        option_policy,
+        ..
+    } = policies;
+    let direction = if request.is_read() {
+        match handler.open_read(request, client) {
+            Ok((source, size)) => {
+                let mut options = option_policy.negotiate(request, size);
+                handler.negotiate_options(request, client, &mut options);
+                let source = ModeReader::new(source, request.mode);
+                let sock = bind().map_err(|e| fail(bind, request, client, e))?;
+                let transfer = Transfer::new(source, sock, client, options, retransmit, rollover);
+                Direction::Read(transfer)
+            }
+            Err(error) => return Err(Unhandled::Refused(refuse(bind, request, client, error))),
+        }
+    } else {
+        match handler.open_write(request, client) {
+            Ok(sink) => {
+                let sink = ModeWriter::new(sink, request.mode);
+                let mut options = option_policy.negotiate(request, None);
+                handler.negotiate_options(request, client, &mut options);
+                let sock = bind().map_err(|e| fail(bind, request, client, e))?;
+                let transfer =
+                    IncomingTransfer::new(sink, sock, client, options, retransmit, rollover);
+                Direction::Write(transfer)
+            }
+            Err(error) => return Err(Unhandled::Refused(refuse(bind, request, client, error))),
+        }
+    };
+    Ok(HandledTransfer {
+        client,
+        filename: request.filename.to_owned(),
+        direction,
+    })
+}
+
+/// why a request wasn't turned into a transfer.
+enum Unhandled {
+    /// the handler refused the request, and the client was sent its error.
+    Refused(IoError),
+    /// the handler accepted the request, but creating the socket for the transfer failed. The client was sent an error too.
+    Failed(IoError),
+}
+
+impl From<Unhandled> for IoError {
+    fn from(unhandled: Unhandled) -> Self {
+        match unhandled {
+            Unhandled::Refused(e) | Unhandled::Failed(e) => e,
+        }
+    }
+}
+
+// tells the client its accepted `request` can't be served after all, because creating the transfer failed with `error`.
+fn fail<T: Transport<Error = IoError>>(
+    bind: &mut impl FnMut() -> IoResult<T>,
+    request: &Request,
+    client: SocketAddr,
+    error: IoError,
+) -> Unhandled {
+    let unexpected = Error::new(ErrorCode::NOT_DEFINED, "Unexpected IO error");
+    let _may_fail = refuse(bind, request, client, unexpected);
+    Unhandled::Failed(error)
+}
+
+// sends `error` to the client in reply to `request`, and returns the error describing that.
+// The error is sent from a new transport created with `bind`, just like the first reply of a transfer would be.
+fn refuse<T: Transport<Error = IoError>>(
+    bind: &mut impl FnMut() -> IoResult<T>,
+    request: &Request,
+    client: SocketAddr,
+    error: Error,
+) -> IoError {
+    let packet = to_vec(Packet::new_error(error.error_code, error.message));
+    let sent = bind().and_then(|mut sock| sock.send_to(&packet, client));
+    match sent {
+        Ok(()) => IoError::other(format!(
+            "Refused request for {:?} ({} : \"{}\")",
+            request.filename, error.error_code, error.message
+        )),
+        Err(e) => e,
+    }
+}
+
+/// keeps track of how many transfers are running, in total and per client ip address.
+struct Slots {
+    limits: Limits,
+    running: Mutex<(usize, HashMap<IpAddr, usize>)>,
+}
+
+impl Slots {
+    fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            running: Mutex::new((0, HashMap::new())),
+        }
+    }
+
+    // reserves a slot for a transfer with `client`, returns false if there is no room.
+    fn acquire(&self, client: IpAddr) -> bool {
+        let mut running = self.running.lock().unwrap();
+        let (total, per_client) = &mut *running;
+        let for_client = per_client.entry(client).or_insert(0);
+        if *total >= self.limits.max_transfers
+            || *for_client >= self.limits.max_transfers_per_client
+        {
+            if *for_client == 0 {
+                per_client.remove(&client);
+            }
+            return false;
+        }
+        *total += 1;
+        *for_client += 1;
+        true
+    }
+
+    fn release(&self, client: IpAddr) {
+        let mut running = self.running.lock().unwrap();
+        let (total, per_client) = &mut *running;
+        *total -= 1;
+        if let Some(for_client) = per_client.get_mut(&client) {
+            *for_client -= 1;
+            if *for_client == 0 {
+                per_client.remove(&client);
+            }
+        }
+    }
+}
+
+/// which way the data of a handled request goes.
+enum Direction<R: Read, W: Write, T: Transport> {
+    Read(Transfer<ModeReader<R>, T>),
+    Write(IncomingTransfer<ModeWriter<W>, T>),
+}
+
+/// A transfer created by a [`Handler`] in response to a request, see [`Server::handle_next_request`].
+/// does nothing until it is consumed with the [`finish`](HandledTransfer::finish) method
+///
+/// The transfer runs over a new UDP socket, or over the transport passed to [`Server::handle_request_with`].
+pub struct HandledTransfer<R: Read, W: Write, T: Transport = UdpSocket> {
+    client: SocketAddr,
+    filename: String,
+    direction: Direction<R, W, T>,
+}
+
+impl<R: Read, W: Write, T: Transport<Error = IoError>> HandledTransfer<R, W, T> {
+    /// returns the address of the client this transfer is with.
+    pub fn client(&self) -> SocketAddr {
+        self.client
+    }
+
+    /// returns the filename of the request that started this transfer.
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// returns true if this transfer sends a file to the client.
+    pub fn is_read(&self) -> bool {
+        matches!(self.direction, Direction::Read(_))
+    }
+
+    /// executes the transfer, see [`Transfer::finish`] and [`IncomingTransfer::finish`].
+    pub fn finish(self) -> Result<(), TransferError> {
+        match self.direction {
+            Direction::Read(transfer) => transfer.finish(),
+            // the mode writer itself is the sink, so a trailing `\r` of a netascii transfer is written out once it's complete.
+            Direction::Write(transfer) => transfer.finish_with(|sink| sink).map(|_| ()),
+        }
+    }
 }
 
 /// An in progress transfer between a server and a client
 /// does nothing until it is consumed with the [`finish`](Transfer::finish) method
-
-pub struct Transfer<R: Read> {
-    sock: TFTPSocket,
-    source: DataStream<R>,
+///
+/// This runs a [`transport::Transfer`] over a new UDP socket, or the transport of a [`HandledTransfer`].
+pub struct Transfer<R: Read, T: Transport = UdpSocket> {
+    sock: T,
+    target: SocketAddr,
+    source: R,
     options: OptionAck<'static>,
+    retransmit: RetransmitPolicy,
+    rollover: Option<Rollover>,
 }
 
-impl<R: Read> Transfer<R> {
+impl<R: Read, T: Transport<Error = IoError>> Transfer<R, T> {
     fn new(
         source: R,
-        ip: IpAddr,
+        sock: T,
         target: SocketAddr,
         options: OptionAck<'static>,
-    ) -> IoResult<Self> {
-        Ok(Self {
-            sock: TFTPSocket::new(
-                SocketAddr::new(ip, 0),
-                Some(target),
-                512 + (options.blocksize.unwrap_or(512) as usize),
-            )?,
-            source: DataStream::new(source, options.blocksize.unwrap_or(512)),
+        retransmit: RetransmitPolicy,
+        rollover: Option<Rollover>,
+    ) -> Self {
+        Self {
+            sock,
+            target,
+            source,
+            // a rollover the client asked for is used even if the server wouldn't roll over by itself.
+            rollover: options.rollover.or(rollover),
             options,
-        })
+            retransmit,
+        }
     }
 
-    // checks that `reply` is an ACK packet with block_nr `current_block`
-    fn check_ack(reply: Packet, current_block: u16) -> IoResult<()> {
-        match reply {
-            Packet::Ack(Ack { block_nr: block }) if block == current_block => Ok(()),
-            Packet::Error(e) => Err(IoError::new(
-                std::io::ErrorKind::Other,
-                format!(
-                    "Received TFTP error ({} : \"{}\") while waiting on ({current_block})",
-                    e.error_code, e.message
-                ),
-            )),
-            e => Err(IoError::new(
-                std::io::ErrorKind::InvalidData,
-                format!("Received unexpected packet while waiting on Ack({current_block}): {e:?}"),
-            )),
+    /// executes the transfer.
+    ///
+    /// If a window size was negotiated as described in [RFC-7440](https://www.rfc-editor.org/rfc/rfc7440.html) up to that many blocks are sent
+    /// before waiting for an acknowledgement, otherwise every block is acknowledged before the next one is sent.
+    /// If the client acknowledges only part of a window, the transfer continues from the first block the client is missing.
+    /// Duplicate and delayed acks are ignored rather than answered with the same blocks again, see [`Sender::handle_packet`](crate::machine::Sender::handle_packet).
+    /// Every window that isn't acknowledged in time is sent again, as configured with [`Server::set_retransmit_policy`].
+    /// Packets from any address other than the client's are answered with an unknown transfer ID error.
+    ///
+    ///an error can occur for 5 reasons:
+    /// 1. we have hit an io-error reading the file, returned as [`TransferError::Io`]
+    /// 2. we hit an io-error while doing udp transfers, returned as [`TransferError::Io`] too
+    /// 3. or the client has send us an error packet during the transfer, see [`TransferError::Peer`]
+    /// 4. or the client has send us an invalid reply, see [`TransferError::ProtocolViolation`]
+    /// 5. or the client stopped replying and we ran out of retries, see [`TransferError::TimedOut`]
+    ///
+    /// in the case of 1, 4 and 5, this function will automatically try to send an error packet to the client
+    /// before returning the error. The same goes for files that need more blocks than the block numbers allow, see [`TransferError::TooManyBlocks`].
+    /// in all other cases it will not notify the client. As either the client Explicitly errored out,
+    /// or we're having issues with the underlying UDP and will likely fail sending the error message too.
+    pub fn finish(self) -> Result<(), TransferError> {
+        let mut buffer = vec![0u8; 512 + (self.options.blocksize.unwrap_or(512) as usize)];
+        let mut inner = transport::Transfer::new(
+            self.sock,
+            self.target,
+            transport::ReadSource::new(self.source),
+            self.options,
+            self.retransmit,
+        );
+        inner.set_rollover(self.rollover);
+        inner.finish(&mut buffer).map_err(TransferError::from)
+    }
+}
+
+/// An in progress transfer from a client to the server, created in response to a write request.
+/// does nothing until it is consumed with the [`finish`](IncomingTransfer::finish) method
+///
+/// This runs a [`transport::IncomingTransfer`] over a new UDP socket, or the transport of a [`HandledTransfer`].
+pub struct IncomingTransfer<W: Write, T: Transport = UdpSocket> {
+    sock: T,
+    target: SocketAddr,
+    sink: W,
+    options: OptionAck<'static>,
+    retransmit: RetransmitPolicy,
+    rollover: Option<Rollover>,
+}
+
+impl<W: Write, T: Transport<Error = IoError>> IncomingTransfer<W, T> {
+    fn new(
+        sink: W,
+        sock: T,
+        target: SocketAddr,
+        options: OptionAck<'static>,
+        retransmit: RetransmitPolicy,
+        rollover: Option<Rollover>,
+    ) -> Self {
+        Self {
+            sock,
+            target,
+            sink,
+            rollover: options.rollover.or(rollover),
+            options,
+            retransmit,
         }
     }
 
-    /// executes the transfer.
+    /// executes the transfer, returning the total amount of bytes written to the sink.
+    ///
+    /// The write request is acknowledged with an option acknowledgement if any options were set, or with an ack for block 0 otherwise.
+    /// After that every data packet is written to the sink and acknowledged, until a packet shorter than the blocksize is received.
+    /// If a window size was negotiated as described in [RFC-7440](https://www.rfc-editor.org/rfc/rfc7440.html), only every window is acknowledged instead.
+    /// If the next data packet doesn't arrive in time, the last acknowledgement is sent again as configured with [`Server::set_retransmit_policy`].
+    /// If the client sends a data packet we don't expect, for example because our ack got lost, the last block we received is acknowledged again
+    /// and the packet is ignored. Packets from any address other than the client's are answered with an unknown transfer ID error.
     ///
-    ///an error can occur for 4 reasons:
-    /// 1. we have hit an io-error reading the file,
+    /// an error can occur for 5 reasons, which are told apart by the [`TransferError`] returned:
+    /// 1. we have hit an io-error writing to the sink,
     /// 2. we hit an io-error while doing udp transfers
     /// 3. or the client has send us an error packet during the transfer,
-    /// 4. or the client has send us an invalid reply.
+    /// 4. or the client has send us an invalid packet.
+    /// 5. or the client stopped sending and we ran out of retries.
     ///
-    /// in the case of 1, this function will automatically try to send an error packet to the client
-    /// before returning the initial IO error.
-    /// in all other cases it will not notify the client. As either the client Explicitly errored out, or the client messed up
-    /// or we're having issues with the underlying UDP and will likely fail sending the error message too.
-    pub fn finish(mut self) -> Result<(), IoError> {
-        if !self.options.is_empty() {
-            self.sock.send_message(Packet::OptionAck(self.options))?;
-            let (reply, _) = self.sock.get_next_message_from()?;
-            Self::check_ack(reply, 0)?;
-        }
-        while let Some(bytes) = {
-            match self.source.next_raw() {
-                Ok(x) => x,
-                //if source.next_raw() fails to get bytes, i.e. calling "read" on the underlying source fails,
-                // try to notify the client of the error before returning
-                Err(e) => {
-                    let _may_fail = self.sock.send_message(Packet::new_error(
-                        crate::packet::ErrorCode::NOT_DEFINED,
-                        "Unexpected IO error",
-                    ));
-                    return Err(e);
-                }
+    /// in the case of 1, 4 and 5, this function will automatically try to send an error packet to the client
+    /// before returning the error.
+    pub fn finish(self) -> Result<u64, TransferError> {
+        self.finish_with(transport::WriteSink::new)
+    }
+
+    // executes the transfer, writing to the sink through the data sink `wrap` turns it into.
+    fn finish_with<K: DataSink<Error = IoError>>(
+        self,
+        wrap: impl FnOnce(W) -> K,
+    ) -> Result<u64, TransferError> {
+        let mut buffer = vec![0u8; 512 + (self.options.blocksize.unwrap_or(512) as usize)];
+        let mut inner = transport::IncomingTransfer::new(
+            self.sock,
+            self.target,
+            wrap(self.sink),
+            self.options,
+            self.retransmit,
+        );
+        inner.set_rollover(self.rollover);
+        inner.finish(&mut buffer).map_err(TransferError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+    use crate::{packet::Mode, transport::WriteSink};
+    use std::{num::NonZeroU8, sync::Arc, time::Instant};
+
+    fn localhost() -> Server {
+        Server::connect_with_port(IpAddr::from([127, 0, 0, 1]), 0).unwrap()
+    }
+
+    fn addr(server: &Server) -> SocketAddr {
+        server.sock.sock.local_addr().unwrap()
+    }
+
+    // a handler that serves `FILE` for every read request and collects the data of write requests.
+    #[derive(Default)]
+    struct Memory {
+        written: Arc<Mutex<Vec<u8>>>,
+        // if set, opening "slow" waits for a message on this channel first.
+        gate: Option<Mutex<mpsc::Receiver<()>>>,
+        // the filenames of the transfers that finished successfully.
+        finished: Mutex<Vec<String>>,
+    }
+
+    const FILE: &[u8] = b"served from memory";
+
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> IoResult<()> {
+            Ok(())
+        }
+    }
+
+    impl Handler for Memory {
+        type Reader = &'static [u8];
+        type Writer = Shared;
+
+        fn open_read(
+            &self,
+            request: &Request,
+            _: SocketAddr,
+        ) -> Result<(Self::Reader, Option<u64>), Error<'static>> {
+            if let (Some(gate), "slow") = (&self.gate, request.filename) {
+                gate.lock().unwrap().recv().unwrap();
+            }
+            Ok((FILE, Some(FILE.len() as u64)))
+        }
+
+        fn open_write(&self, _: &Request, _: SocketAddr) -> Result<Self::Writer, Error<'static>> {
+            Ok(Shared(self.written.clone()))
+        }
+
+        fn transfer_finished(
+            &self,
+            filename: &str,
+            _: SocketAddr,
+            result: &Result<(), TransferError>,
+        ) {
+            if result.is_ok() {
+                self.finished.lock().unwrap().push(filename.to_owned());
             }
-        } {
-            self.sock.sock.send(bytes)?;
-            let (reply, _) = self.sock.get_next_message_from()?;
-            Self::check_ack(reply, self.source.last_block())?;
         }
-        Ok(())
+    }
+
+    // sends a read request for `filename` without any options from `sock` to `server`.
+    fn request(sock: &UdpSocket, filename: &str, server: SocketAddr) {
+        let mut buffer = [0u8; 512];
+        let n_bytes = Request::new_read_request(filename, None)
+            .to_bytes(&mut buffer)
+            .unwrap();
+        sock.send_to(&buffer[..n_bytes], server).unwrap();
+    }
+
+    fn client_socket(ip: [u8; 4]) -> UdpSocket {
+        let sock = UdpSocket::bind(SocketAddr::from((ip, 0))).unwrap();
+        sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        sock
+    }
+
+    #[test]
+    fn write_request() {
+        let data: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+        let mut server = localhost();
+        let client = Client::new(addr(&server));
+        std::thread::scope(|scope| {
+            let put = scope.spawn(|| client.put("file", &data[..]));
+            let (request, client) = server.get_next_request_from().unwrap();
+            assert!(request.is_write());
+            assert_eq!(request.filename, "file");
+            let mut received = Vec::new();
+            let transfer = server
+                .create_transfer_from(client, &mut received, OptionAck::new(None, None, None))
+                .unwrap();
+            assert_eq!(transfer.finish().unwrap(), 3000);
+            put.join().unwrap().unwrap();
+            assert_eq!(received, data);
+        });
+    }
+
+    #[test]
+    fn negotiated_timeout() {
+        let mut server = localhost();
+        // without the timeout option the client would have to wait a minute for the option acknowledgement to be sent again.
+        server.set_retransmit_policy(RetransmitPolicy {
+            timeout: Duration::from_secs(60),
+            max_retries: 1,
+            exponential_backoff: false,
+        });
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let mut request = Request::new_read_request("file", None);
+        request.timeout_seconds = NonZeroU8::new(1);
+        let mut buffer = [0u8; 516];
+        let n_bytes = request.to_bytes(&mut buffer).unwrap();
+        client.send_to(&buffer[..n_bytes], addr(&server)).unwrap();
+
+        let policy = server.option_policy();
+        let (request, from) = server.get_next_request_from().unwrap();
+        let options = policy.negotiate(&request, None);
+        let transfer = server
+            .create_transfer_to(from, &b"data"[..], options)
+            .unwrap();
+        let transfer = std::thread::spawn(move || transfer.finish());
+
+        // the client never acknowledges the options, so they're sent again after the negotiated timeout.
+        let mut arrivals = Vec::new();
+        while arrivals.len() < 2 {
+            let n_bytes = client.recv(&mut buffer).unwrap();
+            let Ok(Packet::OptionAck(options)) = Packet::from_bytes(&buffer[..n_bytes]) else {
+                panic!("expected an option acknowledgement");
+            };
+            assert_eq!(options.timeout_seconds, NonZeroU8::new(1));
+            arrivals.push(Instant::now());
+        }
+        let waited = arrivals[1] - arrivals[0];
+        assert!(
+            waited >= Duration::from_millis(900) && waited < Duration::from_secs(10),
+            "{waited:?}"
+        );
+        assert!(matches!(
+            transfer.join().unwrap(),
+            Err(TransferError::TimedOut { attempts: 2 })
+        ));
+    }
+
+    #[test]
+    fn netascii_trailing_carriage_return() {
+        let mut server = localhost();
+        let handler = Memory::default();
+        let mut client = Client::new(addr(&server));
+        client.set_mode(Mode::NetAscii);
+        std::thread::scope(|scope| {
+            let put = scope.spawn(|| {
+                let mut sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+                let mut buffer = [0u8; 516];
+                // put_with sends the data as is, so it can end in a bare `\r`
+                client
+                    .put_with(&mut sock, "file", &b"line\r\n\r"[..], &mut buffer)
+                    .is_ok()
+            });
+            let transfer = server.handle_next_request(&handler).unwrap();
+            transfer.finish().unwrap();
+            assert!(put.join().unwrap());
+        });
+        assert_eq!(*handler.written.lock().unwrap(), b"line\n\r");
+    }
+
+    #[test]
+    fn slots() {
+        let slots = Slots::new(Limits {
+            max_transfers: 3,
+            max_transfers_per_client: 2,
+        });
+        let (a, b, c) = (
+            IpAddr::from([10, 0, 0, 1]),
+            IpAddr::from([10, 0, 0, 2]),
+            IpAddr::from([10, 0, 0, 3]),
+        );
+        assert!(slots.acquire(a));
+        assert!(slots.acquire(a));
+        assert!(!slots.acquire(a));
+        assert!(slots.acquire(b));
+        // every slot is taken
+        assert!(!slots.acquire(b));
+        assert!(!slots.acquire(c));
+        slots.release(a);
+        assert!(slots.acquire(b));
+        assert!(!slots.acquire(a));
+        slots.release(b);
+        slots.release(b);
+        slots.release(a);
+        assert_eq!(*slots.running.lock().unwrap(), (0, HashMap::new()));
+    }
+
+    #[test]
+    fn transfer_socket_fails() {
+        // nothing can be bound to an address of a documentation network, so creating the transfer fails after the handler opened the file.
+        let ip = IpAddr::from([192, 0, 2, 1]);
+        let client = SocketAddr::from(([127, 0, 0, 1], 4000));
+        let request = Request::new_read_request("file", None);
+        let handled = handle_request(
+            localhost().policies(),
+            &mut bind_to(ip),
+            &request,
+            client,
+            &Memory::default(),
+        );
+        assert!(matches!(handled, Err(Unhandled::Failed(_))));
+    }
+
+    #[test]
+    fn serve_returns_on_socket_errors() {
+        let mut server = localhost();
+        server
+            .sock
+            .sock
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let (done, served) = mpsc::channel();
+        std::thread::spawn(move || done.send(server.serve(&Memory::default())));
+        let result = served.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(matches!(
+            result.unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+        ));
+    }
+
+    #[test]
+    fn serve() {
+        let mut server = localhost();
+        server.set_limits(Limits {
+            max_transfers: 4,
+            max_transfers_per_client: 1,
+        });
+        // serve returns once no requests arrive for a while, after finishing the running transfers.
+        server
+            .sock
+            .sock
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let server_addr = addr(&server);
+        let (open, gate) = mpsc::channel();
+        let handler = Memory {
+            gate: Some(Mutex::new(gate)),
+            ..Memory::default()
+        };
+        std::thread::scope(|scope| {
+            let served = scope.spawn(|| server.serve(&handler));
+            let slow = client_socket([127, 0, 0, 1]);
+            request(&slow, "slow", server_addr);
+
+            // the handler is still opening "slow", but a client on another address is served right away.
+            let mut other = client_socket([127, 0, 0, 2]);
+            let mut buffer = [0u8; 516];
+            let mut received = Vec::new();
+            let got = Client::new(server_addr).get_with(
+                &mut other,
+                "file",
+                WriteSink::new(&mut received),
+                &mut buffer,
+            );
+            assert!(got.is_ok());
+            assert_eq!(received, FILE);
+
+            // the client of "slow" has used up its transfers, so it is told to try again later from a new port.
+            let busy = client_socket([127, 0, 0, 1]);
+            request(&busy, "file", server_addr);
+            let (n_bytes, from) = busy.recv_from(&mut buffer).unwrap();
+            assert_ne!(from, server_addr);
+            assert!(matches!(
+                Packet::from_bytes(&buffer[..n_bytes]),
+                Ok(Packet::Error(Error {
+                    error_code: ErrorCode::NOT_DEFINED,
+                    ..
+                }))
+            ));
+
+            open.send(()).unwrap();
+            let (n_bytes, from) = slow.recv_from(&mut buffer).unwrap();
+            assert!(
+                matches!(Packet::from_bytes(&buffer[..n_bytes]), Ok(Packet::Data(data)) if data.data == FILE)
+            );
+            slow.send_to(&[0, 4, 0, 1], from).unwrap();
+            assert!(served.join().unwrap().is_err());
+        });
+        assert_eq!(*handler.finished.lock().unwrap(), ["file", "slow"]);
     }
 }
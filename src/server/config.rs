@@ -0,0 +1,590 @@
+use super::{
+    fs::{split_filename, RootDirectory},
+    Handler, Limits, Server,
+};
+use crate::{
+    options::OptionPolicy,
+    packet::{Error, ErrorCode, OptionAck, Request},
+};
+use serde::Deserialize;
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{Error as IoError, ErrorKind, Result as IoResult, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    num::NonZeroU16,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The configuration of a server serving one or more directories, usually loaded from a TOML file with [`Config::load`].
+///
+/// ```toml
+/// listen = "0.0.0.0:69"
+///
+/// [limits]
+/// max_transfers = 32
+/// max_transfers_per_client = 4
+///
+/// # option limits shared by all roots, each root can override them in its own `options` table.
+/// [options]
+/// max_blocksize = 1468
+/// max_timeout = 10
+/// max_window_size = 16  # 0 disables the windowsize option
+///
+/// [[root]]
+/// path = "/srv/tftp/boot"
+///
+/// [[root]]
+/// path = "/srv/tftp/uploads"
+/// prefix = "uploads"      # requests for `uploads/<file>` are served from this root
+/// writable = true
+/// max_file_size = 104857600
+/// allow = ["10.0.0.0/8"]  # only these clients may use this root
+/// deny = ["10.0.0.13"]    # except for these
+/// options = { max_blocksize = 512 }
+/// ```
+///
+/// Every part of the file is optional except for the roots. Unknown keys are refused, so typos don't go unnoticed.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// the address the server listens on, `0.0.0.0:69` by default.
+    pub listen: SocketAddr,
+    /// how many transfers the server runs at once.
+    pub limits: Limits,
+    /// the served directories, ordered from the longest prefix to the shortest.
+    pub roots: Vec<Root>,
+}
+
+/// A directory served by a [`ConfigHandler`], and the policy for requests for the files in it.
+#[derive(Debug, Clone)]
+pub struct Root {
+    /// the directory being served.
+    pub path: PathBuf,
+    /// the path requests have to start with to be served from this root, its directories separated by single `/`s
+    /// and without leading or trailing ones. Requested filenames are matched against it a directory at a time, the way [`RootDirectory`] splits them.
+    /// Empty for the root serving every request that no other root matches.
+    pub prefix: String,
+    /// whether clients may write new files to this root.
+    pub writable: bool,
+    /// the largest file clients may write to this root.
+    pub max_file_size: Option<u64>,
+    /// which options requested by clients are acknowledged for transfers of the files in this root.
+    pub options: OptionPolicy,
+    /// if not empty, only clients in one of these ranges may use this root.
+    pub allow: Vec<IpRange>,
+    /// clients in these ranges may not use this root, even if they're allowed by `allow`.
+    pub deny: Vec<IpRange>,
+}
+
+/// A range of ip addresses, written as an address followed by the length of the network prefix like `192.168.0.0/16`.
+/// A single address is a range of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    /// returns true if `ip` is in this range. IPv4 addresses mapped to IPv6 are compared as IPv4 addresses.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (network, prefix_len) = s.split_once('/').unwrap_or((s, ""));
+        let network = network
+            .parse::<IpAddr>()
+            .map_err(|_| format!("{network:?} is not an ip address"))?
+            .to_canonical();
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = if prefix_len.is_empty() {
+            max_len
+        } else {
+            prefix_len
+                .parse()
+                .ok()
+                .filter(|&len| len <= max_len)
+                .ok_or_else(|| {
+                    format!("the prefix length of {s:?} has to be between 0 and {max_len}")
+                })?
+        };
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+impl Display for IpRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+impl Config {
+    /// reads and checks the configuration in the TOML file at `path`.
+    ///
+    /// Returns an [`InvalidData`](ErrorKind::InvalidData) error describing the problem if the file isn't a valid configuration.
+    /// The served directories are only checked when creating the [handler](Config::handler).
+    pub fn load(path: impl AsRef<Path>) -> IoResult<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| IoError::new(e.kind(), format!("can't read {path:?}: {e}")))?;
+        text.parse()
+            .map_err(|e: IoError| IoError::new(e.kind(), format!("{path:?}: {e}")))
+    }
+
+    /// creates a server listening on the configured address, with the configured limits.
+    pub fn server(&self) -> IoResult<Server> {
+        let mut server = Server::connect_with_port(self.listen.ip(), self.listen.port())?;
+        server.set_limits(self.limits);
+        // the handler narrows the options down for every root, so every option is allowed here.
+        server.set_option_policy(OptionPolicy::default());
+        Ok(server)
+    }
+
+    /// creates the handler answering requests as configured. Fails if one of the roots isn't an existing directory.
+    pub fn handler(&self) -> IoResult<ConfigHandler> {
+        let roots = self
+            .roots
+            .iter()
+            .map(|root| {
+                let mut directory = RootDirectory::new(&root.path).map_err(|e| {
+                    IoError::new(e.kind(), format!("can't serve {:?}: {e}", root.path))
+                })?;
+                directory.set_writable(root.writable);
+                Ok((root.clone(), directory))
+            })
+            .collect::<IoResult<_>>()?;
+        Ok(ConfigHandler { roots })
+    }
+}
+
+impl FromStr for Config {
+    type Err = IoError;
+
+    /// parses and checks a configuration, see [`Config::load`].
+    fn from_str(s: &str) -> IoResult<Self> {
+        let invalid = |message: String| IoError::new(ErrorKind::InvalidData, message);
+        let file: ConfigFile = toml::from_str(s).map_err(|e| invalid(e.to_string()))?;
+        if file.limits.max_transfers == 0 || file.limits.max_transfers_per_client == 0 {
+            return Err(invalid("limits: transfers have to be allowed".into()));
+        }
+        if file.root.is_empty() {
+            return Err(invalid("at least one [[root]] has to be configured".into()));
+        }
+        let defaults = file
+            .options
+            .apply(OptionPolicy::default())
+            .map_err(|e| invalid(format!("options: {e}")))?;
+        let mut roots = Vec::<Root>::new();
+        for (i, root) in file.root.into_iter().enumerate() {
+            let context = |e: String| invalid(format!("root {} ({:?}): {e}", i + 1, root.path));
+            let prefix = split_filename(&root.prefix).collect::<Vec<_>>().join("/");
+            if roots.iter().any(|other| other.prefix == prefix) {
+                return Err(context(format!(
+                    "another root already has prefix {prefix:?}"
+                )));
+            }
+            let parse_ranges = |ranges: &[String]| {
+                ranges
+                    .iter()
+                    .map(|range| range.parse().map_err(context))
+                    .collect::<IoResult<Vec<IpRange>>>()
+            };
+            roots.push(Root {
+                options: root
+                    .options
+                    .apply(defaults)
+                    .map_err(|e| context(format!("options: {e}")))?,
+                allow: parse_ranges(&root.allow)?,
+                deny: parse_ranges(&root.deny)?,
+                path: root.path,
+                prefix,
+                writable: root.writable,
+                max_file_size: root.max_file_size,
+            });
+        }
+        // the most specific root is picked when the prefixes of several of them match.
+        roots.sort_by_key(|root| std::cmp::Reverse(root.prefix.len()));
+        Ok(Self {
+            listen: file.listen,
+            limits: Limits {
+                max_transfers: file.limits.max_transfers,
+                max_transfers_per_client: file.limits.max_transfers_per_client,
+            },
+            roots,
+        })
+    }
+}
+
+/// the configuration as written in the file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default = "default_listen")]
+    listen: SocketAddr,
+    #[serde(default)]
+    limits: LimitsFile,
+    #[serde(default)]
+    options: OptionsFile,
+    #[serde(default)]
+    root: Vec<RootFile>,
+}
+
+fn default_listen() -> SocketAddr {
+    SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 69)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct LimitsFile {
+    max_transfers: usize,
+    max_transfers_per_client: usize,
+}
+
+impl Default for LimitsFile {
+    fn default() -> Self {
+        let limits = Limits::default();
+        Self {
+            max_transfers: limits.max_transfers,
+            max_transfers_per_client: limits.max_transfers_per_client,
+        }
+    }
+}
+
+/// option limits, any that aren't set are taken from the enclosing table.
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+struct OptionsFile {
+    min_blocksize: Option<u16>,
+    max_blocksize: Option<u16>,
+    min_timeout: Option<u8>,
+    max_timeout: Option<u8>,
+    max_window_size: Option<u16>,
+    transfer_size: Option<bool>,
+    rollover: Option<bool>,
+}
+
+impl OptionsFile {
+    // overrides the limits in `policy` with the ones set here.
+    fn apply(self, policy: OptionPolicy) -> Result<OptionPolicy, String> {
+        let policy = OptionPolicy {
+            min_blocksize: self.min_blocksize.unwrap_or(policy.min_blocksize),
+            max_blocksize: self.max_blocksize.unwrap_or(policy.max_blocksize),
+            min_timeout: self.min_timeout.unwrap_or(policy.min_timeout),
+            max_timeout: self.max_timeout.unwrap_or(policy.max_timeout),
+            max_window_size: self
+                .max_window_size
+                .map_or(policy.max_window_size, NonZeroU16::new),
+            transfer_size: self.transfer_size.unwrap_or(policy.transfer_size),
+            rollover: self.rollover.unwrap_or(policy.rollover),
+        };
+        let blocksizes = 8..=65464;
+        if !blocksizes.contains(&policy.min_blocksize)
+            || !blocksizes.contains(&policy.max_blocksize)
+        {
+            Err("blocksizes have to be between 8 and 65464".into())
+        } else if policy.min_blocksize > policy.max_blocksize {
+            Err("min_blocksize is larger than max_blocksize".into())
+        } else if policy.min_timeout == 0 {
+            Err("min_timeout has to be at least 1".into())
+        } else if policy.min_timeout > policy.max_timeout {
+            Err("min_timeout is larger than max_timeout".into())
+        } else {
+            Ok(policy)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RootFile {
+    path: PathBuf,
+    #[serde(default)]
+    prefix: String,
+    #[serde(default)]
+    writable: bool,
+    max_file_size: Option<u64>,
+    #[serde(default)]
+    options: OptionsFile,
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+/// A [`Handler`] serving the roots of a [`Config`], created with [`Config::handler`].
+///
+/// Requests are served from the root with the longest prefix matching the requested filename, with the prefix removed.
+/// Clients that aren't allowed to use that root are refused with an access violation,
+/// files larger than the maximum file size of the root are refused while they're written.
+pub struct ConfigHandler {
+    roots: Vec<(Root, RootDirectory)>,
+}
+
+impl ConfigHandler {
+    // finds the root serving `request`, and the filename inside of it.
+    // Filenames are compared a directory at a time, so `./uploads/x` and `uploads\x` are served from the `uploads` root too.
+    fn find(&self, request: &Request) -> Option<(&Root, &RootDirectory, String)> {
+        let names: Vec<&str> = split_filename(request.filename).collect();
+        self.roots.iter().find_map(|(root, directory)| {
+            let prefix: Vec<&str> = split_filename(&root.prefix).collect();
+            let rest = names.strip_prefix(prefix.as_slice())?;
+            Some((root, directory, rest.join("/")))
+        })
+    }
+
+    // finds the root serving `request`, if `client` may use it.
+    fn root(
+        &self,
+        request: &Request,
+        client: SocketAddr,
+    ) -> Result<(&Root, &RootDirectory, String), Error<'static>> {
+        let (root, directory, filename) = self
+            .find(request)
+            .ok_or(Error::new(ErrorCode::FILE_NOT_FOUND, "File not found"))?;
+        let ip = client.ip();
+        if root.deny.iter().any(|range| range.contains(ip))
+            || (!root.allow.is_empty() && !root.allow.iter().any(|range| range.contains(ip)))
+        {
+            return Err(Error::new(ErrorCode::ACCESS_VIOLATION, "Access denied"));
+        }
+        Ok((root, directory, filename))
+    }
+}
+
+impl Handler for ConfigHandler {
+    type Reader = File;
+    type Writer = LimitedWriter<File>;
+
+    fn open_read(
+        &self,
+        request: &Request,
+        client: SocketAddr,
+    ) -> Result<(File, Option<u64>), Error<'static>> {
+        let (_, directory, filename) = self.root(request, client)?;
+        let mut request = request.clone();
+        request.filename = &filename;
+        directory.open_read(&request, client)
+    }
+
+    fn open_write(
+        &self,
+        request: &Request,
+        client: SocketAddr,
+    ) -> Result<LimitedWriter<File>, Error<'static>> {
+        let (root, directory, filename) = self.root(request, client)?;
+        if let (Some(size), Some(max)) = (request.transfer_size, root.max_file_size) {
+            if size > max {
+                return Err(Error::new(
+                    ErrorCode::DISK_FULL_OR_ALLOCATION_EXCEEDED,
+                    "File too large",
+                ));
+            }
+        }
+        let mut request = request.clone();
+        request.filename = &filename;
+        let file = directory.open_write(&request, client)?;
+        Ok(LimitedWriter {
+            inner: file,
+            remaining: root.max_file_size,
+        })
+    }
+
+    fn negotiate_options(
+        &self,
+        request: &Request,
+        _client: SocketAddr,
+        options: &mut OptionAck<'static>,
+    ) {
+        if let Some((root, ..)) = self.find(request) {
+            *options = root.options.negotiate(request, options.transfer_size);
+        }
+    }
+}
+
+/// A writer refusing to write more than a maximum amount of bytes, see [`Root::max_file_size`].
+pub struct LimitedWriter<W: Write> {
+    inner: W,
+    remaining: Option<u64>,
+}
+
+impl<W: Write> Write for LimitedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        if let Some(remaining) = self.remaining {
+            if buf.len() as u64 > remaining {
+                return Err(IoError::new(ErrorKind::FileTooLarge, "File too large"));
+            }
+        }
+        let n_bytes = self.inner.write(buf)?;
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= n_bytes as u64;
+        }
+        Ok(n_bytes)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        listen = "127.0.0.1:6969"
+
+        [options]
+        max_blocksize = 1468
+        max_window_size = 0
+
+        [[root]]
+        path = "boot"
+
+        [[root]]
+        path = "uploads"
+        prefix = "/uploads/"
+        writable = true
+        max_file_size = 4
+        allow = ["10.0.0.0/8", "::1"]
+        deny = ["10.0.0.13"]
+        options = { max_blocksize = 512, max_window_size = 8 }
+    "#;
+
+    #[test]
+    fn parse() {
+        let config: Config = CONFIG.parse().unwrap();
+        assert_eq!(config.listen, "127.0.0.1:6969".parse().unwrap());
+        assert_eq!(config.limits, Limits::default());
+        let [uploads, boot] = &config.roots[..] else {
+            panic!("expected 2 roots");
+        };
+        assert_eq!((boot.prefix.as_str(), boot.writable), ("", false));
+        assert_eq!(boot.options.max_blocksize, 1468);
+        assert_eq!(boot.options.max_window_size, None);
+        assert_eq!(uploads.prefix, "uploads");
+        assert_eq!(uploads.options.max_blocksize, 512);
+        assert_eq!(uploads.options.max_window_size, NonZeroU16::new(8));
+
+        let allowed = |ip: &str| {
+            let ip = ip.parse().unwrap();
+            uploads.allow.iter().any(|range| range.contains(ip))
+                && !uploads.deny.iter().any(|range| range.contains(ip))
+        };
+        assert!(allowed("10.1.2.3"));
+        assert!(allowed("::ffff:10.1.2.3"));
+        assert!(allowed("::1"));
+        assert!(!allowed("10.0.0.13"));
+        assert!(!allowed("11.0.0.1"));
+    }
+
+    #[test]
+    fn invalid() {
+        let error = |config: &str| config.parse::<Config>().unwrap_err().to_string();
+        assert!(error("").contains("at least one [[root]]"));
+        assert!(
+            error("[[root]]\npath = \"a\"\nwritabel = true").contains("unknown field `writabel`")
+        );
+        assert!(
+            error("[[root]]\npath = \"a\"\n[[root]]\npath = \"b\"\nprefix = \"/\"")
+                .contains("root 2 (\"b\"): another root already has prefix \"\"")
+        );
+        assert!(error("[[root]]\npath = \"a\"\ndeny = [\"10.0.0.0/33\"]")
+            .contains("the prefix length of \"10.0.0.0/33\" has to be between 0 and 32"));
+        assert!(
+            error("[options]\nmin_timeout = 5\nmax_timeout = 2\n[[root]]\npath = \"a\"")
+                .contains("options: min_timeout is larger than max_timeout")
+        );
+        assert!(
+            error("[[root]]\npath = \"a\"\noptions = { max_blocksize = 4 }")
+                .contains("root 1 (\"a\"): options: blocksizes have to be between 8 and 65464")
+        );
+    }
+
+    #[test]
+    fn nested_roots() {
+        let dir =
+            std::env::temp_dir().join(format!("simple-tftp-nested-roots-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for root in ["boot", "uploads", "private"] {
+            std::fs::create_dir_all(dir.join(root)).unwrap();
+            std::fs::write(dir.join(root).join("x"), root).unwrap();
+        }
+        let config: Config = format!(
+            "[[root]]\npath = {:?}\n\
+             [[root]]\npath = {:?}\nprefix = \"uploads\"\n\
+             [[root]]\npath = {:?}\nprefix = \"uploads//private/\"\ndeny = [\"127.0.0.1\"]\n",
+            dir.join("boot"),
+            dir.join("uploads"),
+            dir.join("private"),
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(config.roots[0].prefix, "uploads/private");
+        let handler = config.handler().unwrap();
+        let client = SocketAddr::from(([127, 0, 0, 1], 1234));
+        let read = |filename: &str| {
+            let request = Request::new_read_request(filename, None);
+            handler
+                .open_read(&request, client)
+                .map(|(file, _)| std::io::read_to_string(file).unwrap())
+        };
+
+        for filename in ["x", "/x", "./x"] {
+            assert_eq!(read(filename).unwrap(), "boot", "{filename}");
+        }
+        for filename in ["uploads/x", "./uploads/x", "uploads\\x", "//uploads/./x"] {
+            assert_eq!(read(filename).unwrap(), "uploads", "{filename}");
+        }
+        // however the request is spelled, the deny list of the nested root applies.
+        for filename in [
+            "uploads/private/x",
+            "./uploads/private/x",
+            "uploads\\private\\x",
+            "uploads//./private/x",
+        ] {
+            assert_eq!(
+                read(filename).unwrap_err().error_code,
+                ErrorCode::ACCESS_VIOLATION,
+                "{filename}"
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn limited_writer() {
+        let mut writer = LimitedWriter {
+            inner: Vec::new(),
+            remaining: Some(4),
+        };
+        writer.write_all(b"abc").unwrap();
+        assert_eq!(
+            writer.write_all(b"de").unwrap_err().kind(),
+            ErrorKind::FileTooLarge
+        );
+        assert_eq!(writer.inner, b"abc");
+    }
+}
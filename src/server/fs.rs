@@ -0,0 +1,199 @@
+use super::Handler;
+use crate::{
+    netascii,
+    packet::{Error, ErrorCode, Mode, Request},
+};
+use std::{
+    fs::File,
+    io::{ErrorKind, Seek},
+    net::SocketAddr,
+    path::Path,
+};
+
+mod safe_root;
+#[cfg(feature = "config")]
+pub(crate) use safe_root::split_filename;
+pub use safe_root::SafeRoot;
+
+/// A [`Handler`] that serves the files in a directory, and optionally accepts new files written to it.
+///
+/// Requested filenames are interpreted relative to the directory, leading `/`s are ignored and `\`s separate directories like `/`s do.
+/// Requests for anything outside of the directory, for example using `..` or symlinks, are refused as if the file doesn't exist.
+/// Symlinks that stay inside the directory are followed. See [`SafeRoot`] for the details.
+/// Written files are never allowed to overwrite existing ones.
+pub struct RootDirectory {
+    root: SafeRoot,
+    writable: bool,
+}
+
+impl RootDirectory {
+    /// creates a handler that serves the files in `root`, which has to be an existing directory. Write requests are refused by default.
+    pub fn new(root: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self {
+            root: SafeRoot::new(root)?,
+            writable: false,
+        })
+    }
+
+    /// if set, write requests for files that don't exist yet are accepted.
+    pub fn set_writable(&mut self, writable: bool) {
+        self.writable = writable;
+    }
+
+    /// returns the directory being served, after resolving any symlinks.
+    pub fn root(&self) -> &Path {
+        self.root.path()
+    }
+}
+
+impl Handler for RootDirectory {
+    type Reader = File;
+    type Writer = File;
+
+    fn open_read(
+        &self,
+        request: &Request,
+        _client: SocketAddr,
+    ) -> Result<(File, Option<u64>), Error<'static>> {
+        let mut file = self.root.open(request.filename).map_err(io_error)?;
+        let size = if request.transfer_size.is_none() {
+            None
+        } else {
+            match request.mode {
+                Mode::Octet => file.metadata().map(|md| md.len()).ok(),
+                // line endings grow while translating them, so we have to read the whole file to know its size.
+                Mode::NetAscii => netascii::translated_len(&file)
+                    .ok()
+                    .filter(|_| file.rewind().is_ok()),
+            }
+        };
+        Ok((file, size))
+    }
+
+    fn open_write(&self, request: &Request, _client: SocketAddr) -> Result<File, Error<'static>> {
+        if !self.writable {
+            return Err(Error::new(
+                ErrorCode::ACCESS_VIOLATION,
+                "Writing files is not allowed",
+            ));
+        }
+        self.root.create(request.filename).map_err(io_error)
+    }
+}
+
+const NOT_FOUND: Error<'static> = Error {
+    error_code: ErrorCode::FILE_NOT_FOUND,
+    message: "File not found",
+};
+
+// turns an io error opening a file into the error packet sent to the client.
+fn io_error(error: std::io::Error) -> Error<'static> {
+    match error.kind() {
+        ErrorKind::NotFound | ErrorKind::InvalidFilename => NOT_FOUND,
+        ErrorKind::AlreadyExists => {
+            Error::new(ErrorCode::FILE_ALREADY_EXISTS, "File already exists")
+        }
+        ErrorKind::PermissionDenied => Error::new(ErrorCode::ACCESS_VIOLATION, "Access denied"),
+        _ => Error::new(ErrorCode::NOT_DEFINED, "Unexpected IO error"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io::Read, path::PathBuf};
+
+    const CLIENT: SocketAddr =
+        SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 4000);
+
+    // a directory holding a single text file. Escaping it is up to `SafeRoot`, which has its own tests.
+    fn served(name: &str) -> (PathBuf, RootDirectory) {
+        let dir =
+            std::env::temp_dir().join(format!("simple-tftp-fs-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "line\n").unwrap();
+        let handler = RootDirectory::new(&dir).unwrap();
+        (dir, handler)
+    }
+
+    fn read(
+        handler: &RootDirectory,
+        filename: &str,
+        mode: Mode,
+        transfer_size: bool,
+    ) -> Result<(String, Option<u64>), ErrorCode> {
+        let mut request = Request::new_read_request(filename, None);
+        request.mode = mode;
+        request.transfer_size = transfer_size.then_some(0);
+        let (mut file, size) = handler
+            .open_read(&request, CLIENT)
+            .map_err(|e| e.error_code)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        Ok((contents, size))
+    }
+
+    #[test]
+    fn transfer_size() {
+        let (dir, handler) = served("tsize");
+        assert_eq!(
+            read(&handler, "a.txt", Mode::Octet, true),
+            Ok(("line\n".into(), Some(5)))
+        );
+        // the file is read again from the start after computing its size, the server translates it while sending.
+        assert_eq!(
+            read(&handler, "a.txt", Mode::NetAscii, true),
+            Ok(("line\n".into(), Some(6)))
+        );
+        assert_eq!(
+            read(&handler, "a.txt", Mode::NetAscii, false),
+            Ok(("line\n".into(), None))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_requests() {
+        let (dir, mut handler) = served("write");
+        let request = Request::new_write_request("new.txt", None);
+        let refused = handler.open_write(&request, CLIENT).err();
+        assert_eq!(
+            refused.map(|e| e.error_code),
+            Some(ErrorCode::ACCESS_VIOLATION)
+        );
+        assert!(!dir.join("new.txt").exists());
+
+        handler.set_writable(true);
+        handler.open_write(&request, CLIENT).unwrap();
+        assert!(dir.join("new.txt").is_file());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn error_codes() {
+        let (dir, mut handler) = served("errors");
+        handler.set_writable(true);
+        assert_eq!(
+            read(&handler, "missing", Mode::Octet, false),
+            Err(ErrorCode::FILE_NOT_FOUND)
+        );
+        let request = Request::new_write_request("a.txt", None);
+        let refused = handler.open_write(&request, CLIENT).err();
+        assert_eq!(
+            refused.map(|e| e.error_code),
+            Some(ErrorCode::FILE_ALREADY_EXISTS)
+        );
+        fs::remove_dir_all(dir).unwrap();
+
+        for (kind, error_code) in [
+            (ErrorKind::NotFound, ErrorCode::FILE_NOT_FOUND),
+            (ErrorKind::InvalidFilename, ErrorCode::FILE_NOT_FOUND),
+            (ErrorKind::AlreadyExists, ErrorCode::FILE_ALREADY_EXISTS),
+            (ErrorKind::PermissionDenied, ErrorCode::ACCESS_VIOLATION),
+            (ErrorKind::Other, ErrorCode::NOT_DEFINED),
+        ] {
+            assert_eq!(io_error(kind.into()).error_code, error_code, "{kind:?}");
+        }
+    }
+}
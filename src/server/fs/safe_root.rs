@@ -0,0 +1,352 @@
+use std::{
+    fs::File,
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    path::{Path, PathBuf},
+};
+
+/// A directory that files are only ever opened beneath, whatever filename a client sends.
+///
+/// Filenames are split on both `/` and `\`, leading separators and `.` components are ignored.
+/// Names containing `..` components, control characters or a drive letter like `C:` are refused with an
+/// [`InvalidFilename`](ErrorKind::InvalidFilename) error before touching the file system.
+///
+/// On unix the file is opened one component at a time relative to the already opened parent directory, never following symlinks
+/// on the way. Symlinks are resolved by hand instead, so the ones staying inside the root work, while ones leading outside of it
+/// are refused with a [`NotFound`](ErrorKind::NotFound) error. Swapping a directory for a symlink while the file is being opened
+/// can't be used to escape the root either. Elsewhere the path is resolved with [`canonicalize`](Path::canonicalize) and compared to the root,
+/// which can be raced by someone who can create symlinks inside the root.
+#[derive(Debug)]
+pub struct SafeRoot {
+    path: PathBuf,
+    #[cfg(unix)]
+    dir: std::os::fd::OwnedFd,
+}
+
+impl SafeRoot {
+    /// opens the directory `root`, which has to exist.
+    pub fn new(root: impl AsRef<Path>) -> IoResult<Self> {
+        let path = root.as_ref().canonicalize()?;
+        if !path.is_dir() {
+            return Err(IoError::new(
+                ErrorKind::NotADirectory,
+                format!("{path:?} is not a directory"),
+            ));
+        }
+        Ok(Self {
+            #[cfg(unix)]
+            dir: File::open(&path)?.into(),
+            path,
+        })
+    }
+
+    /// returns the path of the root, after resolving any symlinks.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// opens the regular file `filename` beneath the root for reading.
+    pub fn open(&self, filename: &str) -> IoResult<File> {
+        let file = self.resolve(&components(filename)?, false)?;
+        if !file.metadata()?.is_file() {
+            return Err(IoError::new(ErrorKind::NotFound, "not a regular file"));
+        }
+        Ok(file)
+    }
+
+    /// creates the file `filename` beneath the root for writing. Fails if it exists already, also if it's a symlink.
+    /// The directory it is created in has to exist.
+    pub fn create(&self, filename: &str) -> IoResult<File> {
+        self.resolve(&components(filename)?, true)
+    }
+
+    #[cfg(unix)]
+    fn resolve(&self, components: &[&str], create: bool) -> IoResult<File> {
+        use std::{
+            collections::VecDeque,
+            ffi::CString,
+            os::fd::{AsRawFd, FromRawFd, OwnedFd},
+        };
+
+        // symlinks pointing to each other are given up on after as many of them as linux follows.
+        const MAX_LINKS: usize = 40;
+
+        let mut remaining: VecDeque<Vec<u8>> = components
+            .iter()
+            .map(|name| name.as_bytes().to_vec())
+            .collect();
+        // the directories opened beneath the root, the last one is the directory the next component is opened in.
+        let mut dirs: Vec<OwnedFd> = Vec::new();
+        let mut links = 0;
+        while let Some(name) = remaining.pop_front() {
+            match name.as_slice() {
+                b"" | b"." => continue,
+                b".." => {
+                    dirs.pop().ok_or_else(outside_root)?;
+                    continue;
+                }
+                _ => {}
+            }
+            let dir = dirs.last().unwrap_or(&self.dir).as_raw_fd();
+            let is_last = remaining.is_empty();
+            let name = CString::new(name).map_err(|_| invalid_filename())?;
+            let flags = match (is_last, create) {
+                (false, _) => libc::O_RDONLY | libc::O_DIRECTORY,
+                (true, false) => libc::O_RDONLY | libc::O_NONBLOCK,
+                (true, true) => libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL,
+            };
+            // SAFETY: `dir` is an open directory and `name` is nul-terminated.
+            let fd = unsafe {
+                libc::openat(
+                    dir,
+                    name.as_ptr(),
+                    flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                    0o644 as libc::c_uint,
+                )
+            };
+            if fd >= 0 {
+                // SAFETY: `fd` was just opened and isn't owned by anything else.
+                let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+                if is_last {
+                    return Ok(fd.into());
+                }
+                dirs.push(fd);
+                continue;
+            }
+            let error = IoError::last_os_error();
+            // new files are never created through a symlink, even if it points inside the root.
+            let target = match (is_last && create, read_link_at(dir, &name)) {
+                (false, Some(target)) => target,
+                _ => return Err(error),
+            };
+            links += 1;
+            if links > MAX_LINKS {
+                return Err(IoError::from_raw_os_error(libc::ELOOP));
+            }
+            if target.starts_with(b"/") {
+                return Err(outside_root());
+            }
+            for name in target.split(|&byte| byte == b'/').rev() {
+                remaining.push_front(name.to_vec());
+            }
+        }
+        // the filename ends in the root or one of its directories.
+        Err(IoError::new(ErrorKind::NotFound, "not a regular file"))
+    }
+
+    #[cfg(not(unix))]
+    fn resolve(&self, components: &[&str], create: bool) -> IoResult<File> {
+        let requested = components.iter().collect::<PathBuf>();
+        let requested = self.path.join(requested);
+        let inside = |path: &Path| {
+            path.canonicalize()
+                .ok()
+                .filter(|path| path.starts_with(&self.path))
+                .ok_or_else(outside_root)
+        };
+        if create {
+            let (Some(parent), Some(name)) = (requested.parent(), requested.file_name()) else {
+                return Err(invalid_filename());
+            };
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(inside(parent)?.join(name))
+        } else {
+            File::open(inside(&requested)?)
+        }
+    }
+}
+
+// reads the target of the symlink `name` in `dir`, returns `None` if it isn't a symlink.
+#[cfg(unix)]
+fn read_link_at(dir: std::os::fd::RawFd, name: &std::ffi::CStr) -> Option<Vec<u8>> {
+    let mut target = vec![0u8; libc::PATH_MAX as usize];
+    // SAFETY: `name` is nul-terminated and `target` is writable for its whole length.
+    let n_bytes =
+        unsafe { libc::readlinkat(dir, name.as_ptr(), target.as_mut_ptr().cast(), target.len()) };
+    let n_bytes = usize::try_from(n_bytes).ok()?;
+    target.truncate(n_bytes);
+    Some(target)
+}
+
+/// splits `filename` on both `/` and `\\`, leaving out empty and `.` components, without checking the names it is made of.
+pub(crate) fn split_filename(filename: &str) -> impl Iterator<Item = &str> {
+    filename
+        .split(['/', '\\'])
+        .filter(|name| !name.is_empty() && *name != ".")
+}
+
+// splits `filename` into the names of the directories leading up to the file and the name of the file itself.
+fn components(filename: &str) -> IoResult<Vec<&str>> {
+    if filename.chars().any(char::is_control) {
+        return Err(invalid_filename());
+    }
+    let components: Vec<&str> = split_filename(filename).collect();
+    let has_drive = |name: &str| {
+        let mut chars = name.chars();
+        matches!((chars.next(), chars.next()), (Some(drive), Some(':')) if drive.is_ascii_alphabetic())
+    };
+    match components.first() {
+        None => Err(invalid_filename()),
+        Some(first) if has_drive(first) => Err(invalid_filename()),
+        Some(_) if components.contains(&"..") => Err(invalid_filename()),
+        Some(_) => Ok(components),
+    }
+}
+
+fn invalid_filename() -> IoError {
+    IoError::new(ErrorKind::InvalidFilename, "invalid filename")
+}
+
+fn outside_root() -> IoError {
+    IoError::new(ErrorKind::NotFound, "outside of the root")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::{fs, io::Read, os::unix::fs::symlink};
+
+    // a fresh directory with a root inside it, and a secret file next to the root.
+    fn setup(name: &str) -> (PathBuf, SafeRoot) {
+        let dir = std::env::temp_dir().join(format!(
+            "simple-tftp-safe-root-{name}-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("sub/deeper")).unwrap();
+        fs::write(dir.join("secret"), "secret").unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("sub/b.txt"), "b").unwrap();
+        symlink("sub/b.txt", root.join("inside")).unwrap();
+        symlink("sub", root.join("subdir")).unwrap();
+        symlink("../b.txt", root.join("sub/deeper/up")).unwrap();
+        symlink("../secret", root.join("outside")).unwrap();
+        symlink("..", root.join("parent")).unwrap();
+        symlink(dir.join("secret"), root.join("absolute")).unwrap();
+        symlink("loop", root.join("loop")).unwrap();
+        symlink("missing", root.join("dangling")).unwrap();
+        let safe_root = SafeRoot::new(&root).unwrap();
+        (dir, safe_root)
+    }
+
+    fn read(root: &SafeRoot, filename: &str) -> IoResult<String> {
+        let mut contents = String::new();
+        root.open(filename)?.read_to_string(&mut contents)?;
+        Ok(contents)
+    }
+
+    #[test]
+    fn open() {
+        let (dir, root) = setup("open");
+        for (filename, contents) in [
+            ("a.txt", "a"),
+            ("/a.txt", "a"),
+            ("//./a.txt", "a"),
+            ("sub/b.txt", "b"),
+            (r"sub\b.txt", "b"),
+            (r"\sub\.\b.txt", "b"),
+            ("inside", "b"),
+            ("subdir/b.txt", "b"),
+            ("sub/deeper/up", "b"),
+        ] {
+            assert_eq!(read(&root, filename).unwrap(), contents, "{filename:?}");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn malicious_filenames() {
+        let (dir, root) = setup("malicious");
+        for (filename, kind) in [
+            ("../secret", ErrorKind::InvalidFilename),
+            ("/../secret", ErrorKind::InvalidFilename),
+            ("sub/../../secret", ErrorKind::InvalidFilename),
+            (r"..\secret", ErrorKind::InvalidFilename),
+            (r"sub\..\..\secret", ErrorKind::InvalidFilename),
+            ("sub/..", ErrorKind::InvalidFilename),
+            (r"C:\secret", ErrorKind::InvalidFilename),
+            ("c:/secret", ErrorKind::InvalidFilename),
+            ("a.txt\0", ErrorKind::InvalidFilename),
+            ("a\0/../../secret", ErrorKind::InvalidFilename),
+            ("a.txt\n", ErrorKind::InvalidFilename),
+            ("", ErrorKind::InvalidFilename),
+            ("/", ErrorKind::InvalidFilename),
+            (r"\\.\", ErrorKind::InvalidFilename),
+            ("outside", ErrorKind::NotFound),
+            ("parent/secret", ErrorKind::NotFound),
+            ("subdir/../../secret", ErrorKind::InvalidFilename),
+            ("absolute", ErrorKind::NotFound),
+            ("dangling", ErrorKind::NotFound),
+            ("sub", ErrorKind::NotFound),
+            ("subdir", ErrorKind::NotFound),
+            ("parent", ErrorKind::NotFound),
+            ("a.txt/b.txt", ErrorKind::NotADirectory),
+        ] {
+            let error = read(&root, filename).unwrap_err();
+            assert_eq!(error.kind(), kind, "{filename:?}: {error}");
+        }
+        assert_eq!(
+            read(&root, "loop").unwrap_err().raw_os_error(),
+            Some(libc::ELOOP)
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn create() {
+        let (dir, root) = setup("create");
+        root.create("new.txt").unwrap();
+        root.create(r"sub\new.txt").unwrap();
+        assert!(root.path().join("sub/new.txt").is_file());
+        for (filename, kind) in [
+            ("a.txt", ErrorKind::AlreadyExists),
+            ("../new.txt", ErrorKind::InvalidFilename),
+            ("missing/new.txt", ErrorKind::NotFound),
+            ("parent/new.txt", ErrorKind::NotFound),
+            // symlinks are never written through, even ones that lead nowhere.
+            ("dangling", ErrorKind::AlreadyExists),
+            ("outside", ErrorKind::AlreadyExists),
+        ] {
+            let error = root.create(filename).unwrap_err();
+            assert_eq!(error.kind(), kind, "{filename:?}: {error}");
+        }
+        assert!(!root.path().join("missing").exists());
+        assert!(!dir.join("new.txt").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn swapped_symlink() {
+        use std::{
+            sync::atomic::{AtomicBool, Ordering},
+            thread,
+        };
+
+        let (dir, root) = setup("swapped");
+        let swap = root.path().join("swap");
+        fs::create_dir(&swap).unwrap();
+        fs::write(swap.join("secret"), "inside").unwrap();
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            // keeps replacing the directory with a symlink to the parent of the root and back.
+            scope.spawn(|| {
+                let moved = root.path().join("moved");
+                while !done.load(Ordering::Relaxed) {
+                    fs::rename(&swap, &moved).unwrap();
+                    symlink("..", &swap).unwrap();
+                    fs::remove_file(&swap).unwrap();
+                    fs::rename(&moved, &swap).unwrap();
+                }
+            });
+            for _ in 0..2000 {
+                if let Ok(contents) = read(&root, "swap/secret") {
+                    assert_eq!(contents, "inside");
+                }
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
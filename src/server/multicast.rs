@@ -0,0 +1,461 @@
+use crate::{
+    datastream::DataStream,
+    error::TransferError,
+    packet::{ErrorCode, Multicast, OptionAck, Packet},
+    socket::{RetransmitPolicy, TFTPSocket},
+    transfer::to_vec,
+    transport::Settings,
+};
+use std::{
+    collections::VecDeque,
+    io::{Read, Result as IoResult, Seek},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// A read request served to any amount of clients at once by sending the data packets to a multicast group,
+/// as described in [RFC-2090](https://www.rfc-editor.org/rfc/rfc2090.html).
+/// does nothing until it is consumed with the [`finish`](MulticastTransfer::finish) method
+///
+/// One client at a time is the master client, which acknowledges the data packets. Once it has received the whole file
+/// the next client becomes master, and the transfer continues from the first block it is missing.
+/// Clients that request the same file while the transfer is running can join it through [`MulticastClients::add`].
+pub struct MulticastTransfer<R: Read + Seek> {
+    sock: TFTPSocket,
+    group: SocketAddr,
+    source: DataStream<R>,
+    options: OptionAck<'static>,
+    retransmit: RetransmitPolicy,
+    clients: MulticastClients,
+}
+
+/// A handle to add clients to a running [`MulticastTransfer`]. Can be cloned and sent to other threads.
+#[derive(Clone)]
+pub struct MulticastClients {
+    joining: Arc<Mutex<Joining>>,
+}
+
+struct Joining {
+    finished: bool,
+    clients: Vec<SocketAddr>,
+}
+
+impl MulticastClients {
+    /// adds `client` to the transfer, it will be sent an option acknowledgement telling it which group to listen to.
+    /// Adding a client that is already part of the transfer sends it that option acknowledgement again.
+    ///
+    /// Returns false if the transfer has already finished, in which case the client should be served by a new transfer.
+    pub fn add(&self, client: SocketAddr) -> bool {
+        let mut joining = self.joining.lock().unwrap();
+        if !joining.finished {
+            joining.clients.push(client);
+        }
+        !joining.finished
+    }
+
+    // returns the clients added since the last call, or marks the transfer as finished if there are none and `finish_if_empty` is set.
+    fn take(&self, finish_if_empty: bool) -> Vec<SocketAddr> {
+        let mut joining = self.joining.lock().unwrap();
+        if joining.clients.is_empty() {
+            joining.finished |= finish_if_empty;
+        }
+        std::mem::take(&mut joining.clients)
+    }
+}
+
+/// the part of a packet from the master client we care about, once we're done borrowing it from the socket.
+enum Reply {
+    Ack(u16),
+    Error,
+    Unexpected,
+}
+
+impl<R: Read + Seek> MulticastTransfer<R> {
+    pub(super) fn new(
+        sock: TFTPSocket,
+        group: SocketAddr,
+        client: SocketAddr,
+        source: R,
+        options: OptionAck<'static>,
+        retransmit: RetransmitPolicy,
+    ) -> Self {
+        Self {
+            sock,
+            group,
+            source: DataStream::new(source, options.blocksize.unwrap_or(512)),
+            options,
+            retransmit,
+            clients: MulticastClients {
+                joining: Arc::new(Mutex::new(Joining {
+                    finished: false,
+                    clients: vec![client],
+                })),
+            },
+        }
+    }
+
+    /// returns a handle that adds clients to this transfer, also after [`finish`](MulticastTransfer::finish) has been called.
+    pub fn clients(&self) -> MulticastClients {
+        self.clients.clone()
+    }
+
+    /// returns the multicast group the data packets are sent to.
+    pub fn group(&self) -> SocketAddr {
+        self.group
+    }
+
+    /// executes the transfer, until every client has received the whole file or has been given up on.
+    ///
+    /// Every client is told which group to listen to with an option acknowledgement containing the negotiated options.
+    /// The master client is expected to acknowledge that with the last block it received without missing any before it.
+    /// Repeats of the last ack it sent are ignored, any other ack but one of the block just sent makes the transfer continue from the block after it.
+    /// If the master client stops replying, it is sent an error and the next client becomes master, as configured with [`Server::set_retransmit_policy`](super::Server::set_retransmit_policy).
+    /// Clients that send an error packet are dropped from the transfer.
+    ///
+    /// Returns an error if reading the source fails or on io-errors of the socket, after trying to notify the master client.
+    pub fn finish(mut self) -> IoResult<()> {
+        let settings = Settings::from_options(&self.options, self.retransmit);
+        let mut waiting: VecDeque<SocketAddr> = VecDeque::new();
+        let mut master: Option<SocketAddr> = None;
+        // the packet we're waiting on the master client to acknowledge, and where it was sent.
+        let mut outgoing = Vec::new();
+        let mut outgoing_to = self.group;
+        let mut attempt = 0;
+        // when to give up waiting on the master client, and whether the outgoing packet has to be sent (again) first.
+        let mut deadline = Instant::now();
+        let mut send = true;
+        // the last block the current master client acknowledged.
+        let mut acked = None;
+        // the block number of the final block, once we know it.
+        let mut last_block = None;
+        loop {
+            for client in self.clients.take(master.is_none() && waiting.is_empty()) {
+                if Some(client) == master {
+                    self.send_option_ack(client, true)?;
+                    continue;
+                }
+                // a client that becomes master right away is told so below.
+                if master.is_some() || waiting.iter().any(|&waiting| waiting != client) {
+                    self.send_option_ack(client, false)?;
+                }
+                if !waiting.contains(&client) {
+                    waiting.push_back(client);
+                }
+            }
+            let Some(current_master) = master else {
+                let Some(next) = waiting.pop_front() else {
+                    return Ok(());
+                };
+                master = Some(next);
+                outgoing = self.option_ack(true);
+                outgoing_to = next;
+                attempt = 0;
+                acked = None;
+                continue;
+            };
+
+            if send {
+                self.sock.send_raw_to(&outgoing, outgoing_to)?;
+                deadline = Instant::now() + settings.retransmit.timeout_for_attempt(attempt);
+            }
+            send = true;
+            let Some(reply) = self.wait_for_master(current_master, deadline, &mut waiting)? else {
+                attempt += 1;
+                if attempt > settings.retransmit.max_retries {
+                    let _may_fail = self.sock.send_message_to(
+                        Packet::new_error(ErrorCode::NOT_DEFINED, "Timed out waiting for a reply"),
+                        current_master,
+                    );
+                    master = None;
+                }
+                continue;
+            };
+            let block_nr = match reply {
+                Reply::Ack(block_nr) => block_nr,
+                Reply::Error => {
+                    master = None;
+                    continue;
+                }
+                // anything else, including a repeated request, is answered by sending the last packet again.
+                Reply::Unexpected => continue,
+            };
+            if Some(block_nr) == last_block {
+                master = None;
+                continue;
+            }
+            if Some(block_nr) == acked {
+                // the network duplicated or delayed the ack of the block before the one we just sent. Sending that one again
+                // for it would get it acknowledged twice too, and every block after it would go out twice: the Sorcerer's Apprentice Syndrome.
+                send = false;
+                continue;
+            }
+            attempt = 0;
+            acked = Some(block_nr);
+            // if the client acknowledges anything else but the block we just sent, it is missing blocks (or just became master),
+            // so start over from the first one it is missing.
+            if !self.source.acknowledge(block_nr) {
+                self.source.restart_at(block_nr)?;
+            }
+            match self.source.next_raw() {
+                Ok(Some(bytes)) => {
+                    let block_nr = u16::from_be_bytes([bytes[2], bytes[3]]);
+                    if bytes.len() < 4 + settings.blocksize {
+                        last_block = Some(block_nr);
+                    } else if block_nr == u16::MAX {
+                        // the source grew past what the server checked when creating the transfer.
+                        let _may_fail = self.sock.send_message_to(
+                            Packet::new_error(ErrorCode::NOT_DEFINED, "File too large"),
+                            current_master,
+                        );
+                        return Err(TransferError::TooManyBlocks.into());
+                    }
+                    outgoing.clear();
+                    outgoing.extend_from_slice(bytes);
+                    outgoing_to = self.group;
+                }
+                // the client acknowledged a block past the end of the file
+                Ok(None) => master = None,
+                Err(e) => {
+                    let _may_fail = self.sock.send_message_to(
+                        Packet::new_error(ErrorCode::NOT_DEFINED, "Unexpected IO error"),
+                        current_master,
+                    );
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    // waits for a packet from `master` until `deadline`. Packets from other clients are handled on the way:
+    // those that send an error leave the transfer, anything else they send is ignored.
+    fn wait_for_master(
+        &mut self,
+        master: SocketAddr,
+        deadline: Instant,
+        waiting: &mut VecDeque<SocketAddr>,
+    ) -> IoResult<Option<Reply>> {
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            let (reply, from) = match self.sock.get_next_message_within(remaining) {
+                Ok(Some((packet, from))) => (
+                    match packet {
+                        Packet::Ack(ack) => Reply::Ack(ack.block_nr),
+                        Packet::Error(_) => Reply::Error,
+                        _ => Reply::Unexpected,
+                    },
+                    from,
+                ),
+                Ok(None) => return Ok(None),
+                // ignore garbage, it might not even be from one of our clients
+                Err(e) if e.kind() == std::io::ErrorKind::InvalidData => continue,
+                Err(e) => return Err(e),
+            };
+            if from == master {
+                return Ok(Some(reply));
+            }
+            if let Reply::Error = reply {
+                waiting.retain(|&client| client != from);
+            }
+        }
+    }
+
+    fn option_ack(&self, master_client: bool) -> Vec<u8> {
+        let mut options = self.options.clone();
+        options.multicast = Some(Multicast {
+            group: Some(self.group),
+            master_client,
+        });
+        to_vec(Packet::OptionAck(options))
+    }
+
+    fn send_option_ack(&mut self, client: SocketAddr, master_client: bool) -> IoResult<()> {
+        let option_ack = self.option_ack(master_client);
+        self.sock.send_raw_to(&option_ack, client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        packet::{Ack, Error},
+        server::Server,
+    };
+    use std::{io::Cursor, net::UdpSocket, time::Duration};
+
+    fn socket() -> UdpSocket {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        sock
+    }
+
+    // receives the option acknowledgement telling `client` which group to listen to, returns where it came from.
+    fn expect_option_ack(client: &UdpSocket, master_client: bool) -> SocketAddr {
+        let mut buffer = [0u8; 512];
+        let (n_bytes, from) = client.recv_from(&mut buffer).unwrap();
+        let Ok(Packet::OptionAck(options)) = Packet::from_bytes(&buffer[..n_bytes]) else {
+            panic!("expected an option acknowledgement");
+        };
+        assert_eq!(options.multicast.unwrap().master_client, master_client);
+        from
+    }
+
+    // receives packets sent to the group until block `block_nr` arrives, skipping retransmissions of other blocks.
+    fn wait_for_block(group: &UdpSocket, block_nr: u16) -> Vec<u8> {
+        let mut buffer = [0u8; 512];
+        loop {
+            let n_bytes = group.recv(&mut buffer).unwrap();
+            if let Ok(Packet::Data(data)) = Packet::from_bytes(&buffer[..n_bytes]) {
+                if data.block_nr == block_nr {
+                    return data.data.to_vec();
+                }
+            }
+        }
+    }
+
+    fn ack(client: &UdpSocket, block_nr: u16, to: SocketAddr) {
+        let mut buffer = [0u8; 4];
+        Packet::Ack(Ack::new(block_nr))
+            .to_bytes(&mut buffer)
+            .unwrap();
+        client.send_to(&buffer, to).unwrap();
+    }
+
+    #[test]
+    fn joining_clients() {
+        let clients = MulticastClients {
+            joining: Arc::new(Mutex::new(Joining {
+                finished: false,
+                clients: Vec::new(),
+            })),
+        };
+        let client = SocketAddr::from(([10, 0, 0, 2], 4000));
+        assert!(clients.add(client));
+        // a transfer with clients left doesn't finish
+        assert_eq!(clients.take(true), [client]);
+        assert_eq!(clients.take(false), []);
+        assert!(clients.clone().add(client));
+        assert_eq!(clients.take(false), [client]);
+        assert_eq!(clients.take(true), []);
+        assert!(!clients.add(client));
+        assert_eq!(clients.take(false), []);
+    }
+
+    #[test]
+    fn master_disappears() {
+        let mut server = Server::connect_with_port([127, 0, 0, 1].into(), 0).unwrap();
+        server.set_retransmit_policy(RetransmitPolicy {
+            timeout: Duration::from_millis(100),
+            max_retries: 1,
+            exponential_backoff: false,
+        });
+        // the test stands in for the multicast group, which just has to receive every data packet.
+        let group = socket();
+        let (first, second) = (socket(), socket());
+        let file: Vec<u8> = (0..30).collect();
+        let transfer = server
+            .create_multicast_transfer(
+                group.local_addr().unwrap(),
+                first.local_addr().unwrap(),
+                Cursor::new(file.clone()),
+                OptionAck::new(Some(8), None, None),
+            )
+            .unwrap();
+        let clients = transfer.clients();
+        let transfer = std::thread::spawn(move || transfer.finish());
+
+        let tid = expect_option_ack(&first, true);
+        ack(&first, 0, tid);
+        assert_eq!(wait_for_block(&group, 1), file[..8]);
+        ack(&first, 1, tid);
+        assert_eq!(wait_for_block(&group, 2), file[8..16]);
+        assert!(clients.add(second.local_addr().unwrap()));
+        ack(&first, 2, tid);
+        assert_eq!(expect_option_ack(&second, false), tid);
+        assert_eq!(wait_for_block(&group, 3), file[16..24]);
+
+        // the first client never acknowledges block 3, so it is given up on and the second becomes master.
+        assert_eq!(expect_option_ack(&second, true), tid);
+        let mut buffer = [0u8; 512];
+        let n_bytes = first.recv(&mut buffer).unwrap();
+        assert!(matches!(
+            Packet::from_bytes(&buffer[..n_bytes]),
+            Ok(Packet::Error(Error {
+                error_code: ErrorCode::NOT_DEFINED,
+                ..
+            }))
+        ));
+        // it joined after block 2, so it catches up from block 1.
+        ack(&second, 0, tid);
+        let mut received = Vec::new();
+        for block_nr in 1..=4 {
+            received.extend(wait_for_block(&group, block_nr));
+            ack(&second, block_nr, tid);
+        }
+        assert_eq!(received, file);
+        transfer.join().unwrap().unwrap();
+        assert!(!clients.add(first.local_addr().unwrap()));
+    }
+
+    #[test]
+    fn duplicated_acks() {
+        let server = Server::connect_with_port([127, 0, 0, 1].into(), 0).unwrap();
+        let group = socket();
+        let client = socket();
+        let file: Vec<u8> = (0..30).collect();
+        let transfer = server
+            .create_multicast_transfer(
+                group.local_addr().unwrap(),
+                client.local_addr().unwrap(),
+                Cursor::new(file.clone()),
+                OptionAck::new(Some(8), None, None),
+            )
+            .unwrap();
+        let transfer = std::thread::spawn(move || transfer.finish());
+
+        // the network delivers every ack twice
+        let tid = expect_option_ack(&client, true);
+        ack(&client, 0, tid);
+        ack(&client, 0, tid);
+        let mut buffer = [0u8; 512];
+        let mut blocks = Vec::new();
+        while blocks.len() < 4 {
+            let n_bytes = group.recv(&mut buffer).unwrap();
+            let Ok(Packet::Data(data)) = Packet::from_bytes(&buffer[..n_bytes]) else {
+                panic!("expected a data packet");
+            };
+            blocks.push(data.block_nr);
+            ack(&client, data.block_nr, tid);
+            ack(&client, data.block_nr, tid);
+        }
+        transfer.join().unwrap().unwrap();
+        // none of the blocks was sent twice
+        assert_eq!(blocks, [1, 2, 3, 4]);
+        group
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        assert!(group.recv(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn too_many_blocks() {
+        let server = Server::connect_with_port([127, 0, 0, 1].into(), 0).unwrap();
+        let client = SocketAddr::from(([127, 0, 0, 1], 4000));
+        let transfer = |size| {
+            server.create_multicast_transfer(
+                client,
+                client,
+                Cursor::new(vec![0u8; size]),
+                OptionAck::new(Some(8), None, None),
+            )
+        };
+        // the last block has to be shorter than the blocksize, so this needs one block more than there are block numbers.
+        let error = transfer(8 * 65535).err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::FileTooLarge);
+        assert!(transfer(8 * 65535 - 1).is_ok());
+    }
+}
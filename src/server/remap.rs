@@ -0,0 +1,432 @@
+use super::Handler;
+use crate::{
+    error::TransferError,
+    packet::{Error, ErrorCode, OptionAck, Request},
+};
+use regex::{Captures, Regex};
+use std::{
+    borrow::Cow,
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    str::FromStr,
+};
+
+/// Ordered rules rewriting or refusing requested filenames before they're looked up, like the `--map-file` of tftp-hpa.
+///
+/// Every rule whose regex matches the filename is applied in order, until a rule that stops the remapping or denies the request matches.
+/// Wrap a [`Handler`] in [`Remapped`] to apply the rules to every request it gets.
+///
+/// Rules can also be parsed from text with one rule per line, see [`Remap::from_str`].
+#[derive(Debug, Clone, Default)]
+pub struct Remap {
+    rules: Vec<Rule>,
+}
+
+/// A single rule of a [`Remap`].
+///
+/// Replacements are inserted literally, except for `\0` to `\9` which insert the whole match and the capture groups of the regex,
+/// `\i` which inserts the ip address of the client, `\x` which inserts it as hexadecimal digits like PXE clients use,
+/// and `\\` which inserts a single backslash.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    regex: Regex,
+    action: Action,
+    replacement: String,
+    all: bool,
+}
+
+/// What a [`Rule`] does to a filename it matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// replaces the match and continues with the next rule.
+    Replace,
+    /// replaces the match and applies no further rules.
+    RewriteAndStop,
+    /// refuses the request.
+    Deny,
+}
+
+impl Rule {
+    /// creates a rule replacing the first match of `pattern` with `replacement`, then continuing with the next rule.
+    pub fn replace(pattern: &str, replacement: &str) -> Result<Self, regex::Error> {
+        Self::new(pattern, Action::Replace, replacement)
+    }
+
+    /// creates a rule replacing the first match of `pattern` with `replacement`, after which no further rules are applied.
+    pub fn rewrite(pattern: &str, replacement: &str) -> Result<Self, regex::Error> {
+        Self::new(pattern, Action::RewriteAndStop, replacement)
+    }
+
+    /// creates a rule refusing requests for filenames matching `pattern`.
+    pub fn deny(pattern: &str) -> Result<Self, regex::Error> {
+        Self::new(pattern, Action::Deny, "")
+    }
+
+    fn new(pattern: &str, action: Action, replacement: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            regex: Regex::new(pattern)?,
+            action,
+            replacement: replacement.to_owned(),
+            all: false,
+        })
+    }
+
+    /// replaces every match instead of only the first one.
+    pub fn all(mut self) -> Self {
+        self.all = true;
+        self
+    }
+
+    /// returns what this rule does to filenames it matches.
+    pub fn action(&self) -> Action {
+        self.action
+    }
+
+    // builds the replacement for a single match.
+    fn expand(&self, captures: &Captures, client: IpAddr) -> String {
+        // clients on an ipv6 socket show up as v4-mapped addresses, which `\i` and `\x` spell as the ipv4 address.
+        let client = client.to_canonical();
+        let mut expanded = String::new();
+        let mut chars = self.replacement.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                expanded.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some(digit @ '0'..='9') => {
+                    let group = digit as usize - '0' as usize;
+                    expanded += captures.get(group).map_or("", |group| group.as_str());
+                }
+                Some('i') => expanded += &client.to_string(),
+                Some('x') => match client {
+                    IpAddr::V4(ip) => expanded += &format!("{:08X}", u32::from(ip)),
+                    IpAddr::V6(ip) => expanded += &format!("{:032X}", u128::from(ip)),
+                },
+                Some(other) => expanded.push(other),
+                None => expanded.push('\\'),
+            }
+        }
+        expanded
+    }
+}
+
+impl Remap {
+    /// creates a remap without any rules, which leaves every filename as is.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// adds `rule` after the rules added before.
+    pub fn push(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+
+    /// reads the rules in the file at `path`, see [`Remap::from_str`] for the format.
+    pub fn load(path: impl AsRef<Path>) -> IoResult<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| IoError::new(e.kind(), format!("can't read {path:?}: {e}")))?;
+        text.parse()
+            .map_err(|e: IoError| IoError::new(e.kind(), format!("{path:?}: {e}")))
+    }
+
+    /// applies the rules to `filename` requested by the client at `client`. Returns `None` if a rule denies the request.
+    pub fn apply<'a>(&self, filename: &'a str, client: IpAddr) -> Option<Cow<'a, str>> {
+        let mut filename = Cow::Borrowed(filename);
+        for rule in &self.rules {
+            if !rule.regex.is_match(&filename) {
+                continue;
+            }
+            if rule.action == Action::Deny {
+                return None;
+            }
+            let limit = if rule.all { 0 } else { 1 };
+            let replaced = rule
+                .regex
+                .replacen(&filename, limit, |captures: &Captures| {
+                    rule.expand(captures, client)
+                });
+            filename = Cow::Owned(replaced.into_owned());
+            if rule.action == Action::RewriteAndStop {
+                break;
+            }
+        }
+        Some(filename)
+    }
+}
+
+impl FromStr for Remap {
+    type Err = IoError;
+
+    /// parses rules in a format close to the map files of tftp-hpa, with one rule per line:
+    ///
+    /// ```text
+    /// # PXE clients from windows land use backslashes
+    /// rg  \\                /
+    /// r   ^/?tftpboot/
+    /// ri  ^pxelinux\.cfg/default$  pxelinux.cfg/\x
+    /// a   \.\.
+    /// ```
+    ///
+    /// Every line has flags, a regex and optionally a replacement, separated by whitespace. Empty lines and lines starting with `#` are ignored.
+    /// The flags are `r` to replace the match, `g` to replace every match, `i` to match case-insensitively,
+    /// `e` to stop after this rule if it matches, and `a` to deny requests matching the rule.
+    /// The regex uses the syntax of the [`regex`] crate, see [`Rule`] for the syntax of replacements.
+    fn from_str(s: &str) -> IoResult<Self> {
+        let mut remap = Self::new();
+        for (i, line) in s.lines().enumerate() {
+            let invalid = |message: String| {
+                IoError::new(ErrorKind::InvalidData, format!("line {}: {message}", i + 1))
+            };
+            let mut parts = line.split_whitespace();
+            let Some(flags) = parts.next().filter(|flags| !flags.starts_with('#')) else {
+                continue;
+            };
+            let pattern = parts
+                .next()
+                .ok_or_else(|| invalid("missing the regex".into()))?;
+            let replacement = parts.next().unwrap_or("");
+            if parts.next().is_some() {
+                return Err(invalid("expected flags, a regex and a replacement".into()));
+            }
+            let (mut replace, mut all, mut stop, mut deny, mut case_insensitive) =
+                (false, false, false, false, false);
+            for flag in flags.chars() {
+                match flag {
+                    'r' => replace = true,
+                    'g' => all = true,
+                    'e' => stop = true,
+                    'a' => deny = true,
+                    'i' => case_insensitive = true,
+                    other => return Err(invalid(format!("unknown flag {other:?}"))),
+                }
+            }
+            let pattern = if case_insensitive {
+                Cow::Owned(format!("(?i){pattern}"))
+            } else {
+                Cow::Borrowed(pattern)
+            };
+            let rule = match (deny, replace, stop) {
+                (true, false, _) => Rule::deny(&pattern),
+                (false, true, false) => Rule::replace(&pattern, replacement),
+                (false, true, true) => Rule::rewrite(&pattern, replacement),
+                // stopping without replacing anything is a rewrite to the match itself.
+                (false, false, true) => Rule::rewrite(&pattern, "\\0"),
+                (true, true, _) => {
+                    return Err(invalid("a rule can't both deny and replace".into()))
+                }
+                (false, false, false) => {
+                    return Err(invalid("a rule has to replace, stop or deny".into()))
+                }
+            }
+            .map_err(|e| invalid(e.to_string()))?;
+            remap.push(if all { rule.all() } else { rule });
+        }
+        Ok(remap)
+    }
+}
+
+/// A [`Handler`] applying a [`Remap`] to the filename of every request before passing it on to the handler it wraps.
+///
+/// Requests denied by the remap are refused with an access violation.
+/// The wrapped handler only ever sees remapped filenames, also when it's told a transfer finished.
+pub struct Remapped<H> {
+    handler: H,
+    remap: Remap,
+}
+
+impl<H: Handler> Remapped<H> {
+    /// wraps `handler`, applying `remap` to every request before passing it on.
+    pub fn new(handler: H, remap: Remap) -> Self {
+        Self { handler, remap }
+    }
+
+    /// returns the wrapped handler.
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    // calls `f` with `request` after remapping its filename.
+    fn remapped<T>(
+        &self,
+        request: &Request,
+        client: SocketAddr,
+        f: impl FnOnce(&Request) -> Result<T, Error<'static>>,
+    ) -> Result<T, Error<'static>> {
+        let filename = self
+            .remap
+            .apply(request.filename, client.ip())
+            .ok_or(Error::new(ErrorCode::ACCESS_VIOLATION, "Access denied"))?;
+        let mut request = request.clone();
+        request.filename = &filename;
+        f(&request)
+    }
+}
+
+impl<H: Handler> Handler for Remapped<H> {
+    type Reader = H::Reader;
+    type Writer = H::Writer;
+
+    fn open_read(
+        &self,
+        request: &Request,
+        client: SocketAddr,
+    ) -> Result<(Self::Reader, Option<u64>), Error<'static>> {
+        self.remapped(request, client, |request| {
+            self.handler.open_read(request, client)
+        })
+    }
+
+    fn open_write(
+        &self,
+        request: &Request,
+        client: SocketAddr,
+    ) -> Result<Self::Writer, Error<'static>> {
+        self.remapped(request, client, |request| {
+            self.handler.open_write(request, client)
+        })
+    }
+
+    fn transfer_finished(
+        &self,
+        filename: &str,
+        client: SocketAddr,
+        result: &Result<(), TransferError>,
+    ) {
+        let remapped = self.remap.apply(filename, client.ip());
+        let filename = remapped.as_deref().unwrap_or(filename);
+        self.handler.transfer_finished(filename, client, result)
+    }
+
+    fn negotiate_options(
+        &self,
+        request: &Request,
+        client: SocketAddr,
+        options: &mut OptionAck<'static>,
+    ) {
+        let _ = self.remapped(request, client, |request| {
+            self.handler.negotiate_options(request, client, options);
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn apply() {
+        let remap: Remap = r"
+            # windows style paths
+            rg  \\                 /
+            r   ^/?tftpboot/
+            a   \.\.
+            ri  ^pxelinux\.cfg/default$  pxelinux.cfg/\x
+            re  ^(.*)\.img$       images/\i/\1.img
+            r   ^images/          never/
+        "
+        .parse()
+        .unwrap();
+        let client = IpAddr::from([192, 168, 0, 10]);
+        let apply = |filename| remap.apply(filename, client).map(Cow::into_owned);
+        assert_eq!(
+            apply(r"\boot\pxelinux.0").as_deref(),
+            Some("/boot/pxelinux.0")
+        );
+        assert_eq!(apply("/tftpboot/x").as_deref(), Some("x"));
+        assert_eq!(
+            apply("PXELINUX.CFG/Default").as_deref(),
+            Some("pxelinux.cfg/C0A8000A")
+        );
+        assert_eq!(
+            apply("tftpboot/kernel.img").as_deref(),
+            Some("images/192.168.0.10/kernel.img")
+        );
+        assert_eq!(apply(r"boot\..\..\etc\passwd"), None);
+        // a client on an ipv6 socket gets the same files as on an ipv4 one.
+        let mapped = std::net::Ipv4Addr::new(192, 168, 0, 10).to_ipv6_mapped();
+        let apply = |filename| remap.apply(filename, mapped.into()).map(Cow::into_owned);
+        assert_eq!(
+            apply("PXELINUX.CFG/Default").as_deref(),
+            Some("pxelinux.cfg/C0A8000A")
+        );
+        assert_eq!(
+            apply("tftpboot/kernel.img").as_deref(),
+            Some("images/192.168.0.10/kernel.img")
+        );
+        assert!(matches!(
+            remap.apply("untouched", client),
+            Some(Cow::Borrowed("untouched"))
+        ));
+    }
+
+    // records the filenames it is asked to open and told about.
+    #[derive(Default)]
+    struct Recorder {
+        opened: Mutex<Vec<String>>,
+        finished: Mutex<Vec<String>>,
+    }
+
+    impl Handler for Recorder {
+        type Reader = &'static [u8];
+        type Writer = Vec<u8>;
+
+        fn open_read(
+            &self,
+            request: &Request,
+            _client: SocketAddr,
+        ) -> Result<(Self::Reader, Option<u64>), Error<'static>> {
+            self.opened.lock().unwrap().push(request.filename.into());
+            Ok((b"", Some(0)))
+        }
+
+        fn open_write(
+            &self,
+            request: &Request,
+            _client: SocketAddr,
+        ) -> Result<Self::Writer, Error<'static>> {
+            self.opened.lock().unwrap().push(request.filename.into());
+            Ok(Vec::new())
+        }
+
+        fn transfer_finished(
+            &self,
+            filename: &str,
+            _client: SocketAddr,
+            _result: &Result<(), TransferError>,
+        ) {
+            self.finished.lock().unwrap().push(filename.into());
+        }
+    }
+
+    #[test]
+    fn remapped() {
+        let remap: Remap = r"re ^boot$ boot-\i".parse().unwrap();
+        let handler = Remapped::new(Recorder::default(), remap);
+        let client = SocketAddr::from(([10, 0, 0, 1], 1234));
+        let request = Request::new_read_request("boot", None);
+        handler.open_read(&request, client).unwrap();
+        handler.transfer_finished("boot", client, &Ok(()));
+        assert_eq!(*handler.handler().opened.lock().unwrap(), ["boot-10.0.0.1"]);
+        assert_eq!(
+            *handler.handler().finished.lock().unwrap(),
+            ["boot-10.0.0.1"]
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let error = |rules: &str| rules.parse::<Remap>().unwrap_err().to_string();
+        assert_eq!(error("\nx ^a b"), "line 2: unknown flag 'x'");
+        assert_eq!(error("r"), "line 1: missing the regex");
+        assert_eq!(
+            error("ra ^a b"),
+            "line 1: a rule can't both deny and replace"
+        );
+        assert!(error("r (a b").starts_with("line 1: regex parse error"));
+    }
+}
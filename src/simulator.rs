@@ -0,0 +1,407 @@
+use crate::transport::Transport;
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    net::SocketAddr,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// How badly a simulated [`Network`] treats the datagrams sent over it.
+///
+/// The rates are chances between 0 and 1, rolled for every datagram separately.
+/// The default is [`Conditions::NONE`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conditions {
+    /// the chance a datagram is lost.
+    pub drop_rate: f64,
+    /// the chance a datagram is delivered twice.
+    pub duplicate_rate: f64,
+    /// the chance a datagram overtakes the datagram sent to the same address before it, if that one hasn't been received yet.
+    pub reorder_rate: f64,
+    /// every datagram is delayed by a random duration up to this long. Datagrams delayed by different amounts can arrive out of order too.
+    pub max_delay: Duration,
+}
+
+impl Conditions {
+    /// a perfect network that delivers every datagram once, in order and right away.
+    pub const NONE: Self = Self {
+        drop_rate: 0.0,
+        duplicate_rate: 0.0,
+        reorder_rate: 0.0,
+        max_delay: Duration::ZERO,
+    };
+}
+
+impl Default for Conditions {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Counts what happened to the datagrams sent over a [`Network`], to check a test actually ran into the conditions it set up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Statistics {
+    /// datagrams passed to [`send_to`](Transport::send_to).
+    pub sent: u64,
+    /// datagrams lost on purpose.
+    pub dropped: u64,
+    /// datagrams delivered an extra time.
+    pub duplicated: u64,
+    /// datagrams that overtook the one sent before them.
+    pub reordered: u64,
+    /// datagrams received by a socket, including the duplicates.
+    pub delivered: u64,
+}
+
+/// An in-memory network for testing transfers under packet loss, duplication, reordering and delay.
+///
+/// Bind sockets to it with [`bind`](Network::bind); they implement [`Transport`], so they can be used for the client as well as
+/// [`transport::Transfer`](crate::transport::Transfer) and [`transport::IncomingTransfer`](crate::transport::IncomingTransfer),
+/// or to run a [`Server`](crate::server::Server) with [`serve_with`](crate::server::Server::serve_with).
+/// Every socket rolls the fate of the datagrams it sends with its own random number generator, seeded with the seed of the network
+/// and the address of the socket, so a test that fails can be run again with the same seed.
+/// Note that delays and timeouts still use the real clock, so the order in which threads run can change the outcome too.
+///
+/// Addresses are used as given, there are no interfaces or routes: a datagram is delivered to the socket bound to exactly the address it was sent to,
+/// or discarded if there is none. Cloning a network gives another handle to the same network.
+#[derive(Debug, Clone)]
+pub struct Network {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    seed: u64,
+    state: Mutex<State>,
+    // notified whenever a datagram is queued for any socket.
+    arrived: Condvar,
+}
+
+#[derive(Debug)]
+struct State {
+    conditions: Conditions,
+    statistics: Statistics,
+    // the datagrams waiting for every bound socket, sorted by the time they can be received.
+    mailboxes: HashMap<SocketAddr, VecDeque<Datagram>>,
+    next_port: u16,
+}
+
+#[derive(Debug)]
+struct Datagram {
+    from: SocketAddr,
+    data: Vec<u8>,
+    arrives: Instant,
+}
+
+// ports handed out to sockets bound to port 0, like the ephemeral range of an actual network stack.
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+impl Network {
+    /// creates a network treating datagrams according to `conditions`, with `seed` deciding which datagrams are affected.
+    pub fn new(conditions: Conditions, seed: u64) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                seed,
+                state: Mutex::new(State {
+                    conditions,
+                    statistics: Statistics::default(),
+                    mailboxes: HashMap::new(),
+                    next_port: FIRST_EPHEMERAL_PORT,
+                }),
+                arrived: Condvar::new(),
+            }),
+        }
+    }
+
+    /// changes the conditions of the network. Datagrams that are already underway are not affected.
+    pub fn set_conditions(&self, conditions: Conditions) {
+        self.state().conditions = conditions;
+    }
+
+    /// returns the current conditions of the network.
+    pub fn conditions(&self) -> Conditions {
+        self.state().conditions
+    }
+
+    /// returns what happened to the datagrams sent over the network so far.
+    pub fn statistics(&self) -> Statistics {
+        self.state().statistics
+    }
+
+    /// creates a socket bound to `addr`. If the port is 0, a free port on the same ip is picked instead.
+    /// Fails if another socket is already bound to the address.
+    pub fn bind(&self, mut addr: SocketAddr) -> IoResult<SimulatedSocket> {
+        let mut state = self.state();
+        if addr.port() == 0 {
+            let port = (state.next_port..=u16::MAX)
+                .find(|&port| {
+                    !state
+                        .mailboxes
+                        .contains_key(&SocketAddr::new(addr.ip(), port))
+                })
+                .ok_or_else(|| IoError::new(ErrorKind::AddrInUse, "No free ports left"))?;
+            state.next_port = port.wrapping_add(1).max(FIRST_EPHEMERAL_PORT);
+            addr.set_port(port);
+        } else if state.mailboxes.contains_key(&addr) {
+            return Err(IoError::new(
+                ErrorKind::AddrInUse,
+                format!("{addr} is already in use"),
+            ));
+        }
+        state.mailboxes.insert(addr, VecDeque::new());
+        Ok(SimulatedSocket {
+            network: self.clone(),
+            addr,
+            rng: Rng::new(self.shared.seed ^ address_seed(addr)),
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // a test panicking while holding the lock shouldn't take the other sockets down with it.
+        self.shared
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// A socket on a simulated [`Network`], created with [`Network::bind`]. Dropping it frees its address again.
+///
+/// Just like a UDP socket, datagrams larger than the receive buffer are truncated. Sending and receiving never fails though.
+#[derive(Debug)]
+pub struct SimulatedSocket {
+    network: Network,
+    addr: SocketAddr,
+    rng: Rng,
+}
+
+impl Transport for SimulatedSocket {
+    type Error = IoError;
+
+    fn send_to(&mut self, data: &[u8], addr: SocketAddr) -> IoResult<()> {
+        let mut state = self.network.state();
+        let conditions = state.conditions;
+        state.statistics.sent += 1;
+        if self.rng.chance(conditions.drop_rate) {
+            state.statistics.dropped += 1;
+            return Ok(());
+        }
+        let copies = if self.rng.chance(conditions.duplicate_rate) {
+            state.statistics.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        let reorder = self.rng.chance(conditions.reorder_rate);
+        let now = Instant::now();
+        let State {
+            mailboxes,
+            statistics,
+            ..
+        } = &mut *state;
+        let Some(mailbox) = mailboxes.get_mut(&addr) else {
+            return Ok(());
+        };
+        for copy in 0..copies {
+            let arrives = now + self.rng.duration(conditions.max_delay);
+            let datagram = Datagram {
+                from: self.addr,
+                data: data.to_vec(),
+                arrives,
+            };
+            match mailbox.back_mut() {
+                // take the place of the last datagram, arriving at the same time but just before it.
+                Some(last) if copy == 0 && reorder => {
+                    statistics.reordered += 1;
+                    let datagram = Datagram {
+                        arrives: last.arrives,
+                        ..datagram
+                    };
+                    mailbox.insert(mailbox.len() - 1, datagram);
+                }
+                _ => {
+                    let index = mailbox.partition_point(|queued| queued.arrives <= arrives);
+                    mailbox.insert(index, datagram);
+                }
+            }
+        }
+        self.network.shared.arrived.notify_all();
+        Ok(())
+    }
+
+    fn recv_from(
+        &mut self,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> IoResult<Option<(usize, SocketAddr)>> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.network.state();
+        loop {
+            let now = Instant::now();
+            let mailbox = state
+                .mailboxes
+                .get_mut(&self.addr)
+                .expect("sockets are only unbound when dropped");
+            let wake_up = match mailbox.front() {
+                Some(datagram) if datagram.arrives <= now => {
+                    let datagram = mailbox.pop_front().unwrap();
+                    state.statistics.delivered += 1;
+                    let n_bytes = datagram.data.len().min(buffer.len());
+                    buffer[..n_bytes].copy_from_slice(&datagram.data[..n_bytes]);
+                    return Ok(Some((n_bytes, datagram.from)));
+                }
+                Some(datagram) => datagram.arrives.min(deadline),
+                None => deadline,
+            };
+            if now >= deadline {
+                return Ok(None);
+            }
+            state = self
+                .network
+                .shared
+                .arrived
+                .wait_timeout(state, wake_up - now)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+    }
+
+    fn local_addr(&self) -> IoResult<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn now(&self) -> Duration {
+        crate::transport::now()
+    }
+}
+
+impl Drop for SimulatedSocket {
+    fn drop(&mut self) {
+        self.network.state().mailboxes.remove(&self.addr);
+    }
+}
+
+// mixes an address into the seed of the network, so every socket gets its own sequence of random numbers.
+fn address_seed(addr: SocketAddr) -> u64 {
+    let ip = match addr.ip() {
+        std::net::IpAddr::V4(ip) => u32::from(ip) as u128,
+        std::net::IpAddr::V6(ip) => u128::from(ip),
+    };
+    let mut rng = Rng::new((ip as u64) ^ ((ip >> 64) as u64) ^ ((addr.port() as u64) << 48));
+    rng.next()
+}
+
+/// a small SplitMix64 generator, good enough to decide the fate of datagrams and cheap to seed.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // returns true with a chance of `rate`, without drawing a number if it can't be true anyway.
+    fn chance(&mut self, rate: f64) -> bool {
+        rate > 0.0 && ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < rate
+    }
+
+    fn duration(&mut self, max: Duration) -> Duration {
+        match max.as_nanos() as u64 {
+            0 => Duration::ZERO,
+            max => Duration::from_nanos(self.next() % (max + 1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "10.0.0.1:69";
+    const B: &str = "10.0.0.2:1234";
+
+    fn receive_all(sock: &mut SimulatedSocket) -> Vec<u8> {
+        let mut buffer = [0u8; 4];
+        let mut received = Vec::new();
+        while let Some((n_bytes, _)) = sock
+            .recv_from(&mut buffer, Duration::from_millis(20))
+            .unwrap()
+        {
+            assert_eq!(n_bytes, 1);
+            received.push(buffer[0]);
+        }
+        received
+    }
+
+    #[test]
+    fn conditions() {
+        let network = Network::new(Conditions::NONE, 7);
+        let mut a = network.bind(A.parse().unwrap()).unwrap();
+        let mut b = network.bind(B.parse().unwrap()).unwrap();
+        assert!(network.bind(A.parse().unwrap()).is_err());
+        for i in 0..100 {
+            a.send_to(&[i], b.addr).unwrap();
+        }
+        assert_eq!(receive_all(&mut b), (0..100).collect::<Vec<_>>());
+
+        network.set_conditions(Conditions {
+            drop_rate: 0.2,
+            duplicate_rate: 0.2,
+            reorder_rate: 0.2,
+            ..Conditions::NONE
+        });
+        for i in 0..100 {
+            a.send_to(&[i], b.addr).unwrap();
+        }
+        let received = receive_all(&mut b);
+        let statistics = network.statistics();
+        assert_eq!(statistics.sent, 200);
+        assert!(statistics.dropped > 0 && statistics.duplicated > 0 && statistics.reordered > 0);
+        assert_eq!(
+            received.len() as u64,
+            100 - statistics.dropped + statistics.duplicated
+        );
+        assert!(received.windows(2).any(|pair| pair[0] > pair[1]));
+        assert_eq!(statistics.delivered, 100 + received.len() as u64);
+    }
+
+    #[test]
+    fn delay() {
+        let max_delay = Duration::from_millis(10);
+        let network = Network::new(
+            Conditions {
+                max_delay,
+                ..Conditions::NONE
+            },
+            7,
+        );
+        let mut a = network.bind(A.parse().unwrap()).unwrap();
+        let mut b = network
+            .bind(SocketAddr::new(A.parse::<SocketAddr>().unwrap().ip(), 0))
+            .unwrap();
+        assert_eq!(b.addr.port(), FIRST_EPHEMERAL_PORT);
+        let start = Instant::now();
+        a.send_to(&[1], b.addr).unwrap();
+        let mut buffer = [0u8; 4];
+        let received = b.recv_from(&mut buffer, max_delay * 10).unwrap();
+        assert_eq!(received, Some((1, a.addr)));
+        assert!(start.elapsed() <= max_delay * 10);
+
+        // nothing is delivered to addresses nobody is bound to.
+        drop(b);
+        a.send_to(&[1], SocketAddr::new(a.addr.ip(), FIRST_EPHEMERAL_PORT))
+            .unwrap();
+        let b = network.bind(SocketAddr::new(a.addr.ip(), 0)).unwrap();
+        assert_eq!(b.addr.port(), FIRST_EPHEMERAL_PORT + 1);
+        assert_eq!(network.statistics().delivered, 1);
+    }
+}
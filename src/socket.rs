@@ -1,9 +1,12 @@
 use crate::Packet;
 use std::{
-    io::{Error as IoError, Result as IoResult},
+    io::{Error as IoError, ErrorKind, Result as IoResult},
     net::{SocketAddr, UdpSocket},
+    time::Duration,
 };
 
+pub use crate::transport::RetransmitPolicy;
+
 /// Wraps a UDP socket + buffer and exposes methods common to both server and client for reading and sending messages.
 /// unless you're implementing your own server or client, you probably want to use the [`Server`](crate::server::Server) struct instead.
 pub struct TFTPSocket {
@@ -32,7 +35,40 @@ impl TFTPSocket {
     /// fetches a TFTP packet from the socket and returns it and the senders addres.
     pub fn get_next_message_from(&mut self) -> IoResult<(Packet<'_>, SocketAddr)> {
         let (n_bytes, client_addres) = self.sock.recv_from(&mut self.buffer)?;
-        let message_buffer = &self.buffer[..n_bytes];
+        Self::parse(&self.buffer[..n_bytes], client_addres)
+    }
+
+    /// like [`get_next_message_from`](Self::get_next_message_from), but returns `Ok(None)` if nothing was received within `timeout`.
+    pub fn get_next_message_within(
+        &mut self,
+        timeout: Duration,
+    ) -> IoResult<Option<(Packet<'_>, SocketAddr)>> {
+        match self.receive_within(timeout)? {
+            Some((n_bytes, client_addres)) => {
+                Self::parse(&self.buffer[..n_bytes], client_addres).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    // receives a single datagram into the buffer, returning its size and sender.
+    fn receive_within(&mut self, timeout: Duration) -> IoResult<Option<(usize, SocketAddr)>> {
+        // a read timeout of zero is an error, so always wait at least a little while.
+        self.sock
+            .set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        let received = self.sock.recv_from(&mut self.buffer);
+        self.sock.set_read_timeout(None)?;
+        match received {
+            Ok(received) => Ok(Some(received)),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub(crate) fn parse(
+        message_buffer: &[u8],
+        addr: SocketAddr,
+    ) -> IoResult<(Packet<'_>, SocketAddr)> {
         Packet::from_bytes(message_buffer)
             .map_err(|err| {
                 IoError::new(
@@ -40,7 +76,7 @@ impl TFTPSocket {
                     format!("invalid packet received: {err:?}"),
                 )
             })
-            .map(|a| (a, client_addres))
+            .map(|a| (a, addr))
     }
 
     /// sends a TFTP packet `message` to address `addr`
@@ -62,19 +98,26 @@ impl TFTPSocket {
         addr: Option<SocketAddr>,
     ) -> Result<(), IoError> {
         let bytes = message.to_bytes(&mut self.buffer).unwrap();
-        let message = &self.buffer[..bytes];
-        let bytes_send = if let Some(addr) = addr {
-            UdpSocket::send_to(&self.sock, message, addr)
-        } else {
-            UdpSocket::send(&self.sock, message)
-        }?;
-        if bytes_send == message.len() {
-            Ok(())
-        } else {
-            Err(IoError::new(
-                std::io::ErrorKind::Other,
-                format!("Failed to send UDP packet of size {bytes_send}"),
-            ))
-        }
+        send_bytes(&self.sock, &self.buffer[..bytes], addr)
+    }
+
+    /// sends an already serialized packet to `addr`.
+    pub(crate) fn send_raw_to(&mut self, packet: &[u8], addr: SocketAddr) -> IoResult<()> {
+        send_bytes(&self.sock, packet, Some(addr))
+    }
+}
+
+fn send_bytes(sock: &UdpSocket, message: &[u8], addr: Option<SocketAddr>) -> IoResult<()> {
+    let bytes_send = if let Some(addr) = addr {
+        sock.send_to(message, addr)
+    } else {
+        sock.send(message)
+    }?;
+    if bytes_send == message.len() {
+        Ok(())
+    } else {
+        Err(IoError::other(format!(
+            "Failed to send UDP packet of size {bytes_send}"
+        )))
     }
 }
@@ -0,0 +1,1063 @@
+use crate::{
+    client::{unspecified_address, ClientOptions},
+    error::TransferError,
+    machine::{Receiver, Sender, Stop, ILLEGAL_OPERATION},
+    packet::{Ack, Data, Error, ErrorCode, OptionAck, Packet, Request, Rollover},
+    socket::{RetransmitPolicy, TFTPSocket},
+    transfer::to_vec,
+    transport::{self, DataSource, Settings, ERROR_BUFFER_SIZE},
+};
+use ::tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::UdpSocket,
+    time::Instant,
+};
+use core::num::{NonZeroU16, NonZeroU8};
+use std::{
+    collections::VecDeque,
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+/// The async counterpart of [`TFTPSocket`], wrapping a tokio UDP socket + buffer.
+/// unless you're implementing your own server or client, you probably want to use the [`Server`] or [`Client`] struct instead.
+pub struct Socket {
+    sock: UdpSocket,
+    buffer: Vec<u8>,
+    /// the only address packets are accepted from, if any.
+    peer: Option<SocketAddr>,
+}
+
+impl Socket {
+    /// creates a new UDP socket bound to `bind_addr` and optionally connects it to `connect_addr`.
+    /// note that the default port for TFTP is 69.
+    ///
+    /// A connected socket only returns packets from `connect_addr`. Packets from anyone else are answered with an unknown transfer ID error,
+    /// as [RFC-1350](https://www.rfc-editor.org/rfc/inline-errata/rfc1350.html) asks for, which the OS can't do for us if we connected the UDP socket itself.
+    pub async fn new(
+        bind_addr: SocketAddr,
+        connect_addr: Option<SocketAddr>,
+        buffer_size: usize,
+    ) -> IoResult<Self> {
+        Ok(Self {
+            sock: UdpSocket::bind(bind_addr).await?,
+            buffer: vec![0u8; buffer_size],
+            peer: connect_addr,
+        })
+    }
+
+    /// returns the address this socket is bound to.
+    pub fn local_addr(&self) -> IoResult<SocketAddr> {
+        self.sock.local_addr()
+    }
+
+    /// fetches a TFTP packet from the socket and returns it and the senders addres.
+    pub async fn get_next_message_from(&mut self) -> IoResult<(Packet<'_>, SocketAddr)> {
+        loop {
+            let (n_bytes, client_addres) = self.sock.recv_from(&mut self.buffer).await?;
+            if self.accept(n_bytes, client_addres).await {
+                return TFTPSocket::parse(&self.buffer[..n_bytes], client_addres);
+            }
+        }
+    }
+
+    /// like [`get_next_message_from`](Self::get_next_message_from), but returns `Ok(None)` if nothing was received within `timeout`.
+    pub async fn get_next_message_within(
+        &mut self,
+        timeout: Duration,
+    ) -> IoResult<Option<(Packet<'_>, SocketAddr)>> {
+        match self.receive_within(timeout).await? {
+            Some((n_bytes, client_addres)) => {
+                TFTPSocket::parse(&self.buffer[..n_bytes], client_addres).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    // receives a single datagram from the peer into the buffer, returning its size and sender.
+    async fn receive_within(&mut self, timeout: Duration) -> IoResult<Option<(usize, SocketAddr)>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let received =
+                ::tokio::time::timeout_at(deadline, self.sock.recv_from(&mut self.buffer)).await;
+            match received {
+                Ok(Ok((n_bytes, from))) => {
+                    if self.accept(n_bytes, from).await {
+                        return Ok(Some((n_bytes, from)));
+                    }
+                }
+                Ok(Err(e)) => return Err(e),
+                Err(_elapsed) => return Ok(None),
+            }
+        }
+    }
+
+    // returns whether the datagram of `n_bytes` in the buffer is from the peer, rejecting it like
+    // `transport::reject_stranger` does if it isn't.
+    async fn accept(&mut self, n_bytes: usize, from: SocketAddr) -> bool {
+        if self.peer.is_none_or(|peer| peer == from) {
+            return true;
+        }
+        if !matches!(
+            Packet::from_bytes(&self.buffer[..n_bytes]),
+            Ok(Packet::Error(_))
+        ) {
+            let error = to_vec(Packet::new_error(
+                ErrorCode::UNKNOWN_TRANSFER_ID,
+                "Unknown transfer ID",
+            ));
+            let _may_fail = send_bytes(&self.sock, &error, Some(from)).await;
+        }
+        false
+    }
+
+    /// sends a TFTP packet `message` to address `addr`
+    pub async fn send_message_to(&mut self, message: Packet<'_>, addr: SocketAddr) -> IoResult<()> {
+        self.send_message_optionally_to(message, Some(addr)).await
+    }
+
+    /// sends a TFTP packet `message` to the address this socket is connected to.
+    /// this method will fail if the socket is not connected to anything.
+    pub async fn send_message(&mut self, message: Packet<'_>) -> IoResult<()> {
+        self.send_message_optionally_to(message, None).await
+    }
+
+    /// sends a TFTP packet `message` to the given address or the default address this socket is connected to.
+    /// this method will fail if no address is given and the socket is not connected to anything.
+    pub async fn send_message_optionally_to(
+        &mut self,
+        message: Packet<'_>,
+        addr: Option<SocketAddr>,
+    ) -> IoResult<()> {
+        let bytes = message.to_bytes(&mut self.buffer).unwrap();
+        send_bytes(&self.sock, &self.buffer[..bytes], addr.or(self.peer)).await
+    }
+
+    /// sends `request` to `server` and waits for the first reply from the same ip address, from any port.
+    /// See [`transport::send_request`](crate::transport::send_request), the reply is left in the buffer.
+    async fn send_request(
+        &mut self,
+        policy: &RetransmitPolicy,
+        request: &[u8],
+        server: SocketAddr,
+    ) -> Result<(usize, SocketAddr), Failure> {
+        for attempt in 0..=policy.max_retries {
+            send_bytes(&self.sock, request, Some(server))
+                .await
+                .map_err(Failure::Transport)?;
+            let deadline = Instant::now() + policy.timeout_for_attempt(attempt);
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                match self
+                    .receive_within(remaining)
+                    .await
+                    .map_err(Failure::Transport)?
+                {
+                    Some((n_bytes, from)) if from.ip() == server.ip() => {
+                        return Ok((n_bytes, from))
+                    }
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+        }
+        Err(Failure::TimedOut(policy.max_retries + 1))
+    }
+
+    // waits for a datagram from the peer until `deadline`, counted from `start`, and returns its size. The datagram is left in the buffer.
+    async fn wait_until(
+        &mut self,
+        start: Instant,
+        deadline: Option<Duration>,
+    ) -> IoResult<Option<usize>> {
+        let Some(deadline) = deadline else {
+            return Ok(None);
+        };
+        let remaining = (start + deadline).saturating_duration_since(Instant::now());
+        Ok(self
+            .receive_within(remaining)
+            .await?
+            .map(|(n_bytes, _)| n_bytes))
+    }
+
+    async fn send_raw(&self, packet: &[u8]) -> IoResult<()> {
+        send_bytes(&self.sock, packet, self.peer).await
+    }
+
+    // tries to tell the peer why we're aborting the transfer, leaving the buffer and the packet of the peer in it alone.
+    async fn send_error(&self, error_code: ErrorCode, message: &str) {
+        let _may_fail = self
+            .send_raw(&to_vec(Packet::new_error(error_code, message)))
+            .await;
+    }
+
+    // the async counterpart of `transport::reject_reply`, for a reply of `n_bytes` in the buffer.
+    async fn reject_reply(&self, n_bytes: usize) -> Failure {
+        let failure = match Packet::from_bytes(&self.buffer[..n_bytes]) {
+            Ok(Packet::Error(_)) => return Failure::Reply(n_bytes),
+            Ok(_) => Failure::Reply(n_bytes),
+            Err(e) => Failure::InvalidPacket(e),
+        };
+        self.send_error(ErrorCode::ILLEGAL_TFTP_OPERATION, ILLEGAL_OPERATION)
+            .await;
+        failure
+    }
+
+    // tells the server we don't agree with the options it acknowledged, and returns the failure describing that.
+    async fn refuse_options(&self, reason: &'static str) -> Failure {
+        self.send_error(ErrorCode::OPTION_NEGOTIATION_FAILED, reason)
+            .await;
+        Failure::OptionNegotiation(reason)
+    }
+}
+
+async fn send_bytes(sock: &UdpSocket, message: &[u8], addr: Option<SocketAddr>) -> IoResult<()> {
+    let Some(addr) = addr else {
+        return Err(IoError::new(
+            ErrorKind::NotConnected,
+            "the socket isn't connected to a peer",
+        ));
+    };
+    let bytes_send = sock.send_to(message, addr).await?;
+    if bytes_send == message.len() {
+        Ok(())
+    } else {
+        Err(IoError::other(format!(
+            "Failed to send UDP packet of size {bytes_send}"
+        )))
+    }
+}
+
+/// The async counterpart of [`server::Server`](crate::server::Server).
+///
+/// Every transfer gets its own socket, so it can be spawned onto its own task.
+/// There is no [`Handler`](crate::server::Handler) based `serve` and no [limits](crate::server::Limits) on the amount of transfers:
+/// requests are answered by the caller, who picks the options to acknowledge, for example with [`OptionPolicy::negotiate`](crate::options::OptionPolicy::negotiate).
+pub struct Server {
+    sock: Socket,
+    retransmit: RetransmitPolicy,
+    rollover: Option<Rollover>,
+}
+
+impl Server {
+    /// creates a new server bound to ip address `ip` and port 69.
+    pub async fn connect(ip: IpAddr) -> IoResult<Self> {
+        Self::connect_with_port(ip, 69).await
+    }
+
+    /// creates a new server bound to ip address `ip` and port `port`.
+    pub async fn connect_with_port(ip: IpAddr, port: u16) -> IoResult<Self> {
+        Ok(Self {
+            sock: Socket::new(SocketAddr::new(ip, port), None, 0xFFFF).await?,
+            retransmit: RetransmitPolicy::default(),
+            rollover: Some(Rollover::Zero),
+        })
+    }
+
+    /// sets how transfers created by this server deal with lost packets. Only affects transfers created after calling this method.
+    /// The timeout of the policy is overridden for transfers where the client negotiated a timeout using [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html).
+    pub fn set_retransmit_policy(&mut self, policy: RetransmitPolicy) {
+        self.retransmit = policy;
+    }
+
+    /// returns how transfers created by this server deal with lost packets.
+    pub fn retransmit_policy(&self) -> RetransmitPolicy {
+        self.retransmit
+    }
+
+    /// sets what the block number continues with after block 65535 for transfers whose client didn't ask for a rollover itself,
+    /// see [`server::Server::set_rollover`](crate::server::Server::set_rollover). Only affects transfers created after calling this method.
+    pub fn set_rollover(&mut self, rollover: Option<Rollover>) {
+        self.rollover = rollover;
+    }
+
+    /// returns what the block number continues with after block 65535, see [`set_rollover`](Server::set_rollover).
+    pub fn rollover(&self) -> Option<Rollover> {
+        self.rollover
+    }
+
+    /// gets the next request from a client and returns it plus the adress of the client.
+    /// wil return an error if the next packet received is not a request.
+    /// The request borrows the server's receive buffer, use [`Request::to_owned`] to hand it to another task.
+    pub async fn get_next_request_from(&mut self) -> IoResult<(Request<'_>, SocketAddr)> {
+        match self.sock.get_next_message_from().await? {
+            (Packet::Request(req), addr) => Ok((req, addr)),
+            _ => Err(IoError::new(
+                ErrorKind::InvalidData,
+                "Invalid packet received",
+            )),
+        }
+    }
+
+    /// transfers the data contained in `source` to `target`, optionally using the TFTP extensions described in `options`.
+    /// See [`server::Server::create_transfer_to`](crate::server::Server::create_transfer_to).
+    pub async fn create_transfer_to<R: AsyncRead + Unpin>(
+        &self,
+        target: SocketAddr,
+        source: R,
+        options: OptionAck<'static>,
+    ) -> IoResult<Transfer<R>> {
+        Ok(Transfer {
+            sock: self.transfer_socket(target, &options).await?,
+            source,
+            rollover: options.rollover.or(self.rollover),
+            options,
+            retransmit: self.retransmit,
+        })
+    }
+
+    /// receives the data send by `target` and writes it to `sink`, optionally using the TFTP extensions described in `options`.
+    /// See [`server::Server::create_transfer_from`](crate::server::Server::create_transfer_from).
+    pub async fn create_transfer_from<W: AsyncWrite + Unpin>(
+        &self,
+        target: SocketAddr,
+        sink: W,
+        options: OptionAck<'static>,
+    ) -> IoResult<IncomingTransfer<W>> {
+        Ok(IncomingTransfer {
+            sock: self.transfer_socket(target, &options).await?,
+            sink,
+            rollover: options.rollover.or(self.rollover),
+            options,
+            retransmit: self.retransmit,
+        })
+    }
+
+    async fn transfer_socket(
+        &self,
+        target: SocketAddr,
+        options: &OptionAck<'_>,
+    ) -> IoResult<Socket> {
+        Socket::new(
+            SocketAddr::new(self.ip()?, 0),
+            Some(target),
+            512 + (options.blocksize.unwrap_or(512) as usize),
+        )
+        .await
+    }
+
+    /// sends the error message `error` to the client at `addr`.
+    pub async fn send_error_to(&mut self, error: Error<'_>, addr: SocketAddr) -> IoResult<()> {
+        self.sock.send_message_to(Packet::Error(error), addr).await
+    }
+
+    /// return the ip this socket is bound to.
+    pub fn ip(&self) -> IoResult<IpAddr> {
+        self.sock.local_addr().map(|a| a.ip())
+    }
+}
+
+/// The async counterpart of [`server::Transfer`](crate::server::Transfer).
+/// does nothing until it is consumed with the [`finish`](Transfer::finish) method
+pub struct Transfer<R: AsyncRead + Unpin> {
+    sock: Socket,
+    source: R,
+    options: OptionAck<'static>,
+    retransmit: RetransmitPolicy,
+    rollover: Option<Rollover>,
+}
+
+impl<R: AsyncRead + Unpin> Transfer<R> {
+    /// executes the transfer. See [`server::Transfer::finish`](crate::server::Transfer::finish) for details.
+    pub async fn finish(mut self) -> Result<(), TransferError> {
+        let settings = Settings::from_options(&self.options, self.retransmit);
+        let mut source = Prefetch::new(self.source, &settings);
+        let mut sender = Sender::new(self.options, self.retransmit);
+        sender.set_rollover(self.rollover);
+        let result = send_blocks(&mut self.sock, &mut sender, &mut source).await;
+        result.map_err(|failure| failure.with_buffer(&self.sock.buffer).into())
+    }
+}
+
+/// The async counterpart of [`server::IncomingTransfer`](crate::server::IncomingTransfer).
+/// does nothing until it is consumed with the [`finish`](IncomingTransfer::finish) method
+pub struct IncomingTransfer<W: AsyncWrite + Unpin> {
+    sock: Socket,
+    sink: W,
+    options: OptionAck<'static>,
+    retransmit: RetransmitPolicy,
+    rollover: Option<Rollover>,
+}
+
+impl<W: AsyncWrite + Unpin> IncomingTransfer<W> {
+    /// executes the transfer, returning the total amount of bytes written to the sink.
+    /// See [`server::IncomingTransfer::finish`](crate::server::IncomingTransfer::finish) for details.
+    pub async fn finish(mut self) -> Result<u64, TransferError> {
+        let mut receiver = Receiver::new(self.options, self.retransmit);
+        receiver.set_rollover(self.rollover);
+        let result = receive_blocks(&mut self.sock, &mut receiver, &mut self.sink).await;
+        result.map_err(|failure| failure.with_buffer(&self.sock.buffer).into())
+    }
+}
+
+/// The async counterpart of [`client::Client`](crate::client::Client).
+///
+/// Only supports the octet mode, netascii translation is up to the caller.
+pub struct Client {
+    server: SocketAddr,
+    options: ClientOptions,
+}
+
+impl Client {
+    /// creates a new client for the server at `server`. Note that the default port for TFTP is 69.
+    pub fn new(server: SocketAddr) -> Self {
+        Self {
+            server,
+            options: ClientOptions::default(),
+        }
+    }
+
+    /// sets the blocksize to request using the blocksize option defined in [RFC-2348](https://www.rfc-editor.org/rfc/rfc2348.html).
+    /// The server may pick a smaller blocksize.
+    pub fn set_blocksize(&mut self, blocksize: Option<u16>) {
+        self.options.blocksize = blocksize;
+    }
+
+    /// sets the timeout to request using the timeout option defined in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html).
+    /// If the server acknowledges it, it replaces the timeout of the [retransmit policy](Client::set_retransmit_policy).
+    pub fn set_timeout(&mut self, timeout_seconds: Option<NonZeroU8>) {
+        self.options.timeout_seconds = timeout_seconds;
+    }
+
+    /// sets the window size to request using the windowsize option defined in [RFC-7440](https://www.rfc-editor.org/rfc/rfc7440.html).
+    /// The server may pick a smaller window.
+    pub fn set_window_size(&mut self, window_size: Option<NonZeroU16>) {
+        self.options.window_size = window_size;
+    }
+
+    /// sets the block number the transfer continues with after block 65535, using the rollover option some clients send.
+    /// Servers that don't know the option usually continue with block 0.
+    pub fn set_rollover(&mut self, rollover: Option<Rollover>) {
+        self.options.rollover = rollover;
+    }
+
+    /// if set, read requests ask the server for the size of the file using the tsize option defined in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html).
+    pub fn set_request_transfer_size(&mut self, request_transfer_size: bool) {
+        self.options.request_transfer_size = request_transfer_size;
+    }
+
+    /// sets the size of the data uploaded by write requests, told to the server using the tsize option defined in [RFC-2349](https://www.rfc-editor.org/rfc/rfc2349.html).
+    /// The server may refuse files that are too large before any data is sent.
+    pub fn set_write_transfer_size(&mut self, size: Option<u64>) {
+        self.options.write_transfer_size = size;
+    }
+
+    /// sets how transfers deal with lost packets, including the request that starts them.
+    pub fn set_retransmit_policy(&mut self, policy: RetransmitPolicy) {
+        self.options.retransmit = policy;
+    }
+
+    /// returns how transfers deal with lost packets.
+    pub fn retransmit_policy(&self) -> RetransmitPolicy {
+        self.options.retransmit
+    }
+
+    /// downloads the file `filename` from the server and writes it to `sink`, returning the amount of bytes received.
+    ///
+    /// The transfer is bound to the port the server first replies from. Packets from any other address are answered with an unknown transfer ID error.
+    pub async fn get<W: AsyncWrite + Unpin>(
+        &self,
+        filename: &str,
+        mut sink: W,
+    ) -> Result<u64, TransferError> {
+        let mut sock = self.socket().await?;
+        let result = self.receive(&mut sock, filename, &mut sink).await;
+        result.map_err(|failure| failure.with_buffer(&sock.buffer).into())
+    }
+
+    /// uploads the contents of `source` to the server as the file `filename`.
+    ///
+    /// The transfer is bound to the port the server first replies from. Packets from any other address are answered with an unknown transfer ID error.
+    pub async fn put<R: AsyncRead + Unpin>(
+        &self,
+        filename: &str,
+        source: R,
+    ) -> Result<(), TransferError> {
+        let mut sock = self.socket().await?;
+        let result = self.send(&mut sock, filename, source).await;
+        result.map_err(|failure| failure.with_buffer(&sock.buffer).into())
+    }
+
+    // the async counterpart of `ClientOptions::receive`.
+    async fn receive<W: AsyncWrite + Unpin>(
+        &self,
+        sock: &mut Socket,
+        filename: &str,
+        sink: &mut W,
+    ) -> Result<u64, Failure> {
+        let options = &self.options;
+        let request = options.read_request(filename);
+        let (n_bytes, server_tid) = sock
+            .send_request(
+                &options.retransmit,
+                &to_vec(Packet::Request(request.clone())),
+                self.server,
+            )
+            .await?;
+        sock.peer = Some(server_tid);
+        let settings = match Packet::from_bytes(&sock.buffer[..n_bytes]) {
+            Ok(Packet::OptionAck(option_ack)) => options.check_option_ack(&option_ack, &request),
+            // the server ignored our options and started sending right away
+            Ok(Packet::Data(Data { block_nr: 1, data })) if data.len() <= 512 => {
+                let settings = options.default_settings();
+                let is_last_block = data.len() < settings.blocksize;
+                let received = data.len() as u64;
+                if let Err(e) = write_block(sink, data, is_last_block).await {
+                    sock.send_error(
+                        ErrorCode::DISK_FULL_OR_ALLOCATION_EXCEEDED,
+                        "Unexpected IO error",
+                    )
+                    .await;
+                    return Err(Failure::Data(e));
+                }
+                if is_last_block {
+                    sock.send_raw(&to_vec(Packet::new_ack(1)))
+                        .await
+                        .map_err(Failure::Transport)?;
+                    return Ok(received);
+                }
+                let mut receiver = Receiver::acknowledging(settings, 1);
+                return receive_blocks(sock, &mut receiver, sink)
+                    .await
+                    .map(|rest| rest + received);
+            }
+            _ => return Err(sock.reject_reply(n_bytes).await),
+        };
+        match settings {
+            Ok(settings) => {
+                receive_blocks(sock, &mut Receiver::acknowledging(settings, 0), sink).await
+            }
+            Err(reason) => Err(sock.refuse_options(reason).await),
+        }
+    }
+
+    // the async counterpart of `ClientOptions::send`.
+    async fn send<R: AsyncRead + Unpin>(
+        &self,
+        sock: &mut Socket,
+        filename: &str,
+        source: R,
+    ) -> Result<(), Failure> {
+        let options = &self.options;
+        let request = options.write_request(filename);
+        let (n_bytes, server_tid) = sock
+            .send_request(
+                &options.retransmit,
+                &to_vec(Packet::Request(request.clone())),
+                self.server,
+            )
+            .await?;
+        sock.peer = Some(server_tid);
+        let settings = match Packet::from_bytes(&sock.buffer[..n_bytes]) {
+            Ok(Packet::OptionAck(option_ack)) => options.check_option_ack(&option_ack, &request),
+            // the server ignored our options
+            Ok(Packet::Ack(Ack { block_nr: 0 })) => Ok(options.default_settings()),
+            _ => return Err(sock.reject_reply(n_bytes).await),
+        };
+        let settings = match settings {
+            Ok(settings) => settings,
+            Err(reason) => return Err(sock.refuse_options(reason).await),
+        };
+        let mut source = Prefetch::new(source, &settings);
+        send_blocks(sock, &mut Sender::with_settings(settings), &mut source).await
+    }
+
+    async fn socket(&self) -> IoResult<Socket> {
+        Socket::new(
+            unspecified_address(self.server),
+            None,
+            self.options.buffer_size(),
+        )
+        .await
+    }
+}
+
+/// the way a transfer over a [`Socket`] fails. Any packet it refers to is in the buffer of the socket.
+type Failure = transport::Failure<IoError, IoError>;
+
+/// Makes an [`AsyncRead`] usable as the [`DataSource`] of a [`Sender`], like [`ReadSource`](crate::transport::ReadSource) does for a blocking reader.
+///
+/// The sender reads blocks without waiting, so the data is read ahead by [`fill`](Prefetch::fill) before the sender gets to it.
+struct Prefetch<R> {
+    inner: R,
+    /// the data read from `inner` that hasn't been released yet, starting at `start`.
+    buffered: VecDeque<u8>,
+    start: u64,
+    /// how far past `start` the data is read ahead.
+    read_ahead: u64,
+    finished: bool,
+    /// the error reading `inner` failed with, returned once the sender needs the data after it.
+    error: Option<IoError>,
+}
+
+impl<R: AsyncRead + Unpin> Prefetch<R> {
+    fn new(inner: R, settings: &Settings) -> Self {
+        Self {
+            inner,
+            buffered: VecDeque::new(),
+            start: 0,
+            // until the sender releases the blocks the peer acknowledged it may be a window ahead of `start` already,
+            // and it sends up to a window of blocks after those.
+            read_ahead: 2 * (settings.window_size * settings.blocksize) as u64,
+            finished: false,
+            error: None,
+        }
+    }
+
+    // reads from the inner reader until the data the sender may need next is buffered, the reader runs out or fails.
+    async fn fill(&mut self) {
+        let mut chunk = [0u8; 4096];
+        while !self.finished
+            && self.error.is_none()
+            && (self.buffered.len() as u64) < self.read_ahead
+        {
+            match self.inner.read(&mut chunk).await {
+                Ok(0) => self.finished = true,
+                Ok(n_bytes) => self.buffered.extend(&chunk[..n_bytes]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => self.error = Some(e),
+            }
+        }
+    }
+}
+
+impl<R> DataSource for Prefetch<R> {
+    type Error = IoError;
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> IoResult<usize> {
+        if offset < self.start {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                format!("the data at {offset} has already been released"),
+            ));
+        }
+        let skip = usize::try_from(offset - self.start).unwrap_or(usize::MAX);
+        let available = self.buffered.len().saturating_sub(skip);
+        if available < buffer.len() && !self.finished {
+            return Err(self.error.take().unwrap_or_else(|| {
+                IoError::other(format!("the data at {offset} hasn't been read yet"))
+            }));
+        }
+        let n_bytes = buffer.len().min(available);
+        for (to, &from) in buffer
+            .iter_mut()
+            .zip(self.buffered.range(skip..skip + n_bytes))
+        {
+            *to = from;
+        }
+        Ok(n_bytes)
+    }
+
+    fn release(&mut self, offset: u64) {
+        let n_bytes = usize::try_from(offset.saturating_sub(self.start))
+            .unwrap_or(usize::MAX)
+            .min(self.buffered.len());
+        self.buffered.drain(..n_bytes);
+        self.start += n_bytes as u64;
+    }
+}
+
+// writes a block of data to `sink`, flushing it if it's the last one.
+async fn write_block<W: AsyncWrite + Unpin>(
+    sink: &mut W,
+    data: &[u8],
+    is_last_block: bool,
+) -> IoResult<()> {
+    sink.write_all(data).await?;
+    if is_last_block {
+        sink.flush().await?;
+    }
+    Ok(())
+}
+
+/// the async counterpart of [`transport::send_blocks`](crate::transport::send_blocks), drives `sender` until the peer has acknowledged every block of `source`.
+async fn send_blocks<R: AsyncRead + Unpin>(
+    sock: &mut Socket,
+    sender: &mut Sender,
+    source: &mut Prefetch<R>,
+) -> Result<(), Failure> {
+    if sock.buffer.len() < sender.buffer_size() {
+        return Err(Failure::BufferTooSmall);
+    }
+    let start = Instant::now();
+    loop {
+        source.fill().await;
+        loop {
+            match sender.poll_transmit(start.elapsed(), source, &mut sock.buffer) {
+                Ok(Some(n_bytes)) => sock
+                    .send_raw(&sock.buffer[..n_bytes])
+                    .await
+                    .map_err(Failure::Transport)?,
+                Ok(None) => break,
+                Err(e) => {
+                    // try to notify the peer of the error before returning
+                    if let Ok(Some(n_bytes)) =
+                        sender.poll_transmit(start.elapsed(), source, &mut sock.buffer)
+                    {
+                        let _may_fail = sock.send_raw(&sock.buffer[..n_bytes]).await;
+                    }
+                    return Err(Failure::Data(e));
+                }
+            }
+        }
+        if sender.is_finished() {
+            return Ok(());
+        }
+        let received = sock
+            .wait_until(start, sender.deadline())
+            .await
+            .map_err(Failure::Transport)?;
+        let failure = match received {
+            Some(n_bytes) => match sender.handle_packet(&sock.buffer[..n_bytes]) {
+                Ok(()) => continue,
+                // only these leave the packet of the peer in the buffer, so the error for the peer is built elsewhere.
+                Err(stop @ (Stop::Peer(_) | Stop::Unexpected(_) | Stop::InvalidPacket(_))) => {
+                    let failure = Failure::stopped(stop, n_bytes);
+                    let mut error = [0u8; ERROR_BUFFER_SIZE];
+                    if let Ok(Some(n_bytes)) =
+                        sender.poll_transmit(start.elapsed(), source, &mut error)
+                    {
+                        let _may_fail = sock.send_raw(&error[..n_bytes]).await;
+                    }
+                    return Err(failure);
+                }
+                Err(stop) => Failure::stopped(stop, 0),
+            },
+            None => match sender.handle_timeout(start.elapsed()) {
+                Ok(()) => continue,
+                Err(stop) => Failure::stopped(stop, 0),
+            },
+        };
+        // try to notify the peer of the error before returning
+        if let Ok(Some(n_bytes)) = sender.poll_transmit(start.elapsed(), source, &mut sock.buffer) {
+            let _may_fail = sock.send_raw(&sock.buffer[..n_bytes]).await;
+        }
+        return Err(failure);
+    }
+}
+
+/// the async counterpart of [`transport::receive_blocks`](crate::transport::receive_blocks), drives `receiver` and writes the data it receives to `sink`.
+///
+/// Returns the amount of bytes written to `sink`.
+async fn receive_blocks<W: AsyncWrite + Unpin>(
+    sock: &mut Socket,
+    receiver: &mut Receiver,
+    sink: &mut W,
+) -> Result<u64, Failure> {
+    if sock.buffer.len() < receiver.buffer_size() {
+        return Err(Failure::BufferTooSmall);
+    }
+    let start = Instant::now();
+    loop {
+        if let Some(n_bytes) = receiver.poll_transmit(start.elapsed(), &mut sock.buffer) {
+            sock.send_raw(&sock.buffer[..n_bytes])
+                .await
+                .map_err(Failure::Transport)?;
+        }
+        if receiver.is_finished() {
+            return Ok(receiver.bytes_received());
+        }
+        let received = sock
+            .wait_until(start, receiver.deadline())
+            .await
+            .map_err(Failure::Transport)?;
+        let failure = match received {
+            Some(n_bytes) => match receiver.handle_packet(start.elapsed(), &sock.buffer[..n_bytes])
+            {
+                Ok(Some(data)) => {
+                    let Err(e) = write_block(sink, data, receiver.is_complete()).await else {
+                        continue;
+                    };
+                    receiver.abort(
+                        ErrorCode::DISK_FULL_OR_ALLOCATION_EXCEEDED,
+                        "Unexpected IO error",
+                    );
+                    Failure::Data(e)
+                }
+                Ok(None) => continue,
+                Err(Stop::TooManyBlocks) => Failure::TooManyBlocks,
+                // the packet of the peer stays in the buffer, so the error for the peer is built elsewhere.
+                Err(stop) => {
+                    let failure = Failure::stopped(stop, n_bytes);
+                    let mut error = [0u8; ERROR_BUFFER_SIZE];
+                    if let Some(n_bytes) = receiver.poll_transmit(start.elapsed(), &mut error) {
+                        let _may_fail = sock.send_raw(&error[..n_bytes]).await;
+                    }
+                    return Err(failure);
+                }
+            },
+            None => match receiver.handle_timeout(start.elapsed()) {
+                Ok(()) => continue,
+                Err(stop) => Failure::stopped(stop, 0),
+            },
+        };
+        // try to notify the peer of the error before returning
+        if let Some(n_bytes) = receiver.poll_transmit(start.elapsed(), &mut sock.buffer) {
+            let _may_fail = sock.send_raw(&sock.buffer[..n_bytes]).await;
+        }
+        return Err(failure);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::OpCode;
+    use std::io::Cursor;
+
+    // retransmits quickly, so the tests don't wait long for lost packets.
+    fn quick_retransmit() -> RetransmitPolicy {
+        RetransmitPolicy {
+            timeout: Duration::from_millis(100),
+            max_retries: 2,
+            exponential_backoff: false,
+        }
+    }
+
+    async fn localhost() -> UdpSocket {
+        UdpSocket::bind("127.0.0.1:0").await.unwrap()
+    }
+
+    // the next datagram arriving at `sock` and its sender, failing the test if nothing arrives in time.
+    async fn receive(sock: &UdpSocket) -> (Vec<u8>, SocketAddr) {
+        let mut buffer = vec![0u8; 1024];
+        let (n_bytes, from) =
+            ::tokio::time::timeout(Duration::from_secs(5), sock.recv_from(&mut buffer))
+                .await
+                .expect("nothing received")
+                .unwrap();
+        buffer.truncate(n_bytes);
+        (buffer, from)
+    }
+
+    // waits for a data packet from `from` and returns its block number and data.
+    async fn expect_data(sock: &UdpSocket, from: SocketAddr) -> (u16, Vec<u8>) {
+        let (bytes, sender) = receive(sock).await;
+        assert_eq!(sender, from);
+        match Packet::from_bytes(&bytes) {
+            Ok(Packet::Data(Data { block_nr, data })) => (block_nr, data.to_vec()),
+            other => panic!("expected a data packet, got {other:?}"),
+        }
+    }
+
+    async fn expect_ack(sock: &UdpSocket, from: SocketAddr, block_nr: u16) {
+        let (bytes, sender) = receive(sock).await;
+        assert_eq!(sender, from);
+        assert!(
+            matches!(Packet::from_bytes(&bytes), Ok(Packet::Ack(ack)) if ack.block_nr == block_nr),
+            "expected an ack of block {block_nr}, got {:?}",
+            Packet::from_bytes(&bytes)
+        );
+    }
+
+    async fn expect_error(sock: &UdpSocket, error_code: ErrorCode) {
+        let (bytes, _) = receive(sock).await;
+        assert!(
+            matches!(Packet::from_bytes(&bytes), Ok(Packet::Error(e)) if e.error_code == error_code),
+            "expected error {error_code}, got {:?}",
+            Packet::from_bytes(&bytes)
+        );
+    }
+
+    async fn send(sock: &UdpSocket, packet: Packet<'_>, to: SocketAddr) {
+        sock.send_to(&to_vec(packet), to).await.unwrap();
+    }
+
+    #[::tokio::test]
+    async fn get_and_put() {
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let mut server = Server::connect_with_port("127.0.0.1".parse().unwrap(), 0)
+            .await
+            .unwrap();
+        let server_addr = server.sock.local_addr().unwrap();
+        let served = data.clone();
+        let server_task = ::tokio::spawn(async move {
+            let (request, client) = server.get_next_request_from().await.unwrap();
+            assert!(request.is_read());
+            let mut options = OptionAck::new(request.blocksize, None, None);
+            options.window_size = request.window_size;
+            let transfer = server
+                .create_transfer_to(client, &served[..], options)
+                .await
+                .unwrap();
+            transfer.finish().await.unwrap();
+
+            let (request, client) = server.get_next_request_from().await.unwrap();
+            assert!(request.is_write());
+            let transfer = server
+                .create_transfer_from(client, Vec::new(), OptionAck::new(None, None, None))
+                .await
+                .unwrap();
+            transfer.finish().await.unwrap()
+        });
+
+        let mut client = Client::new(server_addr);
+        client.set_blocksize(Some(1000));
+        client.set_window_size(NonZeroU16::new(3));
+        let mut received = Vec::new();
+        assert_eq!(client.get("file", &mut received).await.unwrap(), 5000);
+        assert_eq!(received, data);
+        client.put("file", &data[..]).await.unwrap();
+        assert_eq!(server_task.await.unwrap(), 5000);
+    }
+
+    #[::tokio::test]
+    async fn lost_and_duplicate_acks() {
+        let data: Vec<u8> = (0..40u8).collect();
+        let mut server = Server::connect_with_port("127.0.0.1".parse().unwrap(), 0)
+            .await
+            .unwrap();
+        server.set_retransmit_policy(quick_retransmit());
+        let client = localhost().await;
+        let client_addr = client.local_addr().unwrap();
+        let mut options = OptionAck::new(Some(8), None, None);
+        options.window_size = NonZeroU16::new(2);
+        let transfer = server
+            .create_transfer_to(client_addr, Cursor::new(data.clone()), options)
+            .await
+            .unwrap();
+
+        let script = async {
+            let (bytes, server_tid) = receive(&client).await;
+            assert!(matches!(
+                Packet::from_bytes(&bytes),
+                Ok(Packet::OptionAck(_))
+            ));
+            // the network duplicates our ack of the option acknowledgement
+            send(&client, Packet::new_ack(0), server_tid).await;
+            send(&client, Packet::new_ack(0), server_tid).await;
+            assert_eq!(
+                expect_data(&client, server_tid).await,
+                (1, data[0..8].to_vec())
+            );
+            assert_eq!(expect_data(&client, server_tid).await.0, 2);
+            // block 2 got lost, the window continues from there.
+            send(&client, Packet::new_ack(1), server_tid).await;
+            assert_eq!(expect_data(&client, server_tid).await.0, 2);
+            assert_eq!(expect_data(&client, server_tid).await.0, 3);
+            send(&client, Packet::new_ack(3), server_tid).await;
+            send(&client, Packet::new_ack(3), server_tid).await;
+            assert_eq!(expect_data(&client, server_tid).await.0, 4);
+            let sent = Instant::now();
+            assert_eq!(expect_data(&client, server_tid).await.0, 5);
+            // our ack of this window gets lost, the duplicate ack doesn't make the server send it again before it times out.
+            assert_eq!(expect_data(&client, server_tid).await.0, 4);
+            assert!(sent.elapsed() >= Duration::from_millis(90));
+            assert_eq!(expect_data(&client, server_tid).await.0, 5);
+            send(&client, Packet::new_ack(5), server_tid).await;
+            assert_eq!(expect_data(&client, server_tid).await, (6, Vec::new()));
+            send(&client, Packet::new_ack(6), server_tid).await;
+        };
+        let (finished, ()) = ::tokio::join!(transfer.finish(), script);
+        finished.unwrap();
+    }
+
+    #[::tokio::test]
+    async fn lost_and_duplicate_blocks() {
+        let server = localhost().await;
+        let transfer = localhost().await;
+        let mut client = Client::new(server.local_addr().unwrap());
+        client.set_blocksize(Some(8));
+        client.set_window_size(NonZeroU16::new(2));
+        client.set_retransmit_policy(quick_retransmit());
+
+        let script = async {
+            let (bytes, client_addr) = receive(&server).await;
+            assert!(matches!(Packet::from_bytes(&bytes), Ok(Packet::Request(r)) if r.is_read()));
+            let mut options = OptionAck::new(Some(8), None, None);
+            options.window_size = NonZeroU16::new(2);
+            send(&transfer, Packet::OptionAck(options), client_addr).await;
+            expect_ack(&transfer, client_addr, 0).await;
+            // block 2 gets lost
+            send(&transfer, Packet::new_data(1, b"aaaaaaaa"), client_addr).await;
+            send(&transfer, Packet::new_data(3, b"cccccccc"), client_addr).await;
+            expect_ack(&transfer, client_addr, 1).await;
+            send(&transfer, Packet::new_data(2, b"bbbbbbbb"), client_addr).await;
+            send(&transfer, Packet::new_data(3, b"cccccccc"), client_addr).await;
+            expect_ack(&transfer, client_addr, 3).await;
+            // the network duplicates the last block
+            send(&transfer, Packet::new_data(4, b"dd"), client_addr).await;
+            send(&transfer, Packet::new_data(4, b"dd"), client_addr).await;
+            expect_ack(&transfer, client_addr, 4).await;
+        };
+        let mut received = Vec::new();
+        let (got, ()) = ::tokio::join!(client.get("file", &mut received), script);
+        assert_eq!(got.unwrap(), 26);
+        assert_eq!(received, b"aaaaaaaabbbbbbbbccccccccdd");
+    }
+
+    #[::tokio::test]
+    async fn client_stops_replying() {
+        let mut server = Server::connect_with_port("127.0.0.1".parse().unwrap(), 0)
+            .await
+            .unwrap();
+        server.set_retransmit_policy(quick_retransmit());
+        let client = localhost().await;
+        let client_addr = client.local_addr().unwrap();
+
+        // a client that never replies
+        let transfer = server
+            .create_transfer_from(client_addr, Vec::new(), OptionAck::new(None, None, None))
+            .await
+            .unwrap();
+        let timed_out = transfer.finish().await;
+        assert!(matches!(
+            timed_out,
+            Err(TransferError::TimedOut { attempts: 3 })
+        ));
+        let (bytes, server_tid) = receive(&client).await;
+        assert!(matches!(
+            Packet::from_bytes(&bytes),
+            Ok(Packet::Ack(Ack { block_nr: 0 }))
+        ));
+        for _ in 1..3 {
+            expect_ack(&client, server_tid, 0).await;
+        }
+        expect_error(&client, ErrorCode::NOT_DEFINED).await;
+    }
+
+    #[::tokio::test]
+    async fn protocol_violations() {
+        let server = localhost().await;
+        let transfer = localhost().await;
+        let mut client = Client::new(server.local_addr().unwrap());
+        client.set_retransmit_policy(quick_retransmit());
+
+        // the server rejects the request
+        let script = async {
+            let (_, client_addr) = receive(&server).await;
+            send(
+                &transfer,
+                Packet::new_error(ErrorCode::ACCESS_VIOLATION, "no"),
+                client_addr,
+            )
+            .await;
+        };
+        let (sent, ()) = ::tokio::join!(client.put("file", &b"data"[..]), script);
+        assert!(matches!(
+            sent,
+            Err(TransferError::Peer { error_code: ErrorCode::ACCESS_VIOLATION, message }) if message == "no"
+        ));
+
+        // the server sends an ack while we're receiving
+        let script = async {
+            let (_, client_addr) = receive(&server).await;
+            send(&transfer, Packet::new_data(1, &[0u8; 512]), client_addr).await;
+            expect_ack(&transfer, client_addr, 1).await;
+            send(&transfer, Packet::new_ack(1), client_addr).await;
+            expect_error(&transfer, ErrorCode::ILLEGAL_TFTP_OPERATION).await;
+        };
+        let (got, ()) = ::tokio::join!(client.get("file", Vec::new()), script);
+        assert!(matches!(
+            got,
+            Err(TransferError::ProtocolViolation {
+                opcode: Some(OpCode::Acknowledgement),
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{error::TransferError, packet::Packet, transport};
+use std::io::{Error as IoError, ErrorKind};
+
+/// turns the error of a transfer run over a std socket into a [`TransferError`], keeping the errors of the socket and the data as is.
+impl From<transport::Error<'_, IoError, IoError>> for TransferError {
+    fn from(error: transport::Error<'_, IoError, IoError>) -> Self {
+        match error {
+            transport::Error::Transport(e) | transport::Error::Data(e) => TransferError::Io(e),
+            transport::Error::TimedOut { attempts } => TransferError::TimedOut { attempts },
+            transport::Error::Peer(e) => TransferError::Peer {
+                error_code: e.error_code,
+                message: e.message.to_owned(),
+            },
+            transport::Error::Unexpected(packet) => TransferError::ProtocolViolation {
+                opcode: Some(packet.opcode()),
+                reason: format!("Received unexpected packet: {packet:?}"),
+            },
+            transport::Error::InvalidPacket(e) => TransferError::ProtocolViolation {
+                opcode: None,
+                reason: format!("Received invalid packet: {e}"),
+            },
+            transport::Error::OptionNegotiation(reason) => TransferError::OptionNegotiation(reason),
+            transport::Error::BufferTooSmall => TransferError::Io(IoError::new(
+                ErrorKind::InvalidInput,
+                "Buffer too small for the negotiated blocksize",
+            )),
+            transport::Error::TooManyBlocks => TransferError::TooManyBlocks,
+        }
+    }
+}
+
+// serializes a control packet (an ack, option ack or request) so it can be sent more than once.
+pub(crate) fn to_vec(packet: Packet) -> Vec<u8> {
+    let mut buffer = vec![0u8; 512];
+    loop {
+        match packet.to_bytes(&mut buffer) {
+            Ok(n_bytes) => {
+                buffer.truncate(n_bytes);
+                return buffer;
+            }
+            // requests with long filenames or many options might not fit
+            Err(_) => buffer.resize(buffer.len() * 2, 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{self, ErrorCode, OpCode};
+
+    #[test]
+    fn transfer_errors() {
+        let peer = TransferError::from(transport::Error::Peer(packet::Error::new(
+            ErrorCode::FILE_NOT_FOUND,
+            "no such file",
+        )));
+        assert!(matches!(
+            &peer,
+            TransferError::Peer { error_code: ErrorCode::FILE_NOT_FOUND, message } if message == "no such file"
+        ));
+        let unexpected = TransferError::from(transport::Error::Unexpected(Packet::new_ack(3)));
+        assert!(matches!(
+            unexpected,
+            TransferError::ProtocolViolation {
+                opcode: Some(OpCode::Acknowledgement),
+                ..
+            }
+        ));
+
+        // as an io error it keeps its kind, and can be taken out again.
+        let timed_out = IoError::from(TransferError::from(transport::Error::TimedOut {
+            attempts: 3,
+        }));
+        assert_eq!(timed_out.kind(), ErrorKind::TimedOut);
+        assert!(matches!(
+            timed_out
+                .into_inner()
+                .unwrap()
+                .downcast::<TransferError>()
+                .as_deref(),
+            Ok(TransferError::TimedOut { attempts: 3 })
+        ));
+    }
+}
@@ -0,0 +1,911 @@
+use crate::{
+    machine::{Receiver, Sender, Stop, ILLEGAL_OPERATION},
+    packet::{self, ErrorCode, OptionAck, Packet, Request, Rollover},
+};
+use core::{convert::Infallible, net::SocketAddr, time::Duration};
+
+/// Sends and receives the datagrams of a transfer. Implement this to run transfers over a network stack other than the one in std,
+/// for example on an embedded device.
+///
+/// With the `std` feature this is implemented for [`UdpSocket`](std::net::UdpSocket).
+/// A transport doesn't have to be connected to the peer. Transfers answer packets from other addresses with an unknown transfer ID error, and carry on.
+/// Timeouts are measured with the clock of the transport, so packets that keep arriving don't delay a retransmission, however many there are.
+pub trait Transport {
+    /// the error returned when sending or receiving fails.
+    type Error;
+
+    /// sends `data` as a single datagram to `addr`.
+    fn send_to(&mut self, data: &[u8], addr: SocketAddr) -> Result<(), Self::Error>;
+
+    /// receives a single datagram into `buffer`, returning its size and sender, or `None` if nothing arrived within `timeout`.
+    /// Datagrams larger than `buffer` may be truncated.
+    fn recv_from(
+        &mut self,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> Result<Option<(usize, SocketAddr)>, Self::Error>;
+
+    /// returns the address this transport receives datagrams on.
+    fn local_addr(&self) -> Result<SocketAddr, Self::Error>;
+
+    /// returns the time that has passed since some fixed point in the past, for example when the device booted.
+    /// It may never go backwards, and has to keep running while [`recv_from`](Transport::recv_from) waits.
+    fn now(&self) -> Duration;
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    type Error = T::Error;
+
+    fn send_to(&mut self, data: &[u8], addr: SocketAddr) -> Result<(), Self::Error> {
+        (**self).send_to(data, addr)
+    }
+
+    fn recv_from(
+        &mut self,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> Result<Option<(usize, SocketAddr)>, Self::Error> {
+        (**self).recv_from(buffer, timeout)
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr, Self::Error> {
+        (**self).local_addr()
+    }
+
+    fn now(&self) -> Duration {
+        (**self).now()
+    }
+}
+
+/// Where the data of an outgoing transfer comes from.
+///
+/// Blocks the peer didn't receive are read again, so the data has to be readable at any offset that hasn't been [released](DataSource::release) yet.
+/// Implemented for byte slices, use [`ReadSource`] to send the data of a [`Read`](std::io::Read).
+pub trait DataSource {
+    /// the error returned when reading fails.
+    type Error;
+
+    /// reads the data starting at `offset` into `buffer`, returning the amount of bytes read.
+    /// Less than `buffer.len()` bytes should only be returned at the end of the data.
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// called once the peer has received all data before `offset`, it won't be read again. Does nothing by default.
+    fn release(&mut self, offset: u64) {
+        let _ = offset;
+    }
+}
+
+impl DataSource for &[u8] {
+    type Error = Infallible;
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<usize, Infallible> {
+        let start = usize::try_from(offset).map_or(self.len(), |offset| offset.min(self.len()));
+        let n_bytes = buffer.len().min(self.len() - start);
+        buffer[..n_bytes].copy_from_slice(&self[start..start + n_bytes]);
+        Ok(n_bytes)
+    }
+}
+
+impl<S: DataSource + ?Sized> DataSource for &mut S {
+    type Error = S::Error;
+
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+        (**self).read_at(offset, buffer)
+    }
+
+    fn release(&mut self, offset: u64) {
+        (**self).release(offset)
+    }
+}
+
+/// Where the data of an incoming transfer goes. The data is written in order, every byte exactly once.
+///
+/// Use [`WriteSink`] to write the data to a [`Write`](std::io::Write).
+pub trait DataSink {
+    /// the error returned when writing fails.
+    type Error;
+
+    /// writes the next piece of data.
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// called once the last piece of data has been written. Does nothing by default.
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<K: DataSink + ?Sized> DataSink for &mut K {
+    type Error = K::Error;
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        (**self).write(data)
+    }
+
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        (**self).finish()
+    }
+}
+
+/// Controls how long to wait for a reply before sending a packet again, and how often to do so before giving up.
+///
+/// Used for both the data packets and the acknowledgements of a transfer, as well as for the option acknowledgement (or ack of block 0) that starts it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetransmitPolicy {
+    /// how long to wait for a reply before sending the last packet again.
+    pub timeout: Duration,
+    /// how many times the same packet is sent again before the transfer is aborted.
+    pub max_retries: u32,
+    /// if set, the timeout doubles every time the same packet is sent again.
+    pub exponential_backoff: bool,
+}
+
+impl Default for RetransmitPolicy {
+    /// waits one second for every reply, and sends a packet up to 5 times before giving up.
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(1),
+            max_retries: 5,
+            exponential_backoff: false,
+        }
+    }
+}
+
+impl RetransmitPolicy {
+    /// returns how long to wait for a reply after sending a packet for the `attempt`th time, starting at 0.
+    pub fn timeout_for_attempt(&self, attempt: u32) -> Duration {
+        if self.exponential_backoff {
+            self.timeout.saturating_mul(1 << attempt.min(16))
+        } else {
+            self.timeout
+        }
+    }
+}
+
+/// The parameters of a transfer both sides agreed on.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Settings {
+    pub blocksize: usize,
+    pub window_size: usize,
+    pub retransmit: RetransmitPolicy,
+    /// what the block number continues with after block 65535, or `None` if the transfer may not have more blocks than that.
+    pub rollover: Option<Rollover>,
+}
+
+impl Settings {
+    /// the settings of a transfer using the acknowledged `options`. Options that aren't set fall back to the defaults of [RFC-1350](https://www.rfc-editor.org/rfc/inline-errata/rfc1350.html).
+    /// A negotiated timeout replaces the timeout of `retransmit`. Without a negotiated rollover, block numbers roll over to 0.
+    pub fn from_options(options: &OptionAck, mut retransmit: RetransmitPolicy) -> Self {
+        if let Some(seconds) = options.timeout_seconds {
+            retransmit.timeout = Duration::from_secs(seconds.get().into());
+        }
+        Self {
+            blocksize: options.blocksize.unwrap_or(512) as usize,
+            window_size: options.window_size.map_or(1, |size| size.get() as usize),
+            retransmit,
+            rollover: Some(options.rollover.unwrap_or_default()),
+        }
+    }
+}
+
+/// Why a transfer over a [`Transport`] failed. `T` is the error of the transport, `D` the error of the data source or sink.
+///
+/// Packets received from the peer are borrowed from the buffer the transfer was run with.
+#[derive(Debug)]
+pub enum Error<'b, T, D> {
+    /// sending or receiving a datagram failed.
+    Transport(T),
+    /// reading the data to send or writing the data received failed. The peer was sent an error packet.
+    Data(D),
+    /// the peer stopped replying after the same packet was sent `attempts` times. The peer was sent an error packet.
+    TimedOut {
+        /// how many times the last packet was sent.
+        attempts: u32,
+    },
+    /// the peer aborted the transfer by sending an error packet.
+    Peer(packet::Error<'b>),
+    /// the peer sent a packet that doesn't fit the transfer. The peer was sent an error packet.
+    Unexpected(Packet<'b>),
+    /// the peer sent something that isn't a valid TFTP packet. The peer was sent an error packet.
+    InvalidPacket(crate::error::Error),
+    /// the server acknowledged options that weren't requested, or with values we can't work with. The server was sent an error packet.
+    OptionNegotiation(&'static str),
+    /// the buffer the transfer was run with can't hold a packet of the negotiated blocksize.
+    BufferTooSmall,
+    /// the data needs more than 65535 blocks, and block numbers may not roll over. The peer was sent an error packet.
+    TooManyBlocks,
+}
+
+/// An in progress transfer sending data to a peer, over any [`Transport`].
+/// does nothing until it is consumed with the [`finish`](Transfer::finish) method
+///
+/// This is what [`server::Transfer`](crate::server::Transfer) runs on top of a [`UdpSocket`](std::net::UdpSocket).
+pub struct Transfer<T: Transport, S: DataSource> {
+    transport: T,
+    peer: SocketAddr,
+    source: S,
+    options: OptionAck<'static>,
+    retransmit: RetransmitPolicy,
+    rollover: Option<Rollover>,
+}
+
+impl<T: Transport, S: DataSource> Transfer<T, S> {
+    /// creates a transfer sending the data of `source` to `peer` over `transport`, in reply to a read request.
+    /// If `options` isn't empty it is sent first, and the peer has to acknowledge it before the data is sent.
+    pub fn new(
+        transport: T,
+        peer: SocketAddr,
+        source: S,
+        options: OptionAck<'static>,
+        retransmit: RetransmitPolicy,
+    ) -> Self {
+        Self {
+            transport,
+            peer,
+            source,
+            rollover: Some(options.rollover.unwrap_or_default()),
+            options,
+            retransmit,
+        }
+    }
+
+    /// sets what the block number continues with after block 65535, or `None` to stop the transfer with [`Error::TooManyBlocks`] instead.
+    /// Defaults to the rollover in `options`, or [`Rollover::Zero`] if it isn't set.
+    pub fn set_rollover(&mut self, rollover: Option<Rollover>) {
+        self.rollover = rollover;
+    }
+
+    /// executes the transfer, using `buffer` to build and receive packets. The buffer has to hold at least 4 + blocksize bytes.
+    ///
+    /// Up to the negotiated window size blocks are sent before waiting for an acknowledgement, as described in [RFC-7440](https://www.rfc-editor.org/rfc/rfc7440.html).
+    /// If the peer acknowledges only part of a window, the transfer continues from the first block the peer is missing.
+    /// Duplicate and delayed acks are ignored rather than answered with the same blocks again, see [`Sender::handle_packet`](crate::machine::Sender::handle_packet).
+    /// Every window that isn't acknowledged in time is sent again, as described by the retransmit policy.
+    pub fn finish(mut self, buffer: &mut [u8]) -> Result<(), Error<'_, T::Error, S::Error>> {
+        let mut sender = Sender::new(self.options, self.retransmit);
+        sender.set_rollover(self.rollover);
+        let result = send_blocks(
+            &mut self.transport,
+            self.peer,
+            &mut sender,
+            &mut self.source,
+            buffer,
+        );
+        result.map_err(|failure| failure.with_buffer(buffer))
+    }
+}
+
+/// An in progress transfer receiving data from a peer, over any [`Transport`].
+/// does nothing until it is consumed with the [`finish`](IncomingTransfer::finish) method
+///
+/// This is what [`server::IncomingTransfer`](crate::server::IncomingTransfer) runs on top of a [`UdpSocket`](std::net::UdpSocket).
+pub struct IncomingTransfer<T: Transport, K: DataSink> {
+    transport: T,
+    peer: SocketAddr,
+    sink: K,
+    options: OptionAck<'static>,
+    retransmit: RetransmitPolicy,
+    rollover: Option<Rollover>,
+}
+
+impl<T: Transport, K: DataSink> IncomingTransfer<T, K> {
+    /// creates a transfer writing the data `peer` sends over `transport` to `sink`, in reply to a write request.
+    /// The request is acknowledged with `options`, or with an ack of block 0 if it is empty.
+    pub fn new(
+        transport: T,
+        peer: SocketAddr,
+        sink: K,
+        options: OptionAck<'static>,
+        retransmit: RetransmitPolicy,
+    ) -> Self {
+        Self {
+            transport,
+            peer,
+            sink,
+            rollover: Some(options.rollover.unwrap_or_default()),
+            options,
+            retransmit,
+        }
+    }
+
+    /// sets what the block number continues with after block 65535, or `None` to stop the transfer with [`Error::TooManyBlocks`] instead.
+    /// Defaults to the rollover in `options`, or [`Rollover::Zero`] if it isn't set.
+    pub fn set_rollover(&mut self, rollover: Option<Rollover>) {
+        self.rollover = rollover;
+    }
+
+    /// executes the transfer, using `buffer` to build and receive packets, and returns the amount of bytes written to the sink.
+    /// The buffer has to hold at least 4 + blocksize bytes.
+    ///
+    /// Every data packet is written to the sink and acknowledged, or every window of them if a window size was negotiated as described in [RFC-7440](https://www.rfc-editor.org/rfc/rfc7440.html).
+    /// If the next data packet doesn't arrive in time, the last acknowledgement is sent again as described by the retransmit policy.
+    /// If the peer sends a data packet we don't expect, the last block we received is acknowledged again and the packet is ignored.
+    pub fn finish(mut self, buffer: &mut [u8]) -> Result<u64, Error<'_, T::Error, K::Error>> {
+        let mut receiver = Receiver::new(self.options, self.retransmit);
+        receiver.set_rollover(self.rollover);
+        let result = receive_blocks(
+            &mut self.transport,
+            self.peer,
+            &mut receiver,
+            &mut self.sink,
+            buffer,
+        );
+        result.map_err(|failure| failure.with_buffer(buffer))
+    }
+}
+
+/// [`Error`] without the packets borrowed from the buffer, so the buffer can still be used while the transfer is running.
+#[derive(Debug)]
+pub(crate) enum Failure<T, D> {
+    Transport(T),
+    Data(D),
+    TimedOut(u32),
+    /// the peer sent an error or unexpected packet, which takes up the first `n` bytes of the buffer.
+    Reply(usize),
+    InvalidPacket(crate::error::Error),
+    OptionNegotiation(&'static str),
+    BufferTooSmall,
+    TooManyBlocks,
+}
+
+impl<T, D> Failure<T, D> {
+    /// the failure for a state machine stopping, on the packet in the first `n_bytes` of the buffer if it was stopped by one.
+    pub fn stopped(stop: Stop, n_bytes: usize) -> Self {
+        match stop {
+            Stop::Peer(_) | Stop::Unexpected(_) => Self::Reply(n_bytes),
+            Stop::InvalidPacket(e) => Self::InvalidPacket(e),
+            Stop::TimedOut { attempts } => Self::TimedOut(attempts),
+            Stop::TooManyBlocks => Self::TooManyBlocks,
+        }
+    }
+
+    /// turns this into an [`Error`], borrowing the packet it refers to from `buffer`.
+    pub fn with_buffer(self, buffer: &[u8]) -> Error<'_, T, D> {
+        match self {
+            Self::Transport(e) => Error::Transport(e),
+            Self::Data(e) => Error::Data(e),
+            Self::TimedOut(attempts) => Error::TimedOut { attempts },
+            Self::Reply(n_bytes) => match Packet::from_bytes(&buffer[..n_bytes]) {
+                Ok(Packet::Error(e)) => Error::Peer(e),
+                Ok(packet) => Error::Unexpected(packet),
+                Err(e) => Error::InvalidPacket(e),
+            },
+            Self::InvalidPacket(e) => Error::InvalidPacket(e),
+            Self::OptionNegotiation(reason) => Error::OptionNegotiation(reason),
+            Self::BufferTooSmall => Error::BufferTooSmall,
+            Self::TooManyBlocks => Error::TooManyBlocks,
+        }
+    }
+}
+
+// large enough for the error packets the state machines send when the peer breaks the protocol.
+pub(crate) const ERROR_BUFFER_SIZE: usize = 64;
+
+/// tries to tell the peer why we're aborting the transfer.
+pub(crate) fn send_error<T: Transport>(
+    transport: &mut T,
+    peer: SocketAddr,
+    buffer: &mut [u8],
+    error_code: ErrorCode,
+    message: &str,
+) {
+    if let Ok(n_bytes) = Packet::new_error(error_code, message).to_bytes(buffer) {
+        let _may_fail = transport.send_to(&buffer[..n_bytes], peer);
+    }
+}
+
+/// answers a datagram of `n_bytes` in `buffer` from someone other than the peer of a transfer with an unknown transfer ID error,
+/// as [RFC-1350](https://www.rfc-editor.org/rfc/inline-errata/rfc1350.html) asks for. The transfer itself isn't disturbed.
+/// Error packets aren't answered, so two hosts mistaking each other for strangers can't keep sending each other errors.
+pub(crate) fn reject_stranger<T: Transport>(
+    transport: &mut T,
+    stranger: SocketAddr,
+    buffer: &mut [u8],
+    n_bytes: usize,
+) {
+    if !matches!(Packet::from_bytes(&buffer[..n_bytes]), Ok(Packet::Error(_))) {
+        send_error(
+            transport,
+            stranger,
+            buffer,
+            ErrorCode::UNKNOWN_TRANSFER_ID,
+            "Unknown transfer ID",
+        );
+    }
+}
+
+/// sends `request` to `server` and waits for the first reply from the same ip address, from any port.
+/// The server replies from a new port that identifies the transfer, packets from other hosts are ignored.
+///
+/// Returns the size of the reply, which is left in `buffer`, and the address it came from.
+/// If no reply arrives in time, the request is sent again as described by `policy`.
+pub(crate) fn send_request<T: Transport, D>(
+    transport: &mut T,
+    server: SocketAddr,
+    buffer: &mut [u8],
+    request: &Request,
+    policy: &RetransmitPolicy,
+) -> Result<(usize, SocketAddr), Failure<T::Error, D>> {
+    for attempt in 0..=policy.max_retries {
+        let n_bytes = request
+            .to_bytes(buffer)
+            .map_err(|_| Failure::BufferTooSmall)?;
+        transport
+            .send_to(&buffer[..n_bytes], server)
+            .map_err(Failure::Transport)?;
+        let deadline = transport
+            .now()
+            .saturating_add(policy.timeout_for_attempt(attempt));
+        loop {
+            let remaining = deadline.saturating_sub(transport.now());
+            if remaining.is_zero() {
+                break;
+            }
+            match transport
+                .recv_from(buffer, remaining)
+                .map_err(Failure::Transport)?
+            {
+                Some((n_bytes, from)) if from.ip() == server.ip() => return Ok((n_bytes, from)),
+                _ => {}
+            }
+        }
+    }
+    Err(Failure::TimedOut(policy.max_retries + 1))
+}
+
+/// stops a transfer on a reply to its request that doesn't fit it, which is in the first `n_bytes` of `buffer`.
+/// Unless the server sent an error, it is told it broke the protocol.
+pub(crate) fn reject_reply<T: Transport, D>(
+    transport: &mut T,
+    server_tid: SocketAddr,
+    buffer: &[u8],
+    n_bytes: usize,
+) -> Failure<T::Error, D> {
+    let failure = match Packet::from_bytes(&buffer[..n_bytes]) {
+        Ok(Packet::Error(_)) => return Failure::Reply(n_bytes),
+        Ok(_) => Failure::Reply(n_bytes),
+        Err(e) => Failure::InvalidPacket(e),
+    };
+    let mut error = [0u8; ERROR_BUFFER_SIZE];
+    send_error(
+        transport,
+        server_tid,
+        &mut error,
+        ErrorCode::ILLEGAL_TFTP_OPERATION,
+        ILLEGAL_OPERATION,
+    );
+    failure
+}
+
+/// tells the server we don't agree with the options it acknowledged, and returns the failure describing that.
+pub(crate) fn refuse_options<T: Transport, D>(
+    transport: &mut T,
+    server_tid: SocketAddr,
+    buffer: &mut [u8],
+    reason: &'static str,
+) -> Failure<T::Error, D> {
+    send_error(
+        transport,
+        server_tid,
+        buffer,
+        ErrorCode::OPTION_NEGOTIATION_FAILED,
+        reason,
+    );
+    Failure::OptionNegotiation(reason)
+}
+
+/// drives `sender` over `transport` until the peer has acknowledged every block of `source`, keeping time with the clock of the transport.
+pub(crate) fn send_blocks<T: Transport, S: DataSource>(
+    transport: &mut T,
+    peer: SocketAddr,
+    sender: &mut Sender,
+    source: &mut S,
+    buffer: &mut [u8],
+) -> Result<(), Failure<T::Error, S::Error>> {
+    if buffer.len() < sender.buffer_size() {
+        return Err(Failure::BufferTooSmall);
+    }
+    loop {
+        loop {
+            match sender.poll_transmit(transport.now(), source, buffer) {
+                Ok(Some(n_bytes)) => transport
+                    .send_to(&buffer[..n_bytes], peer)
+                    .map_err(Failure::Transport)?,
+                Ok(None) => break,
+                Err(e) => {
+                    // try to notify the peer of the error before returning
+                    if let Ok(Some(n_bytes)) = sender.poll_transmit(transport.now(), source, buffer)
+                    {
+                        let _may_fail = transport.send_to(&buffer[..n_bytes], peer);
+                    }
+                    return Err(Failure::Data(e));
+                }
+            }
+        }
+        if sender.is_finished() {
+            return Ok(());
+        }
+        let stop = match wait_until(transport, peer, buffer, sender.deadline())? {
+            Some(n_bytes) => match sender.handle_packet(&buffer[..n_bytes]) {
+                Ok(()) => continue,
+                // only these leave the packet of the peer in the buffer, so the error for the peer is built elsewhere.
+                Err(stop @ (Stop::Peer(_) | Stop::Unexpected(_) | Stop::InvalidPacket(_))) => {
+                    let failure = Failure::stopped(stop, n_bytes);
+                    let mut error = [0u8; ERROR_BUFFER_SIZE];
+                    if let Ok(Some(n_bytes)) =
+                        sender.poll_transmit(transport.now(), source, &mut error)
+                    {
+                        let _may_fail = transport.send_to(&error[..n_bytes], peer);
+                    }
+                    return Err(failure);
+                }
+                Err(stop) => Failure::stopped(stop, 0),
+            },
+            None => match sender.handle_timeout(transport.now()) {
+                Ok(()) => continue,
+                Err(stop) => Failure::stopped(stop, 0),
+            },
+        };
+        // try to notify the peer of the error before returning
+        if let Ok(Some(n_bytes)) = sender.poll_transmit(transport.now(), source, buffer) {
+            let _may_fail = transport.send_to(&buffer[..n_bytes], peer);
+        }
+        return Err(stop);
+    }
+}
+
+/// drives `receiver` over `transport`, writing the data it receives to `sink`, until the final block has been acknowledged.
+///
+/// Returns the amount of bytes written to `sink`.
+pub(crate) fn receive_blocks<T: Transport, K: DataSink>(
+    transport: &mut T,
+    peer: SocketAddr,
+    receiver: &mut Receiver,
+    sink: &mut K,
+    buffer: &mut [u8],
+) -> Result<u64, Failure<T::Error, K::Error>> {
+    if buffer.len() < receiver.buffer_size() {
+        return Err(Failure::BufferTooSmall);
+    }
+    loop {
+        if let Some(n_bytes) = receiver.poll_transmit(transport.now(), buffer) {
+            transport
+                .send_to(&buffer[..n_bytes], peer)
+                .map_err(Failure::Transport)?;
+        }
+        if receiver.is_finished() {
+            return Ok(receiver.bytes_received());
+        }
+        let failure = match wait_until(transport, peer, buffer, receiver.deadline())? {
+            Some(n_bytes) => match receiver.handle_packet(transport.now(), &buffer[..n_bytes]) {
+                Ok(Some(data)) => {
+                    let written = sink.write(data).and_then(|_| {
+                        if receiver.is_complete() {
+                            sink.finish()
+                        } else {
+                            Ok(())
+                        }
+                    });
+                    let Err(e) = written else {
+                        continue;
+                    };
+                    receiver.abort(
+                        ErrorCode::DISK_FULL_OR_ALLOCATION_EXCEEDED,
+                        "Unexpected IO error",
+                    );
+                    Failure::Data(e)
+                }
+                Ok(None) => continue,
+                Err(Stop::TooManyBlocks) => Failure::TooManyBlocks,
+                // the packet of the peer stays in the buffer, so the error for the peer is built elsewhere.
+                Err(stop) => {
+                    let failure = Failure::stopped(stop, n_bytes);
+                    let mut error = [0u8; ERROR_BUFFER_SIZE];
+                    if let Some(n_bytes) = receiver.poll_transmit(transport.now(), &mut error) {
+                        let _may_fail = transport.send_to(&error[..n_bytes], peer);
+                    }
+                    return Err(failure);
+                }
+            },
+            None => match receiver.handle_timeout(transport.now()) {
+                Ok(()) => continue,
+                Err(stop) => Failure::stopped(stop, 0),
+            },
+        };
+        // try to notify the peer of the error before returning
+        if let Some(n_bytes) = receiver.poll_transmit(transport.now(), buffer) {
+            let _may_fail = transport.send_to(&buffer[..n_bytes], peer);
+        }
+        return Err(failure);
+    }
+}
+
+/// waits for a datagram from `peer` until `deadline` by the clock of `transport`, and returns its size.
+/// Datagrams from anyone else are [rejected](reject_stranger) on the way.
+fn wait_until<T: Transport, D>(
+    transport: &mut T,
+    peer: SocketAddr,
+    buffer: &mut [u8],
+    deadline: Option<Duration>,
+) -> Result<Option<usize>, Failure<T::Error, D>> {
+    let Some(deadline) = deadline else {
+        return Ok(None);
+    };
+    loop {
+        let remaining = deadline.saturating_sub(transport.now());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        match transport
+            .recv_from(buffer, remaining)
+            .map_err(Failure::Transport)?
+        {
+            Some((n_bytes, from)) if from == peer => return Ok(Some(n_bytes)),
+            Some((n_bytes, from)) => reject_stranger(transport, from, buffer, n_bytes),
+            // the transport may return a little early, so check the clock again.
+            None => {}
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+mod std_impls {
+    use super::{DataSink, DataSource, Transport};
+    use std::{
+        collections::VecDeque,
+        io::{Error as IoError, ErrorKind, Read, Result as IoResult, Write},
+        net::{SocketAddr, UdpSocket},
+        sync::OnceLock,
+        time::{Duration, Instant},
+    };
+
+    impl Transport for UdpSocket {
+        type Error = IoError;
+
+        fn send_to(&mut self, data: &[u8], addr: SocketAddr) -> IoResult<()> {
+            let bytes_send = UdpSocket::send_to(self, data, addr)?;
+            if bytes_send == data.len() {
+                Ok(())
+            } else {
+                Err(IoError::other(format!(
+                    "Failed to send UDP packet of size {bytes_send}"
+                )))
+            }
+        }
+
+        fn recv_from(
+            &mut self,
+            buffer: &mut [u8],
+            timeout: Duration,
+        ) -> IoResult<Option<(usize, SocketAddr)>> {
+            // a read timeout of zero is an error, so always wait at least a little while.
+            self.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+            match UdpSocket::recv_from(self, buffer) {
+                Ok(received) => Ok(Some(received)),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    Ok(None)
+                }
+                Err(e) => Err(e),
+            }
+        }
+
+        fn local_addr(&self) -> IoResult<SocketAddr> {
+            UdpSocket::local_addr(self)
+        }
+
+        fn now(&self) -> Duration {
+            now()
+        }
+    }
+
+    /// the time passed since it was first asked for, which is the clock of the std transports.
+    pub(crate) fn now() -> Duration {
+        static EPOCH: OnceLock<Instant> = OnceLock::new();
+        EPOCH.get_or_init(Instant::now).elapsed()
+    }
+
+    /// Makes the data of a [`Read`] usable as a [`DataSource`], by keeping the data the peer hasn't acknowledged yet in memory.
+    pub struct ReadSource<R: Read> {
+        inner: R,
+        /// the data read from `inner` that hasn't been released yet, starting at `start`.
+        buffered: VecDeque<u8>,
+        start: u64,
+        finished: bool,
+    }
+
+    impl<R: Read> ReadSource<R> {
+        /// creates a data source reading from `inner`.
+        pub fn new(inner: R) -> Self {
+            Self {
+                inner,
+                buffered: VecDeque::new(),
+                start: 0,
+                finished: false,
+            }
+        }
+
+        /// returns the wrapped reader.
+        pub fn into_inner(self) -> R {
+            self.inner
+        }
+    }
+
+    impl<R: Read> DataSource for ReadSource<R> {
+        type Error = IoError;
+
+        fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> IoResult<usize> {
+            if offset < self.start {
+                return Err(IoError::new(
+                    ErrorKind::InvalidInput,
+                    format!("the data at {offset} has already been released"),
+                ));
+            }
+            let mut chunk = [0u8; 4096];
+            while !self.finished
+                && self.start + (self.buffered.len() as u64) < offset + buffer.len() as u64
+            {
+                match self.inner.read(&mut chunk) {
+                    Ok(0) => self.finished = true,
+                    Ok(n_bytes) => self.buffered.extend(&chunk[..n_bytes]),
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            let skip = usize::try_from(offset - self.start).unwrap_or(usize::MAX);
+            let n_bytes = buffer.len().min(self.buffered.len().saturating_sub(skip));
+            for (to, &from) in buffer
+                .iter_mut()
+                .zip(self.buffered.range(skip..skip + n_bytes))
+            {
+                *to = from;
+            }
+            Ok(n_bytes)
+        }
+
+        fn release(&mut self, offset: u64) {
+            let n_bytes = usize::try_from(offset.saturating_sub(self.start))
+                .unwrap_or(usize::MAX)
+                .min(self.buffered.len());
+            self.buffered.drain(..n_bytes);
+            self.start += n_bytes as u64;
+        }
+    }
+
+    /// Makes a [`Write`] usable as a [`DataSink`]. The writer is flushed once the last piece of data has been written.
+    pub struct WriteSink<W: Write> {
+        inner: W,
+    }
+
+    impl<W: Write> WriteSink<W> {
+        /// creates a data sink writing to `inner`.
+        pub fn new(inner: W) -> Self {
+            Self { inner }
+        }
+
+        /// returns the wrapped writer.
+        pub fn into_inner(self) -> W {
+            self.inner
+        }
+    }
+
+    impl<W: Write> DataSink for WriteSink<W> {
+        type Error = IoError;
+
+        fn write(&mut self, data: &[u8]) -> IoResult<()> {
+            self.inner.write_all(data)
+        }
+
+        fn finish(&mut self) -> IoResult<()> {
+            self.inner.flush()
+        }
+    }
+}
+
+#[cfg(feature = "simulator")]
+pub(crate) use std_impls::now;
+#[cfg(feature = "std")]
+#[doc(cfg(feature = "std"))]
+pub use std_impls::{ReadSource, WriteSink};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slice_source() {
+        let mut source: &[u8] = b"0123456789";
+        let mut buffer = [0u8; 4];
+        assert_eq!(source.read_at(8, &mut buffer), Ok(2));
+        assert_eq!(&buffer[..2], b"89");
+        assert_eq!(source.read_at(12, &mut buffer), Ok(0));
+    }
+
+    #[test]
+    fn retransmit_backoff() {
+        let mut policy = RetransmitPolicy {
+            timeout: Duration::from_millis(100),
+            max_retries: 5,
+            exponential_backoff: false,
+        };
+        assert_eq!(policy.timeout_for_attempt(3), Duration::from_millis(100));
+        policy.exponential_backoff = true;
+        let schedule = [0, 1, 2, 3].map(|attempt| policy.timeout_for_attempt(attempt));
+        assert_eq!(schedule, [100, 200, 400, 800].map(Duration::from_millis));
+        // the timeout stops growing at some point, and never overflows.
+        assert_eq!(
+            policy.timeout_for_attempt(40),
+            policy.timeout_for_attempt(16)
+        );
+        policy.timeout = Duration::MAX;
+        assert_eq!(policy.timeout_for_attempt(2), Duration::MAX);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn read_source() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        let mut source = ReadSource::new(&data[..]);
+        let mut buffer = [0u8; 512];
+        assert_eq!(source.read_at(1024, &mut buffer).unwrap(), 512);
+        assert_eq!(&buffer[..], &data[1024..1536]);
+        // data that hasn't been released can be read again
+        assert_eq!(source.read_at(0, &mut buffer).unwrap(), 512);
+        assert_eq!(&buffer[..], &data[..512]);
+        source.release(512);
+        assert!(source.read_at(0, &mut buffer).is_err());
+        assert_eq!(source.read_at(9728, &mut buffer).unwrap(), 272);
+        assert_eq!(&buffer[..272], &data[9728..]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn udp_round_trip() {
+        use crate::client::Client;
+        use core::num::NonZeroU16;
+        use std::net::UdpSocket;
+
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let mut server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = Transport::local_addr(&server).unwrap();
+        let source = data.clone();
+        let serving = std::thread::spawn(move || {
+            let mut buffer = [0u8; 1024 + 4];
+            let (n_bytes, client) =
+                Transport::recv_from(&mut server, &mut buffer, Duration::from_secs(5))
+                    .unwrap()
+                    .unwrap();
+            let Ok(Packet::Request(request)) = Packet::from_bytes(&buffer[..n_bytes]) else {
+                panic!("expected a request");
+            };
+            let mut options = OptionAck::new(request.blocksize, None, None);
+            options.window_size = request.window_size;
+            let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+            Transfer::new(
+                sock,
+                client,
+                &source[..],
+                options,
+                RetransmitPolicy::default(),
+            )
+            .finish(&mut buffer)
+            .map_err(|e| format!("{e:?}"))
+        });
+
+        let mut client = Client::new(server_addr);
+        client.set_blocksize(Some(1024));
+        client.set_window_size(NonZeroU16::new(3));
+        let mut sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut received = Vec::new();
+        let mut buffer = [0u8; 1024 + 4];
+        let n_bytes = client
+            .get_with(
+                &mut sock,
+                "file",
+                WriteSink::new(&mut received),
+                &mut buffer,
+            )
+            .unwrap();
+        assert_eq!(n_bytes, data.len() as u64);
+        assert_eq!(received, data);
+        serving.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,788 @@
+//! runs transfers between the client and a server over a simulated network that loses, duplicates, reorders and delays packets.
+
+use simple_tftp::{
+    client::Client,
+    error::TransferError,
+    netascii::{translated_len, ModeReader, ModeWriter},
+    options::OptionPolicy,
+    packet::{Error, ErrorCode, Mode, OptionAck, Packet, Request, Rollover},
+    server::{Handler, Limits, Server},
+    simulator::{Conditions, Network, SimulatedSocket, Statistics},
+    transport::{
+        self, IncomingTransfer, ReadSource, RetransmitPolicy, Transfer, Transport, WriteSink,
+    },
+};
+use std::{
+    fmt::Debug,
+    io::{self, Cursor, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    num::NonZeroU16,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+const SERVER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 69);
+const CLIENT: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 0);
+
+const SEEDS: [u64; 4] = [1, 2, 3, 0xdecaf];
+
+const LOSSY: Conditions = Conditions {
+    drop_rate: 0.1,
+    duplicate_rate: 0.1,
+    reorder_rate: 0.1,
+    max_delay: Duration::from_millis(2),
+};
+
+// short timeouts keep the tests fast, and enough retries make it very unlikely a transfer gives up on a lossy network.
+fn retransmit() -> RetransmitPolicy {
+    RetransmitPolicy {
+        timeout: Duration::from_millis(20),
+        max_retries: 12,
+        exponential_backoff: false,
+    }
+}
+
+fn file(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i * 7 + i / 251) as u8).collect()
+}
+
+fn client(blocksize: Option<u16>, window_size: Option<u16>) -> Client {
+    let mut client = Client::new(SERVER);
+    client.set_blocksize(blocksize);
+    client.set_window_size(window_size.and_then(NonZeroU16::new));
+    client.set_request_transfer_size(true);
+    client.set_retransmit_policy(retransmit());
+    client
+}
+
+/// serves `file` for every read request, and keeps the data of write requests.
+#[derive(Default)]
+struct Memory {
+    file: Vec<u8>,
+    written: Shared,
+    // the filename and outcome of every finished transfer.
+    finished: Mutex<Vec<(String, bool)>>,
+    // set to limit the window size after the option policy of the server picked one.
+    max_window_size: Option<NonZeroU16>,
+}
+
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Memory {
+    fn new(file: Vec<u8>) -> Self {
+        Self {
+            file,
+            ..Self::default()
+        }
+    }
+
+    fn written(&self) -> Vec<u8> {
+        self.written.0.lock().unwrap().clone()
+    }
+}
+
+impl Handler for Memory {
+    type Reader = Cursor<Vec<u8>>;
+    type Writer = Shared;
+
+    fn open_read(
+        &self,
+        request: &Request,
+        _: SocketAddr,
+    ) -> Result<(Self::Reader, Option<u64>), Error<'static>> {
+        let size = match request.mode {
+            Mode::Octet => self.file.len() as u64,
+            Mode::NetAscii => translated_len(&self.file[..]).unwrap(),
+        };
+        Ok((Cursor::new(self.file.clone()), Some(size)))
+    }
+
+    fn open_write(&self, _: &Request, _: SocketAddr) -> Result<Self::Writer, Error<'static>> {
+        Ok(self.written.clone())
+    }
+
+    fn transfer_finished(&self, filename: &str, _: SocketAddr, result: &Result<(), TransferError>) {
+        let finished = (filename.to_owned(), result.is_ok());
+        self.finished.lock().unwrap().push(finished);
+    }
+
+    fn negotiate_options(&self, _: &Request, _: SocketAddr, options: &mut OptionAck<'static>) {
+        if let Some(max) = self.max_window_size {
+            options.window_size = options.window_size.map(|size| size.min(max));
+        }
+    }
+}
+
+// a server whose settings are used to answer requests on the simulated network. Its own socket is never used.
+fn server() -> Server {
+    let mut server = Server::connect_with_port(IpAddr::V4(Ipv4Addr::LOCALHOST), 0).unwrap();
+    server.set_retransmit_policy(retransmit());
+    server
+}
+
+// binds the sockets of the transfers of `server`, on new ports of the server's ip.
+fn bind(network: &Network) -> impl Fn() -> io::Result<SimulatedSocket> + '_ {
+    || network.bind(SocketAddr::new(SERVER.ip(), 0))
+}
+
+/// how a transfer of the server ended.
+#[derive(Debug)]
+enum Served {
+    /// the transfer finished, with the data the client sent for a write request.
+    Finished(Vec<u8>),
+    /// the last ack of the client got lost, so the server gave up waiting for it.
+    /// The client has all the data by then, so this is fine.
+    TimedOut,
+    /// the file needs more block numbers than there are, and the server doesn't let them roll over.
+    TooManyBlocks,
+}
+
+// answers a single request on the simulated network with a `Server`, acknowledging every option the client asked for.
+fn serve(network: &Network, file: Vec<u8>) -> JoinHandle<Served> {
+    serve_with(network, Memory::new(file), server())
+}
+
+// like `serve`, but block numbers roll over to `rollover` unless the client asks for something else.
+// If `rollover` is `None` they may not roll over, and the rollover option isn't acknowledged.
+fn serve_with_rollover(
+    network: &Network,
+    file: Vec<u8>,
+    rollover: Option<Rollover>,
+) -> JoinHandle<Served> {
+    let mut server = server();
+    server.set_rollover(rollover);
+    server.set_option_policy(OptionPolicy {
+        rollover: rollover.is_some(),
+        ..OptionPolicy::default()
+    });
+    serve_with(network, Memory::new(file), server)
+}
+
+// answers a single request on the simulated network with `server` and `handler`.
+fn serve_with(network: &Network, handler: Memory, server: Server) -> JoinHandle<Served> {
+    let mut sock = network.bind(SERVER).unwrap();
+    let network = network.clone();
+    thread::spawn(move || {
+        let mut buffer = vec![0u8; 4096];
+        let (n_bytes, client) = sock
+            .recv_from(&mut buffer, Duration::from_secs(5))
+            .unwrap()
+            .expect("no request received");
+        let Ok(Packet::Request(request)) = Packet::from_bytes(&buffer[..n_bytes]) else {
+            panic!("expected a request");
+        };
+        let transfer = server
+            .handle_request_with(&request, client, &handler, bind(&network))
+            .unwrap();
+        drop(sock);
+        match transfer.finish() {
+            Ok(()) => Served::Finished(handler.written()),
+            Err(TransferError::TimedOut { .. }) => Served::TimedOut,
+            Err(TransferError::TooManyBlocks) => Served::TooManyBlocks,
+            Err(e) => panic!("server transfer failed: {e:?}"),
+        }
+    })
+}
+
+fn get(network: &Network, client: &Client, file: &[u8]) {
+    get_from(network, serve(network, file.to_vec()), client, file);
+}
+
+// downloads `file` from the transfer `server` answers the request with.
+fn get_from(network: &Network, server: JoinHandle<Served>, client: &Client, file: &[u8]) {
+    let mut sock = network.bind(CLIENT).unwrap();
+    let mut received = Vec::new();
+    let mut buffer = vec![0u8; 4096];
+    let n_bytes = client
+        .get_with(
+            &mut sock,
+            "file",
+            WriteSink::new(&mut received),
+            &mut buffer,
+        )
+        .unwrap();
+    assert_eq!(n_bytes, file.len() as u64);
+    assert!(received == file, "received data differs from the file");
+    match server.join().unwrap() {
+        Served::Finished(_) | Served::TimedOut => {}
+        Served::TooManyBlocks => panic!("server ran out of block numbers"),
+    }
+}
+
+fn put(network: &Network, client: &Client, file: &[u8]) {
+    let server = serve(network, Vec::new());
+    let mut sock = network.bind(CLIENT).unwrap();
+    let mut buffer = vec![0u8; 4096];
+    let sent = client.put_with(&mut sock, "file", file, &mut buffer);
+    match server.join().unwrap() {
+        Served::Finished(received) => assert!(received == file, "sent data differs from the file"),
+        Served::TimedOut => panic!(
+            "server timed out, client: {sent:?} {:?}",
+            network.statistics()
+        ),
+        Served::TooManyBlocks => panic!("server ran out of block numbers"),
+    }
+    // the client can't tell whether the last block arrived if the final ack got lost.
+    if let Err(e) = sent {
+        assert!(matches!(e, transport::Error::TimedOut { .. }), "{e:?}");
+    }
+}
+
+#[test]
+fn perfect_network() {
+    let network = Network::new(Conditions::NONE, 0);
+    let file = file(10_000);
+    get(&network, &client(None, None), &file);
+    put(&network, &client(None, None), &file);
+    get(&network, &client(Some(1024), Some(4)), &file);
+    put(&network, &client(Some(1024), Some(4)), &file);
+    let Statistics {
+        sent,
+        dropped,
+        duplicated,
+        reordered,
+        delivered,
+    } = network.statistics();
+    assert_eq!((dropped, duplicated, reordered), (0, 0, 0));
+    assert_eq!(sent, delivered);
+}
+
+#[test]
+fn lossy_get() {
+    let file = file(40_000);
+    for seed in SEEDS {
+        let network = Network::new(LOSSY, seed);
+        get(&network, &client(None, None), &file);
+        get(&network, &client(Some(1024), Some(8)), &file);
+        let statistics = network.statistics();
+        assert!(statistics.dropped > 0, "{statistics:?}");
+        assert!(statistics.duplicated > 0, "{statistics:?}");
+        assert!(statistics.reordered > 0, "{statistics:?}");
+    }
+}
+
+#[test]
+fn lossy_put() {
+    let file = file(40_000);
+    for seed in SEEDS {
+        let network = Network::new(LOSSY, seed);
+        put(&network, &client(None, None), &file);
+        put(&network, &client(Some(1024), Some(8)), &file);
+        let statistics = network.statistics();
+        assert!(statistics.dropped > 0, "{statistics:?}");
+        assert!(statistics.duplicated > 0, "{statistics:?}");
+        assert!(statistics.reordered > 0, "{statistics:?}");
+    }
+}
+
+#[test]
+fn blocksize_multiple() {
+    // the transfer ends with an empty block, which can get lost like any other.
+    let file = file(512 * 20);
+    for seed in SEEDS {
+        let network = Network::new(LOSSY, seed);
+        get(&network, &client(None, Some(4)), &file);
+        put(&network, &client(None, Some(4)), &file);
+    }
+}
+
+#[test]
+fn option_policy() {
+    // the policy of the server caps the blocksize, and the handler the window size the policy picked.
+    let file = file(20_000);
+    let server = || {
+        let mut server = server();
+        server.set_option_policy(OptionPolicy {
+            max_blocksize: 1024,
+            ..OptionPolicy::default()
+        });
+        server
+    };
+    let handler = || Memory {
+        max_window_size: NonZeroU16::new(2),
+        ..Memory::new(file.clone())
+    };
+
+    let network = Network::new(Conditions::NONE, 0);
+    let served = serve_with(&network, handler(), server());
+    let mut sock = network.bind(CLIENT).unwrap();
+    let mut request = Request::new_read_request("file", Some(1428));
+    request.window_size = NonZeroU16::new(16);
+    request.transfer_size = Some(0);
+    send(&mut sock, Packet::Request(request), SERVER);
+    let mut buffer = [0u8; 1024];
+    let (n_bytes, _) = sock
+        .recv_from(&mut buffer, Duration::from_secs(5))
+        .unwrap()
+        .expect("no reply to the request");
+    let Ok(Packet::OptionAck(options)) = Packet::from_bytes(&buffer[..n_bytes]) else {
+        panic!("expected an option acknowledgement");
+    };
+    assert_eq!(options.blocksize, Some(1024));
+    assert_eq!(options.window_size, NonZeroU16::new(2));
+    assert_eq!(options.transfer_size, Some(file.len() as u64));
+    // the request is never acknowledged.
+    assert!(matches!(served.join().unwrap(), Served::TimedOut));
+    drop(sock);
+
+    // the client goes along with the options it was given.
+    for seed in SEEDS {
+        let network = Network::new(LOSSY, seed);
+        let served = serve_with(&network, handler(), server());
+        get_from(&network, served, &client(Some(1428), Some(16)), &file);
+    }
+}
+
+/// the listening socket of a server, which fails once `stop` is set to make [`Server::serve_with`] return.
+struct Stoppable {
+    sock: SimulatedSocket,
+    stop: Arc<AtomicBool>,
+}
+
+impl Transport for Stoppable {
+    type Error = io::Error;
+
+    fn send_to(&mut self, data: &[u8], addr: SocketAddr) -> io::Result<()> {
+        self.sock.send_to(data, addr)
+    }
+
+    fn recv_from(
+        &mut self,
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> io::Result<Option<(usize, SocketAddr)>> {
+        if self.stop.load(Ordering::Relaxed) {
+            return Err(io::Error::other("stopped"));
+        }
+        // wakes up regularly to notice being stopped.
+        self.sock
+            .recv_from(buffer, timeout.min(Duration::from_millis(10)))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.sock.local_addr()
+    }
+
+    fn now(&self) -> Duration {
+        self.sock.now()
+    }
+}
+
+fn send(sock: &mut SimulatedSocket, packet: Packet, to: SocketAddr) {
+    let mut buffer = [0u8; 1024];
+    let n_bytes = packet.to_bytes(&mut buffer).unwrap();
+    sock.send_to(&buffer[..n_bytes], to).unwrap();
+}
+
+#[test]
+fn busy() {
+    let network = Network::new(Conditions::NONE, 0);
+    let mut server = server();
+    server.set_limits(Limits {
+        max_transfers: 1,
+        max_transfers_per_client: 1,
+    });
+    let file = file(2000);
+    let handler = Memory::new(file.clone());
+    let stop = Arc::new(AtomicBool::new(false));
+    let listener = Stoppable {
+        sock: network.bind(SERVER).unwrap(),
+        stop: stop.clone(),
+    };
+    thread::scope(|s| {
+        let serving = s.spawn(|| server.serve_with(listener, bind(&network), &handler));
+
+        // the first client takes the only slot, and keeps it by not acknowledging the first block.
+        let mut first = network.bind(CLIENT).unwrap();
+        send(
+            &mut first,
+            Packet::Request(Request::new_read_request("first", None)),
+            SERVER,
+        );
+        let mut buffer = vec![0u8; 4096];
+        let (_, transfer) = first
+            .recv_from(&mut buffer, Duration::from_secs(5))
+            .unwrap()
+            .expect("the first request wasn't answered");
+
+        // every other client is told to try again later.
+        let client = client(None, None);
+        let mut sock = network.bind(CLIENT).unwrap();
+        let result = client.get_with(&mut sock, "second", WriteSink::new(Vec::new()), &mut buffer);
+        assert!(
+            matches!(&result, Err(transport::Error::Peer(e)) if e.error_code == ErrorCode::NOT_DEFINED && e.message.contains("busy")),
+            "{result:?}"
+        );
+
+        // once the first transfer is aborted there is room again.
+        send(
+            &mut first,
+            Packet::new_error(ErrorCode::NOT_DEFINED, "cancelled"),
+            transfer,
+        );
+        let start = Instant::now();
+        while handler.finished.lock().unwrap().is_empty() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "the first transfer didn't finish"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        let mut received = Vec::new();
+        client
+            .get_with(
+                &mut sock,
+                "second",
+                WriteSink::new(&mut received),
+                &mut buffer,
+            )
+            .unwrap();
+        assert!(received == file, "received data differs from the file");
+
+        stop.store(true, Ordering::Relaxed);
+        assert!(serving.join().unwrap().is_err());
+    });
+    // the refused request never reached the handler.
+    assert_eq!(
+        *handler.finished.lock().unwrap(),
+        [("first".to_owned(), false), ("second".to_owned(), true)]
+    );
+}
+
+#[test]
+fn lossy_netascii() {
+    // text with every kind of line ending, which the server translates to and from netascii.
+    let text: Vec<u8> = (0..3000)
+        .flat_map(|i| match i % 4 {
+            0 => &b"line\n"[..],
+            1 => b"cr\r",
+            2 => b"crlf\r\n",
+            _ => b"\r\r\n\n",
+        })
+        .copied()
+        .collect();
+    let size = translated_len(&text[..]).unwrap();
+    assert!(size > text.len() as u64);
+    for seed in SEEDS {
+        let network = Network::new(LOSSY, seed);
+        let mut client = client(None, Some(4));
+        client.set_mode(Mode::NetAscii);
+        let mut buffer = vec![0u8; 4096];
+
+        let served = serve(&network, text.clone());
+        let mut sock = network.bind(CLIENT).unwrap();
+        let mut received = ModeWriter::new(Vec::new(), Mode::NetAscii);
+        let n_bytes = client
+            .get_with(&mut sock, "file", &mut received, &mut buffer)
+            .unwrap();
+        assert_eq!(n_bytes, size);
+        assert!(received.finish().unwrap() == text, "received text differs");
+        assert!(matches!(
+            served.join().unwrap(),
+            Served::Finished(_) | Served::TimedOut
+        ));
+        drop(sock);
+
+        let served = serve(&network, Vec::new());
+        let mut sock = network.bind(CLIENT).unwrap();
+        let source = ReadSource::new(ModeReader::new(&text[..], Mode::NetAscii));
+        let sent = client.put_with(&mut sock, "file", source, &mut buffer);
+        match served.join().unwrap() {
+            Served::Finished(written) => assert!(written == text, "sent text differs"),
+            other => panic!("server {other:?}, client: {sent:?}"),
+        }
+    }
+}
+
+#[test]
+fn sorcerers_apprentice() {
+    // every ack arrives twice. If duplicate acks made the server send the next block again,
+    // every block after that would be sent twice as often as the one before it.
+    let network = Network::new(
+        Conditions {
+            duplicate_rate: 1.0,
+            ..Conditions::NONE
+        },
+        0,
+    );
+    let file = file(512 * 30 + 1);
+    get(&network, &client(None, None), &file);
+    // the request, the option acknowledgement and 31 blocks, plus an ack and a second ack answering the duplicate for each of them.
+    let sent = network.statistics().sent;
+    assert!(sent <= 1 + 3 * 32, "{sent} datagrams sent");
+}
+
+#[test]
+fn rollover() {
+    // 512 byte blocks, so block numbers roll over after 32 MiB.
+    let file = file(512 * 65536 + 100);
+    let network = Network::new(Conditions::NONE, 0);
+    for rollover in [None, Some(Rollover::Zero), Some(Rollover::One)] {
+        let mut client = client(None, Some(16));
+        client.set_rollover(rollover);
+        get(&network, &client, &file);
+        put(&network, &client, &file);
+    }
+}
+
+#[test]
+fn rollover_disallowed() {
+    let file = file(512 * 65536);
+    let network = Network::new(Conditions::NONE, 0);
+    let mut client = client(None, Some(16));
+    client.set_rollover(Some(Rollover::One));
+
+    let server = serve_with_rollover(&network, file.clone(), None);
+    let mut sock = network.bind(CLIENT).unwrap();
+    let mut received = Vec::new();
+    let mut buffer = vec![0u8; 4096];
+    let result = client.get_with(
+        &mut sock,
+        "file",
+        WriteSink::new(&mut received),
+        &mut buffer,
+    );
+    assert!(
+        matches!(&result, Err(transport::Error::Peer(e)) if e.error_code == ErrorCode::NOT_DEFINED),
+        "{result:?}"
+    );
+    assert_eq!(received.len(), 512 * 65535);
+    assert!(matches!(server.join().unwrap(), Served::TooManyBlocks));
+    drop(sock);
+
+    let server = serve_with_rollover(&network, Vec::new(), None);
+    let mut sock = network.bind(CLIENT).unwrap();
+    let result = client.put_with(&mut sock, "file", &file[..], &mut buffer);
+    assert!(
+        matches!(&result, Err(transport::Error::Peer(e)) if e.error_code == ErrorCode::DISK_FULL_OR_ALLOCATION_EXCEEDED),
+        "{result:?}"
+    );
+    assert!(matches!(server.join().unwrap(), Served::TooManyBlocks));
+}
+
+#[test]
+fn stranger() {
+    // the client binds a fixed port, so the transfer socket of the server is the first one bound to port 0 and the stranger can guess both.
+    const CLIENT_TID: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 4000);
+    const SERVER_TID: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 49152);
+    const STRANGER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3)), 69);
+    // delays keep the transfer going long enough for the stranger to interfere with it.
+    let network = Network::new(
+        Conditions {
+            max_delay: Duration::from_millis(1),
+            ..Conditions::NONE
+        },
+        0,
+    );
+    let file = file(512 * 200);
+    let mut stranger = network.bind(STRANGER).unwrap();
+    let stranger = thread::spawn(move || {
+        let mut rejected_by = Vec::new();
+        let mut buffer = [0u8; 516];
+        // gives up a while after the transfer is over.
+        for _ in 0..1000 {
+            if rejected_by.len() == 2 {
+                break;
+            }
+            for tid in [CLIENT_TID, SERVER_TID] {
+                stranger.send_to(&[0, 4, 0, 1], tid).unwrap();
+            }
+            while let Some((n_bytes, from)) = stranger
+                .recv_from(&mut buffer, Duration::from_millis(1))
+                .unwrap()
+            {
+                let Ok(Packet::Error(error)) = Packet::from_bytes(&buffer[..n_bytes]) else {
+                    panic!("the stranger was sent something other than an error");
+                };
+                assert_eq!(error.error_code, ErrorCode::UNKNOWN_TRANSFER_ID);
+                if !rejected_by.contains(&from) {
+                    rejected_by.push(from);
+                }
+            }
+        }
+        rejected_by
+    });
+
+    let server = serve(&network, file.clone());
+    let mut sock = network.bind(CLIENT_TID).unwrap();
+    let mut received = Vec::new();
+    let mut buffer = vec![0u8; 4096];
+    client(None, None)
+        .get_with(
+            &mut sock,
+            "file",
+            WriteSink::new(&mut received),
+            &mut buffer,
+        )
+        .unwrap();
+    assert!(received == file, "received data differs from the file");
+    assert!(matches!(server.join().unwrap(), Served::Finished(_)));
+    let rejected_by = stranger.join().unwrap();
+    assert!(
+        rejected_by.contains(&CLIENT_TID) && rejected_by.contains(&SERVER_TID),
+        "{rejected_by:?}"
+    );
+}
+
+// returns how often the last packet was sent by a transfer that timed out.
+fn timed_out<T: Debug, D: Debug>(result: Result<(), transport::Error<T, D>>) -> u32 {
+    match result {
+        Err(transport::Error::TimedOut { attempts }) => attempts,
+        other => panic!("expected the transfer to time out: {other:?}"),
+    }
+}
+
+#[test]
+fn gives_up() {
+    const PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 4000);
+    let policy = RetransmitPolicy {
+        timeout: Duration::from_millis(10),
+        max_retries: 3,
+        exponential_backoff: true,
+    };
+    let network = Network::new(Conditions::NONE, 0);
+    for is_read in [true, false] {
+        let mut peer = network.bind(PEER).unwrap();
+        let transport = network.bind(SocketAddr::new(SERVER.ip(), 0)).unwrap();
+        let served = thread::spawn(move || {
+            let mut buffer = vec![0u8; 1024];
+            let options = OptionAck::new(None, None, None);
+            let attempts = if is_read {
+                let transfer = Transfer::new(transport, PEER, &b"data"[..], options, policy);
+                timed_out(transfer.finish(&mut buffer))
+            } else {
+                let sink = WriteSink::new(Vec::new());
+                let transfer = IncomingTransfer::new(transport, PEER, sink, options, policy);
+                timed_out(transfer.finish(&mut buffer).map(|_| ()))
+            };
+            assert_eq!(attempts, 4);
+        });
+
+        // the peer never replies, so the first packet is sent again with a growing timeout, and then the transfer is aborted.
+        let mut buffer = [0u8; 1024];
+        let mut arrivals = Vec::new();
+        let error = loop {
+            let (n_bytes, _) = peer
+                .recv_from(&mut buffer, Duration::from_secs(5))
+                .unwrap()
+                .expect("the transfer stopped without telling the peer");
+            match Packet::from_bytes(&buffer[..n_bytes]).unwrap() {
+                Packet::Error(error) => break error.error_code,
+                Packet::Data(_) | Packet::Ack(_) => arrivals.push(Instant::now()),
+                packet => panic!("unexpected packet {packet:?}"),
+            }
+        };
+        served.join().unwrap();
+        assert_eq!(error, ErrorCode::NOT_DEFINED);
+        assert_eq!(arrivals.len(), 4);
+        for (attempt, pair) in arrivals.windows(2).enumerate() {
+            let waited = pair[1] - pair[0];
+            assert!(
+                waited >= policy.timeout_for_attempt(attempt as u32),
+                "attempt {attempt} was sent again after {waited:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn ignored_packets_take_no_time() {
+    const PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 4000);
+    const STRANGER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3)), 4000);
+    let policy = RetransmitPolicy {
+        timeout: Duration::from_millis(300),
+        max_retries: 1,
+        exponential_backoff: false,
+    };
+    let network = Network::new(Conditions::NONE, 0);
+    let mut peer = network.bind(PEER).unwrap();
+    let mut stranger = network.bind(STRANGER).unwrap();
+    let transport = network.bind(SocketAddr::new(SERVER.ip(), 0)).unwrap();
+    let data = file(600);
+    let served = thread::spawn(move || {
+        let mut buffer = vec![0u8; 1024];
+        let transfer = Transfer::new(
+            transport,
+            PEER,
+            &data[..],
+            OptionAck::new(None, None, None),
+            policy,
+        );
+        transfer.finish(&mut buffer).is_ok()
+    });
+
+    let mut buffer = [0u8; 1024];
+    let mut receive = |sock: &mut SimulatedSocket| {
+        let (n_bytes, from) = sock
+            .recv_from(&mut buffer, Duration::from_secs(5))
+            .unwrap()
+            .expect("nothing arrived");
+        (
+            Packet::from_bytes(&buffer[..n_bytes]).unwrap().to_owned(),
+            from,
+        )
+    };
+    let (_, server) = receive(&mut peer);
+    peer.send_to(&[0, 4, 0, 1], server).unwrap();
+    let (block, _) = receive(&mut peer);
+    let sent = Instant::now();
+    assert!(matches!(block.as_ref(), Packet::Data(data) if data.block_nr == 2));
+
+    // a flood of duplicate acks and packets from a stranger neither brings the retransmission forward nor makes the transfer give up.
+    for _ in 0..40 {
+        peer.send_to(&[0, 4, 0, 1], server).unwrap();
+        stranger.send_to(&[0, 4, 0, 1], server).unwrap();
+    }
+    for _ in 0..40 {
+        let (error, _) = receive(&mut stranger);
+        assert!(
+            matches!(error.as_ref(), Packet::Error(error) if error.error_code == ErrorCode::UNKNOWN_TRANSFER_ID)
+        );
+    }
+    let (block, _) = receive(&mut peer);
+    assert!(matches!(block.as_ref(), Packet::Data(data) if data.block_nr == 2));
+    assert!(
+        sent.elapsed() >= policy.timeout,
+        "sent again after {:?}",
+        sent.elapsed()
+    );
+    peer.send_to(&[0, 4, 0, 2], server).unwrap();
+    assert!(served.join().unwrap());
+}
+
+#[test]
+fn unreachable_server() {
+    let network = Network::new(
+        Conditions {
+            drop_rate: 1.0,
+            ..Conditions::NONE
+        },
+        0,
+    );
+    let _server: SimulatedSocket = network.bind(SERVER).unwrap();
+    let mut sock = network.bind(CLIENT).unwrap();
+    let mut buffer = vec![0u8; 1024];
+    let result =
+        client(None, None).get_with(&mut sock, "file", WriteSink::new(Vec::new()), &mut buffer);
+    let retries = retransmit().max_retries;
+    assert!(
+        matches!(result, Err(transport::Error::TimedOut { attempts }) if attempts == retries + 1),
+        "{result:?}"
+    );
+    assert_eq!(network.statistics().sent, retries as u64 + 1);
+}